@@ -0,0 +1,421 @@
+// Sound card CW input
+// Copies on-air CW from a receiver plugged into the sound card. The input stream feeds a
+// StreamDecoder (tone_detector.rs) on the manager thread, so live audio takes the same
+// path as a WAV file: a Goertzel tone detector, then MorseDecoder's hand-keyed timing.
+//
+// The pitch is found automatically or picked on the spectrum the UI shows. The device
+// side only delivers mono samples; StreamDecoder can be fed a synthetic stream instead.
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rodio::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rodio::cpal::{self, SampleFormat};
+
+use crate::morse_decoder::MorseDecoder;
+use crate::morse_table::Alphabet;
+use crate::tone_detector::StreamDecoder;
+
+// Starting speed; the decoder follows the sender from the first elements
+const INITIAL_WPM: u32 = 20;
+
+const MAX_TEXT_CHARS: usize = 2000;
+
+// Device list refresh and retry interval after an error
+const RESCAN_INTERVAL_MS: u64 = 2000;
+const SPECTRUM_INTERVAL_MS: u64 = 200;
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioInputChoice {
+    Off,
+    Default,
+    Device(String),
+}
+
+impl AudioInputChoice {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AudioInputChoice::Off => "Off",
+            AudioInputChoice::Default => "Default input",
+            AudioInputChoice::Device(name) => name,
+        }
+    }
+
+    // Settings file form
+    pub fn to_setting(&self) -> String {
+        match self {
+            AudioInputChoice::Off => "off".to_string(),
+            AudioInputChoice::Default => "default".to_string(),
+            AudioInputChoice::Device(name) => name.clone(),
+        }
+    }
+
+    pub fn from_setting(text: &str) -> AudioInputChoice {
+        match text {
+            "" | "off" => AudioInputChoice::Off,
+            "default" => AudioInputChoice::Default,
+            name => AudioInputChoice::Device(name.to_string()),
+        }
+    }
+
+    fn open(&self) -> Option<Result<Input, String>> {
+        let host = cpal::default_host();
+        let device = match self {
+            AudioInputChoice::Off => return None,
+            AudioInputChoice::Default => host.default_input_device(),
+            AudioInputChoice::Device(name) => host.input_devices().ok()
+                .and_then(|mut devices| devices.find(|d| d.name().ok().as_deref() == Some(name))),
+        };
+        Some(match device {
+            Some(device) => Input::open(&device),
+            None => Err("no such input device".to_string()),
+        })
+    }
+}
+
+// Input devices to offer in the picker
+pub fn get_audio_input_choices(devices: &[String]) -> Vec<AudioInputChoice> {
+    let mut choices = vec![AudioInputChoice::Off, AudioInputChoice::Default];
+    choices.extend(devices.iter().cloned().map(AudioInputChoice::Device));
+    choices
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioInputStatus {
+    Off,
+    Listening(String),
+    Error(String),
+}
+
+impl AudioInputStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            AudioInputStatus::Off => "Off".to_string(),
+            AudioInputStatus::Listening(device) => format!("Listening: {}", device),
+            AudioInputStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, AudioInputStatus::Listening(_))
+    }
+}
+
+enum Message {
+    Samples(Vec<f32>),
+    Error(String),
+}
+
+// An open input stream. cpal streams cannot change threads, so it lives and dies on the
+// manager thread; the callback hands the audio over as mono chunks.
+struct Input {
+    _stream: cpal::Stream,
+    messages: Receiver<Message>,
+    sample_rate: u32,
+    name: String,
+}
+
+impl Input {
+    fn open(device: &cpal::Device) -> Result<Input, String> {
+        let name = device.name().map_err(|e| e.to_string())?;
+        let config = device.default_input_config().map_err(|e| e.to_string())?;
+        let channels = config.channels().max(1) as usize;
+        let sample_rate = config.sample_rate().0;
+        let (sender, messages) = mpsc::channel();
+
+        let errors = sender.clone();
+        let on_error = move |e: cpal::StreamError| {
+            let _ = errors.send(Message::Error(e.to_string()));
+        };
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => device.build_input_stream(
+                &config.into(),
+                move |data: &[f32], _: &_| {
+                    let _ = sender.send(Message::Samples(mix(data, channels, |s| s)));
+                },
+                on_error,
+                None,
+            ),
+            SampleFormat::I16 => device.build_input_stream(
+                &config.into(),
+                move |data: &[i16], _: &_| {
+                    let _ = sender.send(Message::Samples(mix(data, channels, |s| s as f32 / 32768.0)));
+                },
+                on_error,
+                None,
+            ),
+            SampleFormat::U16 => device.build_input_stream(
+                &config.into(),
+                move |data: &[u16], _: &_| {
+                    let _ = sender.send(Message::Samples(mix(data, channels, |s| (s as f32 - 32768.0) / 32768.0)));
+                },
+                on_error,
+                None,
+            ),
+            format => return Err(format!("unsupported sample format {}", format)),
+        }
+        .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(Input { _stream: stream, messages, sample_rate, name })
+    }
+}
+
+// Channels mixed to mono
+fn mix<T: Copy>(data: &[T], channels: usize, to_f32: impl Fn(T) -> f32) -> Vec<f32> {
+    data.chunks(channels)
+        .map(|frame| frame.iter().map(|&s| to_f32(s)).sum::<f32>() / channels as f32)
+        .collect()
+}
+
+struct Shared {
+    devices: Vec<String>,
+    choice: AudioInputChoice,
+    status: AudioInputStatus,
+    reopen: bool,
+    pitch: Option<f32>,
+    retune: Option<Option<f32>>,  // Pitch asked for from the UI, None = search again
+    spectrum: Vec<(f32, f32)>,
+    text: String,
+    alphabet: Alphabet,
+    prefer_prosigns: bool,
+}
+
+#[derive(Clone)]
+pub struct AudioInputManager {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl AudioInputManager {
+    pub fn start(choice: AudioInputChoice) -> Self {
+        let manager = AudioInputManager {
+            shared: Arc::new(Mutex::new(Shared {
+                devices: Vec::new(),
+                choice,
+                status: AudioInputStatus::Off,
+                reopen: false,
+                pitch: None,
+                retune: None,
+                spectrum: Vec::new(),
+                text: String::new(),
+                alphabet: Alphabet::Latin,
+                prefer_prosigns: false,
+            })),
+        };
+
+        let runner = manager.clone();
+        thread::spawn(move || runner.run());
+        manager
+    }
+
+    pub fn devices(&self) -> Vec<String> {
+        self.shared.lock().unwrap().devices.clone()
+    }
+
+    pub fn choice(&self) -> AudioInputChoice {
+        self.shared.lock().unwrap().choice.clone()
+    }
+
+    pub fn set_choice(&self, choice: AudioInputChoice) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.choice != choice {
+            shared.choice = choice;
+            shared.reopen = true;
+        }
+    }
+
+    pub fn status(&self) -> AudioInputStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    // The pitch being decoded; None while searching
+    pub fn pitch(&self) -> Option<f32> {
+        self.shared.lock().unwrap().pitch
+    }
+
+    // Decode the tone at this pitch, or None to find the strongest tone again
+    pub fn set_pitch(&self, pitch: Option<f32>) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.pitch = pitch;
+        shared.retune = Some(pitch);
+    }
+
+    // Level per frequency over the last half second, for picking the pitch
+    pub fn spectrum(&self) -> Vec<(f32, f32)> {
+        self.shared.lock().unwrap().spectrum.clone()
+    }
+
+    pub fn text(&self) -> String {
+        self.shared.lock().unwrap().text.clone()
+    }
+
+    pub fn clear_text(&self) {
+        self.shared.lock().unwrap().text.clear();
+    }
+
+    pub fn set_decoding(&self, alphabet: Alphabet, prefer_prosigns: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.alphabet = alphabet;
+        shared.prefer_prosigns = prefer_prosigns;
+    }
+
+    fn set_status(&self, status: AudioInputStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self) {
+        let mut input: Option<(Input, StreamDecoder)> = None;
+        let mut decoder = MorseDecoder::new(INITIAL_WPM);
+        let (mut alphabet, mut prefer_prosigns) = (Alphabet::Latin, false);
+        let mut next_scan = Instant::now();
+        let mut next_spectrum = Instant::now();
+
+        loop {
+            if Instant::now() >= next_scan {
+                next_scan = Instant::now() + Duration::from_millis(RESCAN_INTERVAL_MS);
+                self.refresh_devices();
+
+                // (Re)open the chosen device; after an error this retries once per rescan
+                if input.is_none() {
+                    let choice = self.choice();
+                    match choice.open() {
+                        None => self.set_status(AudioInputStatus::Off),
+                        Some(Ok(opened)) => {
+                            println!("Audio input: {} at {} Hz", opened.name, opened.sample_rate);
+                            self.set_status(AudioInputStatus::Listening(opened.name.clone()));
+                            // A new device starts with a search; a pitch can be picked once
+                            // there is audio to start the levels from
+                            let stream = StreamDecoder::new(opened.sample_rate, None);
+                            decoder = MorseDecoder::new(INITIAL_WPM);
+                            decoder.set_alphabet(alphabet);
+                            decoder.set_prefer_prosigns(prefer_prosigns);
+                            input = Some((opened, stream));
+                        }
+                        Some(Err(e)) => {
+                            self.set_status(AudioInputStatus::Error(format!("{}: {}", choice.as_str(), e)));
+                        }
+                    }
+                }
+            }
+
+            if std::mem::take(&mut self.shared.lock().unwrap().reopen) {
+                input = None;
+                self.shared.lock().unwrap().spectrum.clear();
+                next_scan = Instant::now();
+                continue;
+            }
+
+            let Some((opened, stream)) = input.as_mut() else {
+                thread::sleep(POLL);
+                continue;
+            };
+
+            let (retune, wanted) = {
+                let mut shared = self.shared.lock().unwrap();
+                (shared.retune.take(), (shared.alphabet, shared.prefer_prosigns))
+            };
+            if wanted != (alphabet, prefer_prosigns) {
+                (alphabet, prefer_prosigns) = wanted;
+                decoder.set_alphabet(alphabet);
+                decoder.set_prefer_prosigns(prefer_prosigns);
+            }
+
+            let mut text = String::new();
+            if let Some(pitch) = retune {
+                text += &stream.set_pitch(&mut decoder, pitch);
+            }
+            match opened.messages.recv_timeout(POLL) {
+                Ok(Message::Samples(samples)) => text += &stream.feed(&mut decoder, &samples),
+                Ok(Message::Error(e)) => {
+                    eprintln!("Audio input error: {}", e);
+                    self.set_status(AudioInputStatus::Error(e));
+                    input = None;
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.set_status(AudioInputStatus::Error("input stream closed".to_string()));
+                    input = None;
+                    continue;
+                }
+            }
+
+            let spectrum = (Instant::now() >= next_spectrum).then(|| {
+                next_spectrum = Instant::now() + Duration::from_millis(SPECTRUM_INTERVAL_MS);
+                stream.spectrum()
+            });
+            let mut shared = self.shared.lock().unwrap();
+            shared.pitch = stream.pitch();
+            if let Some(spectrum) = spectrum {
+                shared.spectrum = spectrum;
+            }
+            if !text.is_empty() {
+                let text = if shared.text.is_empty() { text.trim_start() } else { &text };
+                shared.text.push_str(text);
+                let excess = shared.text.chars().count().saturating_sub(MAX_TEXT_CHARS);
+                if excess > 0 {
+                    shared.text = shared.text.chars().skip(excess).collect();
+                }
+            }
+        }
+    }
+
+    fn refresh_devices(&self) {
+        let devices: Vec<String> = cpal::default_host().input_devices()
+            .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+            .unwrap_or_default();
+        self.shared.lock().unwrap().devices = devices;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse_player::MorsePlayer;
+    use crate::tone_queue::{ToneQueue, SAMPLE_RATE};
+
+    // The text as the player keys it, then two seconds key-up so the last word ends.
+    // A little noise, as on a receiver, so the levels have a floor.
+    fn render(frequency: f32, text: &str) -> Vec<f32> {
+        let queue = ToneQueue::new(frequency, 5);
+        MorsePlayer::new_with_alphabet(frequency, 20, 20, Alphabet::Latin).queue_morse(&queue, text);
+        queue.push(false, 2000.0);
+        queue.close();
+        let mut seed = 0x2545_f491u32;
+        queue.source()
+            .map(|sample| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                sample + (seed as f32 / u32::MAX as f32 - 0.5) * 0.1
+            })
+            .collect()
+    }
+
+    // Fed in 10 ms chunks, as a sound card would deliver it
+    fn feed(stream: &mut StreamDecoder, decoder: &mut MorseDecoder, samples: &[f32]) -> String {
+        samples.chunks(SAMPLE_RATE as usize / 100)
+            .map(|chunk| stream.feed(decoder, chunk))
+            .collect()
+    }
+
+    #[test]
+    fn stream_decodes_and_retunes() {
+        let mut decoder = MorseDecoder::new(INITIAL_WPM);
+        let mut stream = StreamDecoder::new(SAMPLE_RATE, None);
+        assert_eq!(stream.pitch(), None);
+
+        let text = feed(&mut stream, &mut decoder, &render(700.0, "CQ DE DL1ABC K"));
+        assert_eq!(text, "CQ DE DL1ABC K ");
+        let pitch = stream.pitch().expect("no pitch found");
+        assert!((pitch - 700.0).abs() < 10.0, "pitch {}", pitch);
+
+        // Picked on the spectrum: the signal at the new pitch is decoded
+        assert_eq!(stream.set_pitch(&mut decoder, Some(1000.0)), "");
+        assert_eq!(stream.pitch(), Some(1000.0));
+        assert_eq!(feed(&mut stream, &mut decoder, &render(1000.0, "TEST")), "TEST ");
+        assert_eq!(stream.pitch(), Some(1000.0));
+    }
+}
@@ -0,0 +1,275 @@
+// Simulated band conditions for listening practice
+// Wraps a playback source and mixes in what a real receiver adds to a clean signal:
+// background noise at a chosen SNR, slow QSB fading, a second CW signal (QRM) and
+// static crashes (QRN). Every impairment is off at strength 0.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rodio::Source;
+use std::time::Duration;
+
+use crate::tone_queue::{ToneQueueSource, SAMPLE_RATE, AMPLITUDE};
+
+// Scales Paul Kellet's pink filter to the same RMS as the white noise it is fed
+const PINK_GAIN: f32 = 0.33;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseColor {
+    White,
+    Pink,  // Softer, more like a receiver's hiss
+}
+
+impl NoiseColor {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NoiseColor::White => "White",
+            NoiseColor::Pink => "Pink",
+        }
+    }
+}
+
+pub fn get_noise_colors() -> Vec<NoiseColor> {
+    vec![NoiseColor::White, NoiseColor::Pink]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandConditions {
+    pub noise_enabled: bool,
+    pub noise_color: NoiseColor,
+    pub snr_db: f32,        // Signal to noise ratio over the whole audio band
+    pub qsb_depth: f32,     // 0.0 - 1.0, how deep the signal fades
+    pub qrm_strength: f32,  // 0.0 - 1.0, loudness of the interfering signal
+    pub qrn_strength: f32,  // 0.0 - 1.0, rate and loudness of static crashes
+}
+
+impl Default for BandConditions {
+    fn default() -> Self {
+        BandConditions {
+            noise_enabled: false,
+            noise_color: NoiseColor::Pink,
+            snr_db: 10.0,
+            qsb_depth: 0.0,
+            qrm_strength: 0.0,
+            qrn_strength: 0.0,
+        }
+    }
+}
+
+impl BandConditions {
+    pub fn is_clean(&self) -> bool {
+        !self.noise_enabled && self.qsb_depth <= 0.0 && self.qrm_strength <= 0.0 && self.qrn_strength <= 0.0
+    }
+}
+
+// Playback source with the band conditions mixed in; ends when the signal ends
+pub struct BandSource {
+    signal: ToneQueueSource,
+    qrm: Option<ToneQueueSource>,
+    conditions: BandConditions,
+    rng: StdRng,
+    noise_rms: f32,
+    pink: [f32; 7],
+    // QSB: one fade cycle at a time, each with a new random period
+    qsb_phase: f32,
+    qsb_step: f32,
+    // QRN: the current crash decays away
+    crash_level: f32,
+    crash_decay: f32,
+}
+
+impl BandSource {
+    // `qrm` is the interfering signal, already queued, if QRM is on
+    pub fn new(signal: ToneQueueSource, qrm: Option<ToneQueueSource>, conditions: BandConditions) -> Self {
+        // Signal power of a full-level sine is A²/2
+        let signal_power = AMPLITUDE * AMPLITUDE / 2.0;
+        let noise_rms = (signal_power / 10f32.powf(conditions.snr_db / 10.0)).sqrt();
+
+        let mut source = BandSource {
+            signal,
+            qrm,
+            conditions,
+            rng: StdRng::from_entropy(),
+            noise_rms,
+            pink: [0.0; 7],
+            qsb_phase: 0.0,
+            qsb_step: 0.0,
+            crash_level: 0.0,
+            crash_decay: 0.0,
+        };
+        // Start somewhere inside a fade cycle
+        source.qsb_phase = source.rng.gen();
+        source.new_qsb_cycle();
+        source
+    }
+
+    fn new_qsb_cycle(&mut self) {
+        let period_s = self.rng.gen_range(4.0..12.0);
+        self.qsb_step = 1.0 / (period_s * SAMPLE_RATE as f32);
+    }
+
+    // Near-Gaussian white noise with unit RMS: the sum of three uniforms in -1..1,
+    // each with a variance of 1/3
+    fn white(&mut self) -> f32 {
+        (0..3).map(|_| self.rng.gen_range(-1.0..1.0)).sum()
+    }
+
+    fn pink(&mut self, white: f32) -> f32 {
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * PINK_GAIN
+    }
+
+    fn qsb_gain(&mut self) -> f32 {
+        self.qsb_phase += self.qsb_step;
+        if self.qsb_phase >= 1.0 {
+            self.qsb_phase -= 1.0;
+            self.new_qsb_cycle();
+        }
+        let fade = 0.5 - 0.5 * (self.qsb_phase * 2.0 * std::f32::consts::PI).cos();
+        1.0 - self.conditions.qsb_depth * fade
+    }
+
+    fn qrn(&mut self) -> f32 {
+        let strength = self.conditions.qrn_strength;
+        // Up to about two crashes a second at full strength
+        if self.rng.gen::<f32>() < strength * 2.0 / SAMPLE_RATE as f32 {
+            self.crash_level = strength * self.rng.gen_range(0.3..1.0);
+            let decay_ms = self.rng.gen_range(20.0..120.0);
+            self.crash_decay = (-1.0 / (decay_ms / 1000.0 * SAMPLE_RATE as f32)).exp();
+        }
+        if self.crash_level < 0.001 {
+            return 0.0;
+        }
+        self.crash_level *= self.crash_decay;
+        self.white() * self.crash_level
+    }
+}
+
+impl Iterator for BandSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut sample = self.signal.next()?;
+
+        if self.conditions.qsb_depth > 0.0 {
+            sample *= self.qsb_gain();
+        }
+        if let Some(qrm) = self.qrm.as_mut() {
+            sample += qrm.next().unwrap_or(0.0) * self.conditions.qrm_strength;
+        }
+        if self.conditions.noise_enabled {
+            let white = self.white();
+            let noise = match self.conditions.noise_color {
+                NoiseColor::White => white,
+                NoiseColor::Pink => self.pink(white),
+            };
+            sample += noise * self.noise_rms;
+        }
+        if self.conditions.qrn_strength > 0.0 {
+            sample += self.qrn();
+        }
+
+        Some(sample.clamp(-1.0, 1.0))
+    }
+}
+
+impl Source for BandSource {
+    fn current_frame_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { 1 }
+    fn sample_rate(&self) -> u32 { SAMPLE_RATE }
+    fn total_duration(&self) -> Option<Duration> { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tone_queue::ToneQueue;
+
+    // Band source over `seconds` of steady key down or key up
+    fn band(conditions: BandConditions, key_down: bool, seconds: f64) -> BandSource {
+        let queue = ToneQueue::new(600.0, 5);
+        queue.push(key_down, seconds * 1000.0);
+        queue.close();
+        let mut source = BandSource::new(queue.source(), None, conditions);
+        source.rng = StdRng::seed_from_u64(7);
+        source
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn noise(color: NoiseColor, snr_db: f32) -> BandConditions {
+        BandConditions { noise_enabled: true, noise_color: color, snr_db, ..BandConditions::default() }
+    }
+
+    #[test]
+    fn white_noise_has_unit_rms_and_gaussian_tails() {
+        let mut source = band(BandConditions::default(), false, 0.0);
+        let samples: Vec<f32> = (0..200_000).map(|_| source.white()).collect();
+        assert!((rms(&samples) - 1.0).abs() < 0.01, "rms {}", rms(&samples));
+        // A Gaussian is beyond 2 sigma 4.6 % of the time, a scaled uniform never
+        let beyond = samples.iter().filter(|s| s.abs() > 2.0).count() as f32 / samples.len() as f32;
+        assert!((0.03..0.06).contains(&beyond), "{beyond} beyond 2 sigma");
+    }
+
+    #[test]
+    fn noise_level_follows_snr() {
+        let signal_power = AMPLITUDE * AMPLITUDE / 2.0;
+        for color in get_noise_colors() {
+            for snr_db in [0.0, 10.0, 20.0] {
+                let samples: Vec<f32> = band(noise(color, snr_db), false, 2.0).collect();
+                let expected = (signal_power / 10f32.powf(snr_db / 10.0)).sqrt();
+                let measured = rms(&samples);
+                assert!((measured / expected - 1.0).abs() < 0.15,
+                        "{} at {snr_db} dB: rms {measured}, expected {expected}", color.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn no_noise_when_disabled() {
+        assert!(band(BandConditions::default(), false, 0.5).all(|s| s == 0.0));
+    }
+
+    // Loudest and quietest tone peak over 20 ms windows
+    fn fade_range(depth: f32) -> (f32, f32) {
+        let conditions = BandConditions { qsb_depth: depth, ..BandConditions::default() };
+        // A fade cycle lasts at most 12 s, so 25 s holds at least one whole one
+        // Skip the rise and the release of the tone
+        let samples: Vec<f32> = band(conditions, true, 25.0)
+            .skip(SAMPLE_RATE as usize / 10)
+            .take(24 * SAMPLE_RATE as usize)
+            .collect();
+        let peaks: Vec<f32> = samples
+            .chunks(SAMPLE_RATE as usize / 50)
+            .map(|window| window.iter().fold(0.0f32, |peak, s| peak.max(s.abs())))
+            .collect();
+        let loudest = peaks.iter().cloned().fold(0.0, f32::max);
+        let quietest = peaks.iter().cloned().fold(f32::MAX, f32::min);
+        (loudest, quietest)
+    }
+
+    #[test]
+    fn qsb_fades_to_the_configured_depth() {
+        for depth in [0.3, 0.6, 1.0] {
+            let (loudest, quietest) = fade_range(depth);
+            assert!((loudest / AMPLITUDE - 1.0).abs() < 0.02, "depth {depth}: loudest {loudest}");
+            let floor = AMPLITUDE * (1.0 - depth);
+            assert!((quietest - floor).abs() < 0.02 * AMPLITUDE, "depth {depth}: quietest {quietest}, expected {floor}");
+        }
+    }
+
+    #[test]
+    fn no_fading_without_qsb() {
+        let (loudest, quietest) = fade_range(0.0);
+        assert!((loudest / AMPLITUDE - 1.0).abs() < 0.01);
+        assert!((quietest / AMPLITUDE - 1.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,442 @@
+// Command-line interface
+// The encoder, decoder, listening drill and practice net relay without a window, for
+// scripts, servers and SSH sessions. main() opens the GUI unless the first argument is
+// one of the commands, so arguments added by launchers (-psn_..., a file) are ignored.
+//
+// Options are parsed by hand: each command says which options take a value and which
+// stand alone, and anything else is a usage error (exit code 2; failures exit with 1).
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use midir::{MidiInput, MidiOutput};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{OutputStream, Sink};
+
+use crate::cw_academy_training::{get_cumulative_session, PracticeType, SessionNumber};
+use crate::morse_player::MorsePlayer;
+use crate::morse_table::Alphabet;
+use crate::practice_net::{self, NetRelay, RelayStatus};
+use crate::tone_detector::decode_wav;
+
+const CLIENT_NAME: &str = "paddle-decoder-cli";
+
+const DEFAULT_WPM: u32 = 20;
+const DEFAULT_FREQUENCY: u32 = 600;
+const DEFAULT_OUT: &str = "morse.wav";
+const DEFAULT_DRILL_COUNT: u32 = 10;
+
+const USAGE: &str = "\
+Usage: paddle_decoder [COMMAND] [OPTIONS]
+
+Without a command the window opens.
+
+Commands:
+  encode TEXT [--out FILE]   Render TEXT as CW to a WAV file (default morse.wav)
+  decode FILE                Decode CW from a WAV file and print the text
+  drill [--session N] [--type TYPE] [--count N]
+                             Listening drill: type what you hear (default
+                             session 1, words, 10 items)
+  list-ports                 List serial ports, MIDI ports and audio inputs
+  serve [--port N]           Host a practice net relay (default port 7373)
+  help                       Show this help
+
+Options:
+  --wpm N            Character speed, 5-60 (default 20)
+  --farnsworth N     Effective speed with Farnsworth spacing (encode, drill;
+                     default: the character speed)
+  --frequency HZ     Tone pitch, 300-1000 (encode, drill; default 600)
+  --alphabet NAME    latin, extended, cyrillic, greek or wabun (default latin)
+  --prosigns         Print prosigns such as <BT> instead of = (decode)
+
+Drill types: characters, characters-numbers, words, abbreviations, numbers,
+callsigns, phrases
+
+Examples:
+  paddle_decoder encode \"CQ CQ DE DL1ABC\" --wpm 20 --farnsworth 15 --out cq.wav
+  paddle_decoder decode cq.wav
+  paddle_decoder drill --session 5 --type words";
+
+const COMMANDS: [&str; 8] = ["encode", "decode", "drill", "list-ports", "serve", "help", "--help", "-h"];
+
+// Whether the arguments start with a command rather than whatever a launcher passed
+pub fn is_command(arg: &str) -> bool {
+    COMMANDS.contains(&arg)
+}
+
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+// Run a command; the result is the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => ("help", args),
+    };
+    let result = match command {
+        "encode" => encode(rest),
+        "decode" => decode(rest),
+        "drill" => drill(rest),
+        "list-ports" => list_ports(rest),
+        "serve" => serve(rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(CliError::Usage(format!("unknown command '{}'", other))),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(CliError::Usage(e)) => {
+            eprintln!("Error: {}\nRun 'paddle_decoder help' for the commands and options.", e);
+            2
+        }
+        Err(CliError::Failed(e)) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+// Positional arguments and options of one command
+struct Args {
+    positional: Vec<String>,
+    values: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    // `valued` options take a value (`--wpm 20` or `--wpm=20`), `flags` stand alone
+    fn parse(args: &[String], valued: &[&str], flags: &[&str]) -> Result<Args, CliError> {
+        let mut parsed = Args { positional: Vec::new(), values: HashMap::new(), flags: Vec::new() };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--") else {
+                parsed.positional.push(arg.clone());
+                continue;
+            };
+            let (name, inline) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (option, None),
+            };
+            if valued.contains(&name) {
+                let value = inline.or_else(|| args.next().cloned())
+                    .ok_or_else(|| CliError::Usage(format!("--{} needs a value", name)))?;
+                parsed.values.insert(name.to_string(), value);
+            } else if flags.contains(&name) && inline.is_none() {
+                parsed.flags.push(name.to_string());
+            } else {
+                return Err(CliError::Usage(format!("unknown option '{}'", arg)));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    // Number option within the range, or the default when not given
+    fn number<T>(&self, name: &str, default: T, range: std::ops::RangeInclusive<T>) -> Result<T, CliError>
+    where
+        T: FromStr + PartialOrd + Display,
+    {
+        let Some(text) = self.value(name) else { return Ok(default) };
+        match text.parse::<T>() {
+            Ok(number) if range.contains(&number) => Ok(number),
+            _ => Err(CliError::Usage(format!("--{} must be a number from {} to {}",
+                                             name, range.start(), range.end()))),
+        }
+    }
+
+    fn alphabet(&self) -> Result<Alphabet, CliError> {
+        match self.value("alphabet").unwrap_or("latin") {
+            "latin" => Ok(Alphabet::Latin),
+            "extended" => Ok(Alphabet::ExtendedLatin),
+            "cyrillic" => Ok(Alphabet::Cyrillic),
+            "greek" => Ok(Alphabet::Greek),
+            "wabun" => Ok(Alphabet::Wabun),
+            other => Err(CliError::Usage(format!("unknown alphabet '{}'", other))),
+        }
+    }
+
+    // Player with the speed, Farnsworth spacing, pitch and alphabet options
+    fn player(&self) -> Result<MorsePlayer, CliError> {
+        let wpm = self.number("wpm", DEFAULT_WPM, 5..=60)?;
+        let farnsworth = self.number("farnsworth", wpm, 5..=wpm)?;
+        let frequency = self.number("frequency", DEFAULT_FREQUENCY, 300..=1000)?;
+        Ok(MorsePlayer::new_with_alphabet(frequency as f32, wpm, farnsworth, self.alphabet()?))
+    }
+
+    fn no_positional(&self) -> Result<(), CliError> {
+        match self.positional.first() {
+            Some(extra) => Err(CliError::Usage(format!("unexpected argument '{}'", extra))),
+            None => Ok(()),
+        }
+    }
+}
+
+const PLAYER_OPTIONS: [&str; 4] = ["wpm", "farnsworth", "frequency", "alphabet"];
+
+fn encode(args: &[String]) -> Result<(), CliError> {
+    let args = Args::parse(args, &[&PLAYER_OPTIONS[..], &["out"]].concat(), &[])?;
+    let text = args.positional.join(" ");
+    if text.trim().is_empty() {
+        return Err(CliError::Usage("encode needs the text to send".to_string()));
+    }
+    let player = args.player()?;
+    let path = Path::new(args.value("out").unwrap_or(DEFAULT_OUT));
+
+    player.export_wav(path, text.trim())
+        .map_err(|e| CliError::Failed(format!("failed to write {}: {}", path.display(), e)))?;
+    println!("Saved {}", path.display());
+    Ok(())
+}
+
+fn decode(args: &[String]) -> Result<(), CliError> {
+    let args = Args::parse(args, &["wpm", "alphabet"], &["prosigns"])?;
+    let path = match args.positional.as_slice() {
+        [path] => Path::new(path),
+        [] => return Err(CliError::Usage("decode needs a WAV file".to_string())),
+        [_, extra, ..] => return Err(CliError::Usage(format!("unexpected argument '{}'", extra))),
+    };
+    let wpm = args.number("wpm", DEFAULT_WPM, 5..=60)?;
+
+    match decode_wav(path, wpm, args.alphabet()?, args.flag("prosigns")) {
+        Ok(Some(decoded)) => {
+            // Only the text goes to stdout, so it can be piped
+            eprintln!("Tone at {:.0} Hz{}", decoded.pitch,
+                      decoded.wpm.map(|wpm| format!(", about {} WPM", wpm)).unwrap_or_default());
+            println!("{}", decoded.text);
+            Ok(())
+        }
+        Ok(None) => Err(CliError::Failed(format!("no CW tone found in {}", path.display()))),
+        Err(e) => Err(CliError::Failed(format!("failed to read {}: {}", path.display(), e))),
+    }
+}
+
+fn practice_type(name: &str) -> Option<PracticeType> {
+    match name {
+        "characters" => Some(PracticeType::Characters),
+        "characters-numbers" => Some(PracticeType::CharactersAndNumbers),
+        "words" => Some(PracticeType::Words),
+        "abbreviations" => Some(PracticeType::Abbreviations),
+        "numbers" => Some(PracticeType::Numbers),
+        "callsigns" => Some(PracticeType::Callsigns),
+        "phrases" => Some(PracticeType::Phrases),
+        _ => None,
+    }
+}
+
+// Listening drill: each item is played, the answer typed in and checked as in the
+// Training window
+fn drill(args: &[String]) -> Result<(), CliError> {
+    let args = Args::parse(args, &[&PLAYER_OPTIONS[..], &["session", "type", "count"]].concat(), &[])?;
+    args.no_positional()?;
+    let session_number = args.number("session", 1, 1..=10)?;
+    let type_name = args.value("type").unwrap_or("words");
+    let practice = practice_type(type_name)
+        .ok_or_else(|| CliError::Usage(format!("unknown drill type '{}'", type_name)))?;
+    let count = args.number("count", DEFAULT_DRILL_COUNT, 1..=1000)?;
+    let player = args.player()?;
+
+    let session = get_cumulative_session(SessionNumber::all_values()[session_number as usize - 1]);
+    if session.get_practice_items(practice).is_empty() {
+        return Err(CliError::Failed(format!("session {} has no {}", session_number, practice.as_str())));
+    }
+    let (_stream, stream_handle) = OutputStream::try_default()
+        .map_err(|e| CliError::Failed(format!("no audio output: {}", e)))?;
+    let sink = Sink::try_new(&stream_handle)
+        .map_err(|e| CliError::Failed(format!("no audio output: {}", e)))?;
+
+    println!("Session {} - {}, {} items", session_number, practice.as_str(), count);
+    println!("Type what you hear and press Enter. An empty line plays it again, q quits.\n");
+
+    let mut lines = io::stdin().lock().lines();
+    let (mut correct, mut asked) = (0, 0);
+    let mut missed: Vec<String> = Vec::new();
+    'items: for number in 1..=count {
+        let item = session.get_random_item(practice).unwrap_or_default();
+        let answer = loop {
+            player.play_morse(&sink, item);
+            print!("{}/{} > ", number, count);
+            io::stdout().flush().ok();
+            match lines.next() {
+                Some(Ok(line)) if line.trim().is_empty() => continue,
+                Some(Ok(line)) if line.trim().eq_ignore_ascii_case("q") => break 'items,
+                Some(Ok(line)) => break line,
+                // End of input or a broken terminal
+                _ => break 'items,
+            }
+        };
+
+        asked += 1;
+        if answer.trim().to_uppercase() == item.trim().to_uppercase() {
+            correct += 1;
+            println!("  ✓");
+        } else {
+            println!("  ✗ {}", item);
+            missed.push(item.to_string());
+        }
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    if asked > 0 {
+        println!("\nScore: {}/{} ({:.0}%)", correct, asked, correct as f32 / asked as f32 * 100.0);
+        if !missed.is_empty() {
+            println!("Missed: {}", missed.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn list_ports(args: &[String]) -> Result<(), CliError> {
+    Args::parse(args, &[], &[])?.no_positional()?;
+
+    let serial: Vec<String> = serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|port| port.port_name).collect())
+        .unwrap_or_default();
+    let midi_in: Vec<String> = MidiInput::new(CLIENT_NAME)
+        .map(|midi| midi.ports().iter().filter_map(|port| midi.port_name(port).ok()).collect())
+        .unwrap_or_default();
+    let midi_out: Vec<String> = MidiOutput::new(CLIENT_NAME)
+        .map(|midi| midi.ports().iter().filter_map(|port| midi.port_name(port).ok()).collect())
+        .unwrap_or_default();
+    let audio_in: Vec<String> = rodio::cpal::default_host().input_devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default();
+
+    for (title, names) in [
+        ("Serial ports (paddles, WinKeyer, keying output)", serial),
+        ("MIDI inputs (paddles)", midi_in),
+        ("MIDI outputs (keying output)", midi_out),
+        ("Audio inputs (CW from a receiver)", audio_in),
+    ] {
+        println!("{}:", title);
+        if names.is_empty() {
+            println!("  (none)");
+        }
+        for name in names {
+            println!("  {}", name);
+        }
+    }
+    Ok(())
+}
+
+// Practice net relay until interrupted; joins are printed by the relay itself
+fn serve(args: &[String]) -> Result<(), CliError> {
+    let args = Args::parse(args, &["port"], &[])?;
+    args.no_positional()?;
+    let port = args.number("port", practice_net::DEFAULT_PORT, 1..=65535)?;
+
+    let relay = NetRelay::start(true, port);
+    println!("Press Ctrl+C to stop.");
+    let mut last = None;
+    loop {
+        let status = relay.status();
+        // Off only until the relay thread has bound the port
+        if status != RelayStatus::Off && last.as_ref() != Some(&status) {
+            println!("{}", status.describe());
+            last = Some(status);
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn parse(args: &[&str]) -> Result<Args, CliError> {
+        Args::parse(&strings(args), &["wpm", "out"], &["prosigns"])
+    }
+
+    fn usage_error(result: Result<Args, CliError>) -> String {
+        match result {
+            Err(CliError::Usage(e)) => e,
+            Err(CliError::Failed(e)) => panic!("failure instead of a usage error: {}", e),
+            Ok(_) => panic!("parsed"),
+        }
+    }
+
+    #[test]
+    fn commands_and_launcher_arguments() {
+        for command in ["encode", "decode", "drill", "list-ports", "serve", "help", "--help", "-h"] {
+            assert!(is_command(command), "{}", command);
+        }
+        // Finder's process serial number, a file from a desktop entry
+        assert!(!is_command("-psn_0_12345"));
+        assert!(!is_command("/home/op/cq.wav"));
+        assert!(!is_command("Encode"));
+    }
+
+    #[test]
+    fn options_with_values_and_flags() {
+        let args = parse(&["CQ", "--wpm", "25", "DE", "--out=cq.wav", "--prosigns"]).ok().unwrap();
+        assert_eq!(args.positional, ["CQ", "DE"]);
+        assert_eq!(args.value("wpm"), Some("25"));
+        assert_eq!(args.value("out"), Some("cq.wav"));
+        assert!(args.flag("prosigns"));
+
+        let args = parse(&["--wpm=18", "--wpm", "30"]).ok().unwrap();
+        assert_eq!(args.value("wpm"), Some("30"));
+        assert!(!args.flag("prosigns"));
+        assert!(args.positional.is_empty());
+    }
+
+    #[test]
+    fn missing_values() {
+        assert_eq!(usage_error(parse(&["--wpm"])), "--wpm needs a value");
+        assert_eq!(usage_error(parse(&["text", "--out"])), "--out needs a value");
+        // A value may look like an option
+        assert_eq!(parse(&["--out", "--prosigns"]).ok().unwrap().value("out"), Some("--prosigns"));
+    }
+
+    #[test]
+    fn unknown_options() {
+        assert_eq!(usage_error(parse(&["--speed", "20"])), "unknown option '--speed'");
+        // Flags take no value
+        assert_eq!(usage_error(parse(&["--prosigns=yes"])), "unknown option '--prosigns=yes'");
+        // Single dashes are positional
+        assert_eq!(parse(&["-5"]).ok().unwrap().positional, ["-5"]);
+    }
+
+    #[test]
+    fn numbers_in_range() {
+        let args = parse(&["--wpm", "25"]).ok().unwrap();
+        assert_eq!(args.number("wpm", DEFAULT_WPM, 5..=60).ok(), Some(25));
+        assert_eq!(parse(&[]).ok().unwrap().number("wpm", DEFAULT_WPM, 5..=60).ok(), Some(DEFAULT_WPM));
+
+        let args = parse(&["--wpm", "61"]).ok().unwrap();
+        match args.number("wpm", DEFAULT_WPM, 5..=60) {
+            Err(CliError::Usage(e)) => assert_eq!(e, "--wpm must be a number from 5 to 60"),
+            _ => panic!("61 WPM accepted"),
+        }
+    }
+
+    #[test]
+    fn alphabets_and_extra_arguments() {
+        let args = Args::parse(&strings(&["--alphabet", "greek"]), &["alphabet"], &[]).ok().unwrap();
+        assert_eq!(args.alphabet().ok(), Some(Alphabet::Greek));
+        let args = Args::parse(&strings(&["--alphabet", "klingon"]), &["alphabet"], &[]).ok().unwrap();
+        assert!(matches!(args.alphabet(), Err(CliError::Usage(_))));
+
+        assert!(parse(&[]).ok().unwrap().no_positional().is_ok());
+        assert!(matches!(parse(&["extra"]).ok().unwrap().no_positional(), Err(CliError::Usage(_))));
+    }
+}
@@ -361,18 +361,7 @@ pub fn get_cumulative_session(session: SessionNumber) -> TrainingSession {
 }
 
 pub fn get_all_sessions() -> Vec<SessionNumber> {
-    vec![
-        SessionNumber::Session1,
-        SessionNumber::Session2,
-        SessionNumber::Session3,
-        SessionNumber::Session4,
-        SessionNumber::Session5,
-        SessionNumber::Session6,
-        SessionNumber::Session7,
-        SessionNumber::Session8,
-        SessionNumber::Session9,
-        SessionNumber::Session10,
-    ]
+    SessionNumber::all_values()
 }
 
 pub fn get_practice_types() -> Vec<PracticeType> {
@@ -0,0 +1,124 @@
+// Raised-cosine keying envelope
+// Shapes the start and end of every tone so keying does not click. The gain follows
+// half a cosine period from 0 to 1 over the edge time when the key goes down and back
+// when it goes up; a key change in the middle of an edge turns around where it is.
+
+pub const MIN_EDGE_MS: u32 = 1;
+pub const MAX_EDGE_MS: u32 = 10;
+pub const DEFAULT_EDGE_MS: u32 = 5;
+
+pub struct KeyingEnvelope {
+    edge_samples: u32,
+    position: u32,  // 0 = silent, edge_samples = full level
+}
+
+impl KeyingEnvelope {
+    pub fn new(edge_ms: u32, sample_rate: u32) -> Self {
+        KeyingEnvelope {
+            edge_samples: edge_samples(edge_ms, sample_rate),
+            position: 0,
+        }
+    }
+
+    // Change the edge time, keeping the current level
+    pub fn set_edge_ms(&mut self, edge_ms: u32, sample_rate: u32) {
+        let samples = edge_samples(edge_ms, sample_rate);
+        if samples != self.edge_samples {
+            self.position = (self.position as u64 * samples as u64 / self.edge_samples as u64) as u32;
+            self.edge_samples = samples;
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.position == 0
+    }
+
+    // Gain for the next sample
+    pub fn next_gain(&mut self, key_down: bool) -> f32 {
+        if key_down {
+            self.position = (self.position + 1).min(self.edge_samples);
+        } else {
+            self.position = self.position.saturating_sub(1);
+        }
+        let x = self.position as f32 / self.edge_samples as f32;
+        0.5 - 0.5 * (x * std::f32::consts::PI).cos()
+    }
+}
+
+fn edge_samples(edge_ms: u32, sample_rate: u32) -> u32 {
+    let edge_ms = edge_ms.clamp(MIN_EDGE_MS, MAX_EDGE_MS);
+    (sample_rate * edge_ms / 1000).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    fn gains(envelope: &mut KeyingEnvelope, key_down: bool, samples: usize) -> Vec<f32> {
+        (0..samples).map(|_| envelope.next_gain(key_down)).collect()
+    }
+
+    #[test]
+    fn edges_rise_and_fall_symmetrically() {
+        let edge = (DEFAULT_EDGE_MS * RATE / 1000) as usize;
+        let mut envelope = KeyingEnvelope::new(DEFAULT_EDGE_MS, RATE);
+        assert!(envelope.is_silent());
+
+        let rise = gains(&mut envelope, true, edge);
+        assert!(rise[0] > 0.0);
+        assert!(rise.windows(2).all(|pair| pair[1] > pair[0]));
+        assert_eq!(rise[edge - 1], 1.0);
+        // Stays at full level while the key is held
+        assert!(gains(&mut envelope, true, 100).iter().all(|&gain| gain == 1.0));
+
+        let fall = gains(&mut envelope, false, edge);
+        for i in 0..edge - 1 {
+            assert_eq!(fall[i], rise[edge - 2 - i]);
+        }
+        assert_eq!(fall[edge - 1], 0.0);
+        assert!(envelope.is_silent());
+    }
+
+    #[test]
+    fn edge_time_is_clamped() {
+        assert_eq!(edge_samples(0, RATE), MIN_EDGE_MS * RATE / 1000);
+        assert_eq!(edge_samples(50, RATE), MAX_EDGE_MS * RATE / 1000);
+        assert_eq!(edge_samples(5, RATE), 240);
+    }
+
+    #[test]
+    fn key_change_mid_edge_turns_around() {
+        let mut envelope = KeyingEnvelope::new(DEFAULT_EDGE_MS, RATE);
+        let rise = gains(&mut envelope, true, 100);
+        // Falls back from where it got to, not from full level
+        let fall = gains(&mut envelope, false, 100);
+        assert_eq!(fall[0], rise[98]);
+        assert_eq!(fall[99], 0.0);
+
+        gains(&mut envelope, true, 100);
+        let up_again = envelope.next_gain(false);
+        assert!(up_again < rise[99] && up_again > rise[97]);
+    }
+
+    #[test]
+    fn new_edge_time_keeps_the_level() {
+        let mut envelope = KeyingEnvelope::new(5, RATE);
+        gains(&mut envelope, true, 120);
+        assert_eq!(envelope.position, 120);
+
+        envelope.set_edge_ms(10, RATE);
+        assert_eq!(envelope.edge_samples, 480);
+        assert_eq!(envelope.position, 240);
+        // Half way up the new, longer edge
+        let gain = envelope.next_gain(true);
+        assert!((gain - 0.5).abs() < 0.01);
+
+        // Full level stays full level
+        gains(&mut envelope, true, 480);
+        envelope.set_edge_ms(2, RATE);
+        assert_eq!(envelope.position, envelope.edge_samples);
+        assert_eq!(envelope.next_gain(true), 1.0);
+    }
+}
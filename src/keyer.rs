@@ -0,0 +1,391 @@
+// Automatic keyer logic - decides which element the paddles ask for next
+// The keyer thread in main.rs does the timing and audio, this module only
+// holds the iambic state machine so it stays independent of threads and sound.
+// Straight key and bug modes bypass the state machine; ManualKey decides what their
+// contacts do.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyerMode {
+    Simple,   // Original behaviour: alternate while squeezed, stop on release
+    IambicA,  // Curtis Mode A: finish current element, nothing extra on release
+    IambicB,  // Curtis Mode B: squeeze during an element adds one opposite element
+    StraightKey,  // Either contact gates the sidetone directly
+    Bug,      // Semi-automatic: LEFT makes automatic dits, RIGHT is a manual dah contact
+}
+
+impl KeyerMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            KeyerMode::Simple => "Simple",
+            KeyerMode::IambicA => "Iambic A",
+            KeyerMode::IambicB => "Iambic B",
+            KeyerMode::StraightKey => "Straight Key",
+            KeyerMode::Bug => "Bug (Semi-Auto)",
+        }
+    }
+
+    pub fn is_iambic(&self) -> bool {
+        matches!(self, KeyerMode::IambicA | KeyerMode::IambicB)
+    }
+
+    pub fn is_manual(&self) -> bool {
+        matches!(self, KeyerMode::StraightKey | KeyerMode::Bug)
+    }
+}
+
+pub fn get_keyer_modes() -> Vec<KeyerMode> {
+    vec![
+        KeyerMode::Simple,
+        KeyerMode::IambicA,
+        KeyerMode::IambicB,
+        KeyerMode::StraightKey,
+        KeyerMode::Bug,
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyerElement {
+    Dit,
+    Dah,
+}
+
+impl KeyerElement {
+    pub fn is_dit(&self) -> bool {
+        *self == KeyerElement::Dit
+    }
+
+    pub fn opposite(&self) -> KeyerElement {
+        match self {
+            KeyerElement::Dit => KeyerElement::Dah,
+            KeyerElement::Dah => KeyerElement::Dit,
+        }
+    }
+}
+
+pub struct IambicKeyer {
+    mode: KeyerMode,
+    last_element: Option<KeyerElement>,
+    squeeze_seen: bool,    // Both paddles were down during the element
+    dit_memory: bool,      // Dit memory enabled
+    dah_memory: bool,      // Dah memory enabled
+    dit_latched: bool,     // Dit paddle pressed while a dah was being sent
+    dah_latched: bool,     // Dah paddle pressed while a dit was being sent
+}
+
+impl IambicKeyer {
+    pub fn new(mode: KeyerMode) -> Self {
+        IambicKeyer {
+            mode,
+            last_element: None,
+            squeeze_seen: false,
+            dit_memory: true,
+            dah_memory: true,
+            dit_latched: false,
+            dah_latched: false,
+        }
+    }
+
+    pub fn mode(&self) -> KeyerMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: KeyerMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.squeeze_seen = false;
+        }
+    }
+
+    pub fn set_memory(&mut self, dit_memory: bool, dah_memory: bool) {
+        self.dit_memory = dit_memory;
+        self.dah_memory = dah_memory;
+        self.dit_latched &= dit_memory;
+        self.dah_latched &= dah_memory;
+    }
+
+    // Called repeatedly while an element and its following gap are being sent.
+    // A paddle counts as pressed if it is down now or was pressed since the last call.
+    pub fn sample_paddles(&mut self, dit: bool, dah: bool) {
+        if dit && dah {
+            self.squeeze_seen = true;
+        }
+
+        // Latch the opposite paddle so a quick tap is not lost. Simple mode has no memory.
+        match self.last_element {
+            _ if !self.mode.is_iambic() => {}
+            Some(KeyerElement::Dah) if dit && self.dit_memory => self.dit_latched = true,
+            Some(KeyerElement::Dit) if dah && self.dah_memory => self.dah_latched = true,
+            _ => {}
+        }
+
+        // Mode A: letting go of a squeeze cancels anything still remembered
+        if self.mode == KeyerMode::IambicA && self.squeeze_seen && !dit && !dah {
+            self.dit_latched = false;
+            self.dah_latched = false;
+        }
+    }
+
+    // Decide the next element once the previous element and its gap are complete
+    pub fn next_element(&mut self, dit: bool, dah: bool) -> Option<KeyerElement> {
+        let squeeze_seen = std::mem::take(&mut self.squeeze_seen);
+        let dit_latched = std::mem::take(&mut self.dit_latched);
+        let dah_latched = std::mem::take(&mut self.dah_latched);
+
+        let next = match (dit, dah) {
+            // Squeeze - alternate, starting with a dit from idle
+            (true, true) => Some(self.last_element
+                .map(|e| e.opposite())
+                .unwrap_or(KeyerElement::Dit)),
+            // Mode B: a squeeze seen during the last element earns one opposite element
+            _ if squeeze_seen && self.mode == KeyerMode::IambicB => {
+                self.last_element.map(|e| e.opposite())
+            }
+            // Paddle memory: a tap on the opposite paddle is sent next
+            _ if dit_latched => Some(KeyerElement::Dit),
+            _ if dah_latched => Some(KeyerElement::Dah),
+            (true, false) => Some(KeyerElement::Dit),
+            (false, true) => Some(KeyerElement::Dah),
+            (false, false) => None,
+        };
+
+        match next {
+            Some(element) => self.last_element = Some(element),
+            // Iambic modes start a fresh squeeze from idle, Simple keeps alternating
+            None if self.mode.is_iambic() => self.last_element = None,
+            None => {}
+        }
+
+        next
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManualAction {
+    KeyDown,  // Key the sidetone until KeyUp
+    KeyUp,
+    Dit,      // Send one timed dit and its gap
+}
+
+// Manual contacts of the straight key and bug modes
+#[derive(Debug, Default)]
+pub struct ManualKey {
+    down: bool,
+}
+
+impl ManualKey {
+    // What the paddle levels ask for in `mode`, None when nothing changes.
+    // Any other mode releases a key left down by a manual mode.
+    pub fn update(&mut self, mode: KeyerMode, left: bool, right: bool) -> Option<ManualAction> {
+        let key_down = match mode {
+            // Either contact works as a straight key
+            KeyerMode::StraightKey => left || right,
+            // RIGHT is the manual dah contact and wins over LEFT's automatic dits
+            KeyerMode::Bug if right || self.down => right,
+            KeyerMode::Bug if left => return Some(ManualAction::Dit),
+            _ => false,
+        };
+
+        match (key_down, self.down) {
+            (true, false) => {
+                self.down = true;
+                Some(ManualAction::KeyDown)
+            }
+            (false, true) => {
+                self.down = false;
+                Some(ManualAction::KeyUp)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyerElement::{Dah, Dit};
+
+    // Paddle levels sampled while the element just returned is sounding
+    fn sound(keyer: &mut IambicKeyer, samples: &[(bool, bool)]) {
+        for &(dit, dah) in samples {
+            keyer.sample_paddles(dit, dah);
+        }
+    }
+
+    #[test]
+    fn squeeze_alternates_from_a_dit() {
+        for mode in [KeyerMode::Simple, KeyerMode::IambicA, KeyerMode::IambicB] {
+            let mut keyer = IambicKeyer::new(mode);
+            let elements: Vec<_> = (0..4).map(|_| {
+                let element = keyer.next_element(true, true);
+                sound(&mut keyer, &[(true, true)]);
+                element
+            }).collect();
+            assert_eq!(elements, [Some(Dit), Some(Dah), Some(Dit), Some(Dah)], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn mode_b_adds_one_element_after_a_squeeze() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicB);
+        assert_eq!(keyer.next_element(false, true), Some(Dah));
+        // Squeezed during the dah, both released before it ends
+        sound(&mut keyer, &[(false, true), (true, true), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), Some(Dit));
+        sound(&mut keyer, &[(false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn mode_a_stops_when_a_squeeze_is_released() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicA);
+        assert_eq!(keyer.next_element(false, true), Some(Dah));
+        sound(&mut keyer, &[(false, true), (true, true), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn mode_b_single_paddle_adds_nothing() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicB);
+        assert_eq!(keyer.next_element(true, false), Some(Dit));
+        sound(&mut keyer, &[(true, false), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn iambic_restarts_from_idle_simple_keeps_alternating() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicA);
+        assert_eq!(keyer.next_element(true, true), Some(Dit));
+        sound(&mut keyer, &[(true, true)]);
+        assert_eq!(keyer.next_element(true, true), Some(Dah));
+        sound(&mut keyer, &[(false, false)]);
+        keyer.next_element(false, false);
+        assert_eq!(keyer.next_element(true, true), Some(Dit));
+
+        let mut keyer = IambicKeyer::new(KeyerMode::Simple);
+        assert_eq!(keyer.next_element(true, true), Some(Dit));
+        assert_eq!(keyer.next_element(false, false), None);
+        assert_eq!(keyer.next_element(true, true), Some(Dah));
+    }
+
+    #[test]
+    fn simple_mode_follows_the_paddle_level() {
+        let mut keyer = IambicKeyer::new(KeyerMode::Simple);
+        assert_eq!(keyer.next_element(true, false), Some(Dit));
+        // A tap on the dah paddle that is over before the dit ends is not sent
+        sound(&mut keyer, &[(true, true), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn tap_during_an_element_is_remembered() {
+        for mode in [KeyerMode::IambicA, KeyerMode::IambicB] {
+            // Dah tapped and released while a held dit sounds: the dah comes next
+            let mut keyer = IambicKeyer::new(mode);
+            assert_eq!(keyer.next_element(true, false), Some(Dit));
+            sound(&mut keyer, &[(true, false), (true, true), (true, false)]);
+            assert_eq!(keyer.next_element(true, false), Some(Dah), "{:?}", mode);
+            sound(&mut keyer, &[(true, false)]);
+            assert_eq!(keyer.next_element(true, false), Some(Dit));
+
+            // Dit tapped during a dah, both paddles up by the end of it
+            let mut keyer = IambicKeyer::new(mode);
+            assert_eq!(keyer.next_element(false, true), Some(Dah));
+            sound(&mut keyer, &[(false, false), (true, false), (false, false)]);
+            assert_eq!(keyer.next_element(false, false), Some(Dit), "{:?}", mode);
+            assert_eq!(keyer.next_element(false, false), None);
+        }
+    }
+
+    #[test]
+    fn memory_switched_off() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicB);
+        keyer.set_memory(true, false);
+        assert_eq!(keyer.next_element(true, false), Some(Dit));
+        sound(&mut keyer, &[(false, false), (false, true), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+
+        // Dit memory still works
+        assert_eq!(keyer.next_element(false, true), Some(Dah));
+        sound(&mut keyer, &[(false, false), (true, false), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), Some(Dit));
+
+        keyer.set_memory(false, true);
+        assert_eq!(keyer.next_element(false, true), Some(Dah));
+        sound(&mut keyer, &[(false, false), (true, false), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn switching_memory_off_drops_a_remembered_tap() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicA);
+        assert_eq!(keyer.next_element(true, false), Some(Dit));
+        sound(&mut keyer, &[(false, false), (false, true), (false, false)]);
+        keyer.set_memory(true, false);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn same_paddle_is_not_remembered() {
+        let mut keyer = IambicKeyer::new(KeyerMode::IambicB);
+        assert_eq!(keyer.next_element(true, false), Some(Dit));
+        // Re-pressing the dit paddle during the dit does not queue another one
+        sound(&mut keyer, &[(false, false), (true, false), (false, false)]);
+        assert_eq!(keyer.next_element(false, false), None);
+    }
+
+    #[test]
+    fn straight_key_follows_either_contact() {
+        let mut key = ManualKey::default();
+        let mode = KeyerMode::StraightKey;
+        assert_eq!(key.update(mode, false, false), None);
+        assert_eq!(key.update(mode, true, false), Some(ManualAction::KeyDown));
+        assert_eq!(key.update(mode, true, false), None);
+        // Handing over from one contact to the other keeps the key down
+        assert_eq!(key.update(mode, true, true), None);
+        assert_eq!(key.update(mode, false, true), None);
+        assert_eq!(key.update(mode, false, false), Some(ManualAction::KeyUp));
+        assert_eq!(key.update(mode, false, true), Some(ManualAction::KeyDown));
+        assert!(key.down);
+    }
+
+    #[test]
+    fn bug_sends_dits_on_left_and_keys_right_by_hand() {
+        let mut key = ManualKey::default();
+        let mode = KeyerMode::Bug;
+        // Dits repeat for as long as LEFT is held
+        assert_eq!(key.update(mode, true, false), Some(ManualAction::Dit));
+        assert_eq!(key.update(mode, true, false), Some(ManualAction::Dit));
+        assert!(!key.down);
+
+        assert_eq!(key.update(mode, false, true), Some(ManualAction::KeyDown));
+        assert_eq!(key.update(mode, false, true), None);
+        // No dits while the dah contact is closed
+        assert_eq!(key.update(mode, true, true), None);
+        // Letting go of RIGHT ends the dah before any dit
+        assert_eq!(key.update(mode, true, false), Some(ManualAction::KeyUp));
+        assert_eq!(key.update(mode, true, false), Some(ManualAction::Dit));
+        assert_eq!(key.update(mode, false, false), None);
+    }
+
+    #[test]
+    fn leaving_a_manual_mode_releases_the_key() {
+        for (mode, left, right) in [(KeyerMode::StraightKey, true, false), (KeyerMode::Bug, false, true)] {
+            for next in [KeyerMode::Simple, KeyerMode::IambicA, KeyerMode::IambicB] {
+                let mut key = ManualKey::default();
+                assert_eq!(key.update(mode, left, right), Some(ManualAction::KeyDown));
+                assert_eq!(key.update(next, left, right), Some(ManualAction::KeyUp), "{:?} to {:?}", mode, next);
+                assert_eq!(key.update(next, left, right), None);
+                assert!(!key.down);
+            }
+        }
+    }
+
+    #[test]
+    fn other_modes_never_key_by_hand() {
+        let mut key = ManualKey::default();
+        for mode in [KeyerMode::Simple, KeyerMode::IambicA, KeyerMode::IambicB] {
+            assert_eq!(key.update(mode, true, true), None);
+            assert_eq!(key.update(mode, true, false), None);
+        }
+    }
+}
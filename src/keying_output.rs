@@ -0,0 +1,617 @@
+// Transmitter keying output
+// Mirrors what the sidetone and the training playback send to a serial port line (DTR or
+// RTS, through the usual transistor or opto-coupler keying interface) or to a MIDI output
+// as note on / off, so the app can key a real transmitter.
+//
+// The output runs its own timeline of key-down / key-up segments rather than following
+// the audio, which is rendered in chunks: segments are appended back to back like on the
+// tone queue, so element lengths match the sidetone exactly. PTT (on the other serial
+// line, or a second MIDI note) is raised a lead-in time before the first element and
+// dropped a hang time after the last one.
+use midir::{MidiOutput, MidiOutputConnection};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLIENT_NAME: &str = "paddle-decoder-keying";
+
+// MIDI notes for the key and PTT, channel 1 - the same notes the paddle input uses
+const KEY_NOTE: u8 = 1;
+const PTT_NOTE: u8 = 2;
+
+// Port list refresh and retry interval after an error
+const RESCAN_INTERVAL_MS: u64 = 1000;
+
+pub const MAX_LEAD_IN_MS: u32 = 200;
+pub const MAX_HANG_MS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyLine {
+    Dtr,
+    Rts,
+}
+
+impl KeyLine {
+    pub fn as_str(&self) -> &str {
+        match self {
+            KeyLine::Dtr => "DTR",
+            KeyLine::Rts => "RTS",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<KeyLine> {
+        get_key_lines().into_iter().find(|line| line.as_str() == name)
+    }
+}
+
+pub fn get_key_lines() -> Vec<KeyLine> {
+    vec![KeyLine::Dtr, KeyLine::Rts]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyingChoice {
+    Off,
+    Serial(String),
+    Midi(String),
+}
+
+impl KeyingChoice {
+    pub fn describe(&self) -> String {
+        match self {
+            KeyingChoice::Off => "Off".to_string(),
+            KeyingChoice::Serial(name) => format!("Serial: {}", name),
+            KeyingChoice::Midi(name) => format!("MIDI: {}", name),
+        }
+    }
+
+    // Settings file form: "off", "serial:COM3", "midi:Port name"
+    pub fn to_setting(&self) -> String {
+        match self {
+            KeyingChoice::Off => "off".to_string(),
+            KeyingChoice::Serial(name) => format!("serial:{}", name),
+            KeyingChoice::Midi(name) => format!("midi:{}", name),
+        }
+    }
+
+    pub fn from_setting(text: &str) -> KeyingChoice {
+        if let Some(name) = text.strip_prefix("serial:") {
+            KeyingChoice::Serial(name.to_string())
+        } else if let Some(name) = text.strip_prefix("midi:") {
+            KeyingChoice::Midi(name.to_string())
+        } else {
+            KeyingChoice::Off
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyingStatus {
+    Off,
+    Connected(String),
+    Error(String),
+}
+
+impl KeyingStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            KeyingStatus::Off => "Off".to_string(),
+            KeyingStatus::Connected(port) => format!("Connected: {}", port),
+            KeyingStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, KeyingStatus::Connected(_))
+    }
+}
+
+// Where the key and PTT go
+trait KeyLines: Send {
+    fn set_key(&mut self, down: bool) -> io::Result<()>;
+    fn set_ptt(&mut self, on: bool) -> io::Result<()>;
+}
+
+struct SerialKeyLines {
+    port: Box<dyn serialport::SerialPort>,
+    key_line: KeyLine,
+}
+
+impl SerialKeyLines {
+    fn open(name: &str, key_line: KeyLine) -> serialport::Result<Self> {
+        let mut port = serialport::new(name, 9600)
+            .timeout(Duration::from_millis(10))
+            .open()?;
+        // Some drivers raise DTR when the port opens
+        port.write_data_terminal_ready(false)?;
+        port.write_request_to_send(false)?;
+        Ok(SerialKeyLines { port, key_line })
+    }
+
+    fn write_line(&mut self, line: KeyLine, level: bool) -> io::Result<()> {
+        match line {
+            KeyLine::Dtr => self.port.write_data_terminal_ready(level)?,
+            KeyLine::Rts => self.port.write_request_to_send(level)?,
+        }
+        Ok(())
+    }
+}
+
+impl KeyLines for SerialKeyLines {
+    fn set_key(&mut self, down: bool) -> io::Result<()> {
+        self.write_line(self.key_line, down)
+    }
+
+    // PTT is on whichever line does not key
+    fn set_ptt(&mut self, on: bool) -> io::Result<()> {
+        let line = match self.key_line {
+            KeyLine::Dtr => KeyLine::Rts,
+            KeyLine::Rts => KeyLine::Dtr,
+        };
+        self.write_line(line, on)
+    }
+}
+
+struct MidiKeyLines {
+    connection: MidiOutputConnection,
+}
+
+impl MidiKeyLines {
+    fn open(name: &str) -> Result<Self, String> {
+        let midi_out = MidiOutput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+        let port = midi_out.ports().into_iter()
+            .find(|port| midi_out.port_name(port).ok().as_deref() == Some(name))
+            .ok_or_else(|| "port not found".to_string())?;
+        let connection = midi_out.connect(&port, CLIENT_NAME).map_err(|e| e.to_string())?;
+        Ok(MidiKeyLines { connection })
+    }
+
+    fn note(&mut self, note: u8, on: bool) -> io::Result<()> {
+        let message = if on { [0x90, note, 127] } else { [0x80, note, 0] };
+        self.connection.send(&message)
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+impl KeyLines for MidiKeyLines {
+    fn set_key(&mut self, down: bool) -> io::Result<()> {
+        self.note(KEY_NOTE, down)
+    }
+
+    fn set_ptt(&mut self, on: bool) -> io::Result<()> {
+        self.note(PTT_NOTE, on)
+    }
+}
+
+fn open_lines(choice: &KeyingChoice, key_line: KeyLine) -> Option<Result<Box<dyn KeyLines>, String>> {
+    match choice {
+        KeyingChoice::Off => None,
+        KeyingChoice::Serial(name) => Some(SerialKeyLines::open(name, key_line)
+            .map(|lines| Box::new(lines) as Box<dyn KeyLines>)
+            .map_err(|e| e.to_string())),
+        KeyingChoice::Midi(name) => Some(MidiKeyLines::open(name)
+            .map(|lines| Box::new(lines) as Box<dyn KeyLines>)),
+    }
+}
+
+struct Shared {
+    serial_ports: Vec<String>,
+    midi_ports: Vec<String>,
+    choice: KeyingChoice,
+    key_line: KeyLine,
+    lead_in_ms: u32,
+    hang_ms: u32,
+    status: KeyingStatus,
+    reopen: bool,
+    segments: VecDeque<(bool, f64)>,  // Key down, length in ms
+    live_key: bool,
+}
+
+#[derive(Clone)]
+pub struct KeyingOutput {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl KeyingOutput {
+    pub fn start(choice: KeyingChoice, key_line: KeyLine, lead_in_ms: u32, hang_ms: u32) -> Self {
+        let output = KeyingOutput {
+            shared: Arc::new(Mutex::new(Shared {
+                serial_ports: Vec::new(),
+                midi_ports: Vec::new(),
+                choice,
+                key_line,
+                lead_in_ms,
+                hang_ms,
+                status: KeyingStatus::Off,
+                reopen: false,
+                segments: VecDeque::new(),
+                live_key: false,
+            })),
+        };
+
+        let runner = output.clone();
+        thread::spawn(move || runner.run());
+        output
+    }
+
+    // Serial and MIDI output ports to offer in the picker
+    pub fn choices(&self) -> Vec<KeyingChoice> {
+        let shared = self.shared.lock().unwrap();
+        let mut choices = vec![KeyingChoice::Off];
+        choices.extend(shared.serial_ports.iter().cloned().map(KeyingChoice::Serial));
+        choices.extend(shared.midi_ports.iter().cloned().map(KeyingChoice::Midi));
+        choices
+    }
+
+    pub fn choice(&self) -> KeyingChoice {
+        self.shared.lock().unwrap().choice.clone()
+    }
+
+    pub fn set_choice(&self, choice: KeyingChoice) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.choice != choice {
+            shared.choice = choice;
+            shared.reopen = true;
+        }
+    }
+
+    pub fn key_line(&self) -> KeyLine {
+        self.shared.lock().unwrap().key_line
+    }
+
+    pub fn set_key_line(&self, key_line: KeyLine) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.key_line != key_line {
+            shared.key_line = key_line;
+            shared.reopen = true;
+        }
+    }
+
+    // PTT lead-in and hang time; both 0 leaves PTT alone (QSK)
+    pub fn ptt_times(&self) -> (u32, u32) {
+        let shared = self.shared.lock().unwrap();
+        (shared.lead_in_ms, shared.hang_ms)
+    }
+
+    pub fn set_ptt_times(&self, lead_in_ms: u32, hang_ms: u32) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.lead_in_ms = lead_in_ms.min(MAX_LEAD_IN_MS);
+        shared.hang_ms = hang_ms.min(MAX_HANG_MS);
+    }
+
+    pub fn status(&self) -> KeyingStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    // Key down or up for the given time, after everything queued so far
+    pub fn push(&self, key_down: bool, duration_ms: f64) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.status.is_connected() && duration_ms > 0.0 {
+            shared.segments.push_back((key_down, duration_ms));
+        }
+    }
+
+    // Live key (straight key), used while nothing is queued
+    pub fn key(&self, down: bool) {
+        self.shared.lock().unwrap().live_key = down;
+    }
+
+    fn set_status(&self, status: KeyingStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self) {
+        let mut lines: Option<Box<dyn KeyLines>> = None;
+        let mut next_scan = Instant::now();
+        let mut timeline = Timeline::default();
+
+        loop {
+            let now = Instant::now();
+            if now >= next_scan {
+                next_scan = now + Duration::from_millis(RESCAN_INTERVAL_MS);
+                self.refresh_ports();
+
+                // (Re)open the chosen output; after an error this retries once per rescan
+                if lines.is_none() {
+                    let (choice, key_line) = {
+                        let shared = self.shared.lock().unwrap();
+                        (shared.choice.clone(), shared.key_line)
+                    };
+                    match open_lines(&choice, key_line) {
+                        None => self.set_status(KeyingStatus::Off),
+                        Some(Ok(opened)) => {
+                            println!("Keying output connected: {}", choice.describe());
+                            self.set_status(KeyingStatus::Connected(choice.describe()));
+                            lines = Some(opened);
+                        }
+                        Some(Err(e)) => self.set_status(KeyingStatus::Error(format!("{}: {}", choice.describe(), e))),
+                    }
+                }
+            }
+
+            if std::mem::take(&mut self.shared.lock().unwrap().reopen) {
+                if let Some(lines) = lines.as_mut() {
+                    let _ = lines.set_key(false);
+                    let _ = lines.set_ptt(false);
+                }
+                lines = None;
+                timeline = Timeline::default();
+                self.shared.lock().unwrap().segments.clear();
+                self.set_status(KeyingStatus::Off);
+                next_scan = now;
+                continue;
+            }
+
+            let wait = match lines.as_mut() {
+                Some(output) => match timeline.step(&self.shared, output.as_mut(), now) {
+                    Ok(wait) => wait,
+                    Err(e) => {
+                        eprintln!("Keying output error: {}", e);
+                        self.set_status(KeyingStatus::Error(e.to_string()));
+                        self.shared.lock().unwrap().segments.clear();
+                        lines = None;
+                        timeline = Timeline::default();
+                        Duration::from_millis(50)
+                    }
+                },
+                None => Duration::from_millis(50),
+            };
+            thread::sleep(wait);
+        }
+    }
+
+    fn refresh_ports(&self) {
+        let serial_ports: Vec<String> = serialport::available_ports()
+            .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+            .unwrap_or_default();
+        let midi_ports: Vec<String> = MidiOutput::new(CLIENT_NAME)
+            .map(|midi_out| midi_out.ports().iter()
+                .filter_map(|port| midi_out.port_name(port).ok())
+                .collect())
+            .unwrap_or_default();
+
+        let mut shared = self.shared.lock().unwrap();
+        shared.serial_ports = serial_ports;
+        shared.midi_ports = midi_ports;
+    }
+}
+
+// Where the output is on its timeline
+#[derive(Default)]
+struct Timeline {
+    key_down: bool,
+    ptt_on: bool,
+    segment_end: Option<Instant>,  // End of the segment being sent
+    hold_until: Option<Instant>,   // PTT lead-in running
+    idle_since: Option<Instant>,   // Key up with nothing queued
+}
+
+impl Timeline {
+    // Bring the lines up to date; returns how long to sleep before the next step
+    fn step(&mut self, shared: &Mutex<Shared>, lines: &mut dyn KeyLines, now: Instant) -> io::Result<Duration> {
+        let tick = Duration::from_millis(1);
+
+        // Still inside the current segment
+        if let Some(end) = self.segment_end {
+            if now < end {
+                return Ok((end - now).min(tick));
+            }
+        }
+
+        let (next, live_key, lead_in, hang) = {
+            let mut shared = shared.lock().unwrap();
+            let busy = !shared.segments.is_empty() || shared.live_key;
+            let lead_in = Duration::from_millis(shared.lead_in_ms as u64);
+            // Hold the timeline while PTT is still coming up
+            let next = if self.hold_until.is_none_or(|until| now >= until) && busy {
+                shared.segments.pop_front()
+            } else {
+                None
+            };
+            let use_ptt = shared.lead_in_ms > 0 || shared.hang_ms > 0;
+            (next, shared.live_key, use_ptt.then_some(lead_in), Duration::from_millis(shared.hang_ms as u64))
+        };
+
+        // Something to send: raise PTT first
+        let busy = next.is_some() || live_key;
+        if busy && !self.ptt_on {
+            if let Some(lead_in) = lead_in {
+                lines.set_ptt(true)?;
+                self.ptt_on = true;
+                if !lead_in.is_zero() {
+                    self.hold_until = Some(now + lead_in);
+                    self.segment_end = None;
+                    // The segment waits for the lead-in
+                    if let Some(segment) = next {
+                        shared.lock().unwrap().segments.push_front(segment);
+                    }
+                    return Ok(tick);
+                }
+            }
+        }
+
+        let key_down = match next {
+            Some((key_down, duration_ms)) => {
+                // Back to back with the previous segment unless the output fell idle
+                let start = match self.segment_end {
+                    Some(end) if now - end <= tick => end,
+                    _ => now,
+                };
+                self.segment_end = Some(start + Duration::from_secs_f64(duration_ms / 1000.0));
+                key_down
+            }
+            None => {
+                self.segment_end = None;
+                live_key && self.hold_until.is_none_or(|until| now >= until)
+            }
+        };
+
+        if key_down != self.key_down {
+            lines.set_key(key_down)?;
+            self.key_down = key_down;
+        }
+
+        // Drop PTT once nothing has been sent for the hang time
+        if key_down || next.is_some() {
+            self.idle_since = None;
+        } else {
+            let idle_since = *self.idle_since.get_or_insert(now);
+            if self.ptt_on && now - idle_since >= hang {
+                lines.set_ptt(false)?;
+                self.ptt_on = false;
+                self.hold_until = None;
+            }
+        }
+        Ok(tick)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Line changes with the time they happened, in ms from the start
+    #[derive(Default)]
+    struct RecordedLines {
+        now_ms: u64,
+        changes: Vec<(u64, &'static str, bool)>,
+    }
+
+    impl KeyLines for RecordedLines {
+        fn set_key(&mut self, down: bool) -> io::Result<()> {
+            self.changes.push((self.now_ms, "key", down));
+            Ok(())
+        }
+
+        fn set_ptt(&mut self, on: bool) -> io::Result<()> {
+            self.changes.push((self.now_ms, "ptt", on));
+            Ok(())
+        }
+    }
+
+    // A timeline stepped a millisecond at a time on a clock of its own
+    struct Run {
+        shared: Mutex<Shared>,
+        timeline: Timeline,
+        lines: RecordedLines,
+        start: Instant,
+    }
+
+    impl Run {
+        fn new(lead_in_ms: u32, hang_ms: u32) -> Run {
+            Run {
+                shared: Mutex::new(Shared {
+                    serial_ports: Vec::new(),
+                    midi_ports: Vec::new(),
+                    choice: KeyingChoice::Off,
+                    key_line: KeyLine::Dtr,
+                    lead_in_ms,
+                    hang_ms,
+                    status: KeyingStatus::Connected("test".to_string()),
+                    reopen: false,
+                    segments: VecDeque::new(),
+                    live_key: false,
+                }),
+                timeline: Timeline::default(),
+                lines: RecordedLines::default(),
+                start: Instant::now(),
+            }
+        }
+
+        fn push(&self, segments: &[(bool, f64)]) {
+            self.shared.lock().unwrap().segments.extend(segments);
+        }
+
+        fn until(&mut self, end_ms: u64) {
+            while self.lines.now_ms < end_ms {
+                let now = self.start + Duration::from_millis(self.lines.now_ms);
+                self.timeline.step(&self.shared, &mut self.lines, now).unwrap();
+                self.lines.now_ms += 1;
+            }
+        }
+    }
+
+    // Dit and dah at 20 WPM, each with its element gap
+    const DIT_DAH: [(bool, f64); 4] = [(true, 60.0), (false, 60.0), (true, 180.0), (false, 60.0)];
+
+    #[test]
+    fn ptt_lead_in_and_hang() {
+        let mut run = Run::new(50, 200);
+        run.push(&DIT_DAH);
+        run.until(1000);
+        assert_eq!(run.lines.changes, [
+            (0, "ptt", true),
+            // The first element waits for the lead-in, then the rest follow back to back
+            (50, "key", true),
+            (110, "key", false),
+            (170, "key", true),
+            (350, "key", false),
+            // Dropped the hang time after the last segment (its element gap) ends
+            (610, "ptt", false),
+        ]);
+    }
+
+    #[test]
+    fn ptt_held_through_a_pause_shorter_than_the_hang() {
+        let mut run = Run::new(50, 200);
+        run.push(&DIT_DAH);
+        run.until(500);
+        // Another letter inside the hang time: no new lead-in, PTT stays up
+        run.push(&DIT_DAH);
+        run.until(1500);
+        assert_eq!(run.lines.changes, [
+            (0, "ptt", true),
+            (50, "key", true),
+            (110, "key", false),
+            (170, "key", true),
+            (350, "key", false),
+            (500, "key", true),
+            (560, "key", false),
+            (620, "key", true),
+            (800, "key", false),
+            (1060, "ptt", false),
+        ]);
+    }
+
+    #[test]
+    fn qsk_leaves_ptt_alone() {
+        let mut run = Run::new(0, 0);
+        run.push(&DIT_DAH);
+        run.until(1000);
+        assert_eq!(run.lines.changes, [
+            (0, "key", true),
+            (60, "key", false),
+            (120, "key", true),
+            (300, "key", false),
+        ]);
+    }
+
+    #[test]
+    fn hang_without_lead_in() {
+        let mut run = Run::new(0, 100);
+        run.push(&DIT_DAH[..2]);
+        run.until(1000);
+        assert_eq!(run.lines.changes, [
+            (0, "ptt", true),
+            (0, "key", true),
+            (60, "key", false),
+            (220, "ptt", false),
+        ]);
+    }
+
+    #[test]
+    fn live_key_waits_for_the_lead_in() {
+        let mut run = Run::new(30, 100);
+        run.shared.lock().unwrap().live_key = true;
+        run.until(200);
+        run.shared.lock().unwrap().live_key = false;
+        run.until(1000);
+        assert_eq!(run.lines.changes, [
+            (0, "ptt", true),
+            (30, "key", true),
+            (200, "key", false),
+            (300, "ptt", false),
+        ]);
+    }
+}
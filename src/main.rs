@@ -1,6 +1,5 @@
 use eframe::egui;
-use midir::{MidiInput, MidiInputConnection};
-use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::{OutputStream, OutputStreamHandle, Sink};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::thread;
@@ -11,209 +10,123 @@ use cw_academy_training::{SessionNumber, PracticeType, get_cumulative_session,
                           BlockSize, get_block_sizes, generate_random_block};
 
 mod morse_player;
-use morse_player::{MorsePlayer, MorseElement};
+use morse_player::MorsePlayer;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum TrainingState {
-    SelectingItem,
-    Playing,
-    WaitingForInput,
-    CheckingAnswer,
-}
+mod keyer;
+use keyer::{IambicKeyer, KeyerMode, ManualAction, ManualKey, get_keyer_modes};
 
-#[derive(Debug, Clone)]
-struct TrainingData {
-    state: TrainingState,
-    current_text: String,
-    user_input: String,
-    attempts: u32,
-    feedback_message: String,
-    feedback_color: egui::Color32,
-    show_answer: bool,
-}
+mod morse_decoder;
+use morse_decoder::{MorseDecoder, Symbol};
 
-// Morse code decoder
-struct MorseDecoder {
-    current_sequence: String,
-    last_element_time: Option<Instant>,
-    wpm: u32,
-    dit_length_ms: u32,
-    letter_space_ms: u32,
-}
+mod morse_table;
+use morse_table::{Alphabet, get_alphabets};
 
-impl MorseDecoder {
-    fn new(wpm: u32) -> Self {
-        let dit_length_ms = 1200 / wpm.max(1);
-        let letter_space_ms = dit_length_ms * 5;
-        
-        MorseDecoder {
-            current_sequence: String::new(),
-            last_element_time: None,
-            wpm,
-            dit_length_ms,
-            letter_space_ms,
-        }
-    }
-    
-    fn update_wpm(&mut self, wpm: u32) {
-        self.wpm = wpm.clamp(5, 40);
-        self.dit_length_ms = 1200 / self.wpm;
-        self.letter_space_ms = self.dit_length_ms * 5;
-    }
-    
-    fn add_element(&mut self, is_dit: bool) {
-        if is_dit {
-            self.current_sequence.push('.');
-        } else {
-            self.current_sequence.push('-');
-        }
-        self.last_element_time = Some(Instant::now());
-    }
-    
-    fn check_timeout(&mut self) -> Option<String> {
-        if let Some(last_time) = self.last_element_time {
-            let elapsed = last_time.elapsed().as_millis() as u32;
-            
-            if elapsed > self.letter_space_ms && !self.current_sequence.is_empty() {
-                let decoded = self.decode_sequence();
-                self.current_sequence.clear();
-                self.last_element_time = None;
-                return Some(decoded);
-            }
-        }
-        None
-    }
-    
-    fn decode_sequence(&self) -> String {
-        match self.current_sequence.as_str() {
-            // Letters
-            ".-" => "A", "-..." => "B", "-.-." => "C", "-.." => "D",
-            "." => "E", "..-." => "F", "--." => "G", "...." => "H",
-            ".." => "I", ".---" => "J", "-.-" => "K", ".-.." => "L",
-            "--" => "M", "-." => "N", "---" => "O", ".--." => "P",
-            "--.-" => "Q", ".-." => "R", "..." => "S", "-" => "T",
-            "..-" => "U", "...-" => "V", ".--" => "W", "-..-" => "X",
-            "-.--" => "Y", "--.." => "Z",
-            // Numbers
-            "-----" => "0", ".----" => "1", "..---" => "2", "...--" => "3",
-            "....-" => "4", "....." => "5", "-...." => "6", "--..." => "7",
-            "---.." => "8", "----." => "9",
-            // Punctuation
-            ".-.-.-" => ".", "--..--" => ",", "..--.." => "?", ".----." => "'",
-            "-.-.--" => "!", "-..-." => "/", "-.--." => "(", "-.--.-" => ")",
-            ".-..." => "&", "---..." => ":", "-.-.-." => ";", "-...-" => "=",
-            ".-.-." => "+", "-....-" => "-", "..--.-" => "_", ".-..-." => "\"",
-            "...-..-" => "$", ".--.-." => "@",
-            // Prosigns (special multi-character sequences)
-            "-..-.-" => "<BK>",  // Break (pause in transmission)
-            ".-.-.." => "<AR>",  // End of message
-            // NOTE: <BT> uses same pattern as "=" (-...-), so it decodes as "="
-            "...-.-" => "<SK>",  // End of contact/silent key
-            _ => "?",
-        }.to_string()
-    }
-}
+mod envelope;
+use envelope::{DEFAULT_EDGE_MS, MIN_EDGE_MS, MAX_EDGE_MS};
 
-// Audio tone generator
-struct ToneGenerator {
-    frequency: f32,
-    sample_rate: u32,
-    phase: f32,
-}
+mod tone_queue;
+use tone_queue::ToneQueue;
 
-impl ToneGenerator {
-    fn new(frequency: f32) -> Self {
-        ToneGenerator {
-            frequency,
-            sample_rate: 48000,
-            phase: 0.0,
-        }
-    }
-}
+mod band_conditions;
+use band_conditions::{BandConditions, get_noise_colors};
 
-impl Iterator for ToneGenerator {
-    type Item = f32;
-    
-    fn next(&mut self) -> Option<Self::Item> {
-        let sample = (self.phase * 2.0 * std::f32::consts::PI).sin() * 0.3;
-        self.phase += self.frequency / self.sample_rate as f32;
-        if self.phase >= 1.0 {
-            self.phase -= 1.0;
-        }
-        Some(sample)
-    }
-}
+mod settings;
+use settings::Settings;
 
-impl Source for ToneGenerator {
-    fn current_frame_len(&self) -> Option<usize> { None }
-    fn channels(&self) -> u16 { 1 }
-    fn sample_rate(&self) -> u32 { self.sample_rate }
-    fn total_duration(&self) -> Option<Duration> { None }
-}
+mod midi_input;
+use midi_input::MidiInputManager;
 
-// Function to play morse code sequence
-fn play_morse_sequence(
-    morse_elements: Vec<MorseElement>,
-    sink: Arc<Mutex<Sink>>,
-    wpm: u32,
-    frequency: u32,
-) {
-    thread::spawn(move || {
-        let dit_ms = 1200 / wpm.max(1);
-        
-        for element in morse_elements {
-            match element {
-                MorseElement::Dit => {
-                    // Play dit
-                    {
-                        let snk = sink.lock().unwrap();
-                        snk.stop();
-                        let tone = ToneGenerator::new(frequency as f32);
-                        snk.append(tone);
-                        snk.play();
-                    }
-                    thread::sleep(Duration::from_millis(dit_ms as u64));
-                    sink.lock().unwrap().stop();
-                    // Element space
-                    thread::sleep(Duration::from_millis(dit_ms as u64));
-                }
-                MorseElement::Dah => {
-                    // Play dah
-                    {
-                        let snk = sink.lock().unwrap();
-                        snk.stop();
-                        let tone = ToneGenerator::new(frequency as f32);
-                        snk.append(tone);
-                        snk.play();
-                    }
-                    thread::sleep(Duration::from_millis((dit_ms * 3) as u64));
-                    sink.lock().unwrap().stop();
-                    // Element space
-                    thread::sleep(Duration::from_millis(dit_ms as u64));
-                }
-                MorseElement::LetterSpace => {
-                    // Extra space between letters (already have 1 dit, add 2 more = 3 total)
-                    thread::sleep(Duration::from_millis((dit_ms * 2) as u64));
-                }
-                MorseElement::WordSpace => {
-                    // Space between words (7 dits total, already have 1, add 6 more)
-                    thread::sleep(Duration::from_millis((dit_ms * 6) as u64));
-                }
-            }
-        }
-    });
-}
+mod midi_mapping;
+use midi_mapping::{MidiBinding, MidiMapping, MidiTrigger, learn_binding};
+
+mod serial_input;
+use serial_input::{SerialChoice, SerialInputManager, get_serial_choices};
+
+mod winkeyer;
+use winkeyer::{WinkeyerChoice, WinkeyerHost, get_winkeyer_choices};
+
+mod winkeyer_emulator;
+use winkeyer_emulator::{EmulatorChoice, WinkeyerEmulator, get_emulator_choices};
+
+mod keying_output;
+use keying_output::{KeyLine, KeyingChoice, KeyingOutput, get_key_lines, MAX_HANG_MS, MAX_LEAD_IN_MS};
+
+mod rig_control;
+use rig_control::{RigControl, DEFAULT_ADDRESS};
+
+mod mopp;
+use mopp::{MoppClient, DEFAULT_SERVER, MAX_CHANNEL};
+
+mod practice_net;
+use practice_net::NetRelay;
+
+mod tone_detector;
+use tone_detector::decode_wav;
+
+mod audio_input;
+use audio_input::{AudioInputChoice, AudioInputManager, get_audio_input_choices};
+
+mod cli;
+
+mod paddle_input;
+use paddle_input::{PaddleContacts, PaddleKey, PaddleSide, PaddleSource, get_paddle_keys,
+                   DEFAULT_LEFT_KEY, DEFAULT_RIGHT_KEY};
+
+const MIDI_PORT_SETTING: &str = "midi_port";
+const MIDI_LEFT_SETTING: &str = "midi_left";
+const MIDI_RIGHT_SETTING: &str = "midi_right";
+const SERIAL_PORT_SETTING: &str = "serial_port";
+const SERIAL_SWAP_SETTING: &str = "serial_swap";
+const WINKEYER_PORT_SETTING: &str = "winkeyer_port";
+const WINKEYER_EMULATOR_SETTING: &str = "winkeyer_emulator";
+const KEYING_OUTPUT_SETTING: &str = "keying_output";
+const KEYING_LINE_SETTING: &str = "keying_line";
+const PTT_LEAD_IN_SETTING: &str = "ptt_lead_in_ms";
+const PTT_HANG_SETTING: &str = "ptt_hang_ms";
+const KEYING_PLAYBACK_SETTING: &str = "keying_playback";
+const RIG_ENABLED_SETTING: &str = "rig_enabled";
+const RIG_ADDRESS_SETTING: &str = "rig_address";
+const RIG_SEND_DECODED_SETTING: &str = "rig_send_decoded";
+const RIG_SYNC_SPEED_SETTING: &str = "rig_sync_speed";
+const RIG_MACRO_SETTINGS: [&str; 4] = ["rig_macro_1", "rig_macro_2", "rig_macro_3", "rig_macro_4"];
+const MOPP_ENABLED_SETTING: &str = "mopp_enabled";
+const MOPP_SERVER_SETTING: &str = "mopp_server";
+const MOPP_CHANNEL_SETTING: &str = "mopp_channel";
+const NET_RELAY_ENABLED_SETTING: &str = "net_relay_enabled";
+const NET_RELAY_PORT_SETTING: &str = "net_relay_port";
+const AUDIO_INPUT_SETTING: &str = "audio_input";
+const KEYBOARD_PADDLES_SETTING: &str = "keyboard_paddles";
+const KEY_LEFT_SETTING: &str = "key_left";
+const KEY_RIGHT_SETTING: &str = "key_right";
 
 // Shared application state
 #[derive(Clone)]
 struct AppState {
     left_pressed: bool,
     right_pressed: bool,
+    left_press_latched: bool,   // Press edge seen since the keyer last looked
+    right_press_latched: bool,
+    paddle_contacts: PaddleContacts,  // Paddles held down by each input source
+    keyboard_paddles: bool,
+    key_left: PaddleKey,
+    key_right: PaddleKey,
+    midi_mapping: MidiMapping,
+    midi_learn: Option<PaddleSide>,  // Paddle waiting for a MIDI message to learn
+    midi_mapping_changed: bool,      // Not saved to the settings file yet
     decoded_text: String,
     current_sequence: String,
     wpm: u32,
     farnsworth_wpm: u32,  // Effective WPM with Farnsworth spacing
     frequency: u32,
+    envelope_ms: u32,  // Rise/fall time of sidetone and playback tones
+    keyer_mode: KeyerMode,
+    dit_memory: bool,
+    dah_memory: bool,
+    adaptive_decoding: bool,  // Decoder follows the sender's measured speed
+    auto_word_space: bool,    // Insert a space after a word-length pause
+    prefer_prosigns: bool,    // Decode <BT>, <AR>, <KN>, <AS> instead of = + ( &
+    alphabet: Alphabet,       // Letters used by the decoder and the player
+    word_space_dits: f32,
     // Training mode
     training_mode: bool,
     show_training_window: bool,
@@ -246,6 +159,24 @@ struct AppState {
     // Result display timing
     result_display_start: Option<Instant>,
     result_display_duration: u64, // seconds to show result before moving to next
+    // Simulated band conditions for listening practice and WAV export
+    band_conditions: BandConditions,
+    // WAV export
+    export_text: String,
+    export_path: String,
+    export_status: String,
+    winkeyer_playback: bool,  // Listening drills are keyed on the WinKeyer
+    keying_playback: bool,    // Training playback keys the transmitter too
+    rig_address: String,      // rigctld host:port being edited
+    rig_text: String,
+    rig_macros: [String; 4],
+    winkeyer_text: String,
+    mopp_server: String,      // MOPP server host:port being edited
+    // Decoding a recording
+    decode_file_path: String,
+    decode_file_busy: bool,
+    decode_file_status: String,
+    decode_file_text: String,
 }
 
 impl Default for AppState {
@@ -253,11 +184,29 @@ impl Default for AppState {
         AppState {
             left_pressed: false,
             right_pressed: false,
+            left_press_latched: false,
+            right_press_latched: false,
+            paddle_contacts: PaddleContacts::default(),
+            keyboard_paddles: true,
+            key_left: DEFAULT_LEFT_KEY,
+            key_right: DEFAULT_RIGHT_KEY,
+            midi_mapping: MidiMapping::default(),
+            midi_learn: None,
+            midi_mapping_changed: false,
             decoded_text: String::new(),
             current_sequence: String::new(),
             wpm: 20,
             farnsworth_wpm: 15,  // Default Farnsworth spacing
             frequency: 600,
+            envelope_ms: DEFAULT_EDGE_MS,
+            keyer_mode: KeyerMode::Simple,
+            dit_memory: true,
+            dah_memory: true,
+            adaptive_decoding: true,
+            auto_word_space: true,
+            prefer_prosigns: false,
+            alphabet: Alphabet::Latin,
+            word_space_dits: 7.0,
             training_mode: false,
             show_training_window: false,
             training_session_active: false,  // Not started by default
@@ -283,18 +232,79 @@ impl Default for AppState {
             repeat_wrong_chance: 0.3, // 30% chance to repeat wrong answers
             result_display_start: None,
             result_display_duration: 3, // Show result for 3 seconds before moving to next
+            band_conditions: BandConditions::default(),
+            export_text: String::new(),
+            export_path: "cw_practice.wav".to_string(),
+            export_status: String::new(),
+            winkeyer_playback: false,
+            keying_playback: true,
+            rig_address: DEFAULT_ADDRESS.to_string(),
+            rig_text: String::new(),
+            rig_macros: [
+                "CQ CQ CQ".to_string(),
+                "TU 5NN".to_string(),
+                "QRZ?".to_string(),
+                "TU 73".to_string(),
+            ],
+            winkeyer_text: String::new(),
+            mopp_server: DEFAULT_SERVER.to_string(),
+            decode_file_path: "recording.wav".to_string(),
+            decode_file_busy: false,
+            decode_file_status: String::new(),
+            decode_file_text: String::new(),
+        }
+    }
+}
+
+impl AppState {
+    // Update a paddle from one input source, remembering press edges so short taps
+    // are not lost. The paddle stays down while any source holds it.
+    fn set_paddle(&mut self, source: PaddleSource, side: PaddleSide, pressed: bool) {
+        self.paddle_contacts.set(source, side, pressed);
+        let pressed = self.paddle_contacts.is_down(side);
+        if side.is_left() {
+            self.left_press_latched |= pressed && !self.left_pressed;
+            self.left_pressed = pressed;
+        } else {
+            self.right_press_latched |= pressed && !self.right_pressed;
+            self.right_pressed = pressed;
         }
     }
+    
+    // Let go of both paddles held by one source, e.g. when its device is unplugged
+    fn release_source(&mut self, source: PaddleSource) {
+        self.set_paddle(source, PaddleSide::Left, false);
+        self.set_paddle(source, PaddleSide::Right, false);
+    }
+    
+    // Paddle levels combined with any press edge since the last call
+    fn take_paddles(&mut self) -> (bool, bool) {
+        // Take the latches even while the paddle is held, or a long press would
+        // come back as one more press after it is released
+        let left = std::mem::take(&mut self.left_press_latched) || self.left_pressed;
+        let right = std::mem::take(&mut self.right_press_latched) || self.right_pressed;
+        (left, right)
+    }
 }
 
 // Main GUI application
 struct PaddleDecoderApp {
     state: Arc<Mutex<AppState>>,
-    _midi_conn: Option<MidiInputConnection<()>>,
+    midi: MidiInputManager,
+    serial: SerialInputManager,
+    winkeyer: WinkeyerHost,
+    emulator: WinkeyerEmulator,
+    keying: KeyingOutput,
+    rig: RigControl,
+    mopp: MoppClient,
+    relay: NetRelay,
+    audio_input: AudioInputManager,
+    settings: Settings,
     decoder: Arc<Mutex<MorseDecoder>>,
     _stream: OutputStream,
     _stream_handle: OutputStreamHandle,
     playback_sink: Arc<Mutex<Sink>>,
+    _sidetone_sink: Sink,
 }
 
 impl PaddleDecoderApp {
@@ -311,6 +321,64 @@ impl PaddleDecoderApp {
         }
         
         // Otherwise generate a new item
+        Self::generate_training_item(state)
+    }
+    
+    // Keyboard paddles: key presses are applied in order so a tap shorter than a frame
+    // still reaches the keyer. Typing into a text field is not keying.
+    fn update_keyboard_paddles(&self, ctx: &egui::Context) {
+        let mut state = self.state.lock().unwrap();
+        let active = state.keyboard_paddles
+            && ctx.input(|i| i.focused)
+            && !ctx.wants_keyboard_input();
+        
+        for (side, key) in [(PaddleSide::Left, state.key_left), (PaddleSide::Right, state.key_right)] {
+            let (tapped, down) = ctx.input(|i| {
+                let tapped = i.events.iter().any(|event| matches!(event,
+                    egui::Event::Key { key: k, pressed: true, repeat: false, .. }
+                        if PaddleKey::Key(*k) == key));
+                (tapped, key.is_down(i))
+            });
+            if active && tapped {
+                state.set_paddle(PaddleSource::Keyboard, side, true);
+            }
+            state.set_paddle(PaddleSource::Keyboard, side, active && down);
+        }
+    }
+    
+    // Player with the current speed, pitch, alphabet, tone and band settings
+    fn make_player(state: &AppState) -> MorsePlayer {
+        let mut player = MorsePlayer::new_with_alphabet(
+            state.frequency as f32, state.wpm, state.farnsworth_wpm, state.alphabet);
+        player.set_edge_ms(state.envelope_ms);
+        player.set_band_conditions(state.band_conditions);
+        player
+    }
+    
+    // Play text for the listening drills, keyed on the WinKeyer when that is chosen
+    fn start_playback(&self, state: &AppState, text: String, delay: Duration) {
+        if state.winkeyer_playback && self.winkeyer.is_connected() {
+            let winkeyer = self.winkeyer.clone();
+            thread::spawn(move || {
+                thread::sleep(delay);
+                winkeyer.send_text(&text);
+            });
+        } else {
+            let sink_clone = Arc::clone(&self.playback_sink);
+            let mut player = Self::make_player(state);
+            if state.keying_playback {
+                player.set_keying_output(self.keying.clone());
+            }
+            thread::spawn(move || {
+                thread::sleep(delay);
+                let sink = sink_clone.lock().unwrap();
+                player.play_morse(&sink, &text);
+            });
+        }
+    }
+    
+    // New item from the current session or random block settings
+    fn generate_training_item(state: &AppState) -> String {
         if state.random_blocks_mode {
             generate_random_block(
                 state.block_from_session,
@@ -325,43 +393,210 @@ impl PaddleDecoderApp {
         }
     }
     
-    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        install_fallback_fonts(&cc.egui_ctx);
+        
         let (stream, stream_handle) = OutputStream::try_default()
             .expect("Failed to open audio stream");
         
-        let sink = Arc::new(Mutex::new(
-            Sink::try_new(&stream_handle).expect("Failed to create audio sink")
-        ));
+        let state = Arc::new(Mutex::new(AppState::default()));
+        
+        // The sidetone plays silence until the keyer queues elements or keys it
+        let sidetone = {
+            let s = state.lock().unwrap();
+            ToneQueue::new(s.frequency as f32, s.envelope_ms)
+        };
+        let sidetone_sink = Sink::try_new(&stream_handle).expect("Failed to create audio sink");
+        sidetone_sink.append(sidetone.source());
+        sidetone_sink.play();
         
         // Create a separate sink for morse playback
         let playback_sink = Arc::new(Mutex::new(
             Sink::try_new(&stream_handle).expect("Failed to create playback sink")
         ));
         
-        let state = Arc::new(Mutex::new(AppState::default()));
-        let state_clone = Arc::clone(&state);
-        
-        let decoder = Arc::new(Mutex::new(MorseDecoder::new(20)));
-        let decoder_clone = Arc::clone(&decoder);
+        let decoder = {
+            let s = state.lock().unwrap();
+            let mut decoder = MorseDecoder::new(s.wpm);
+            decoder.update_farnsworth(s.farnsworth_wpm);
+            decoder.set_word_space(s.auto_word_space, s.word_space_dits);
+            decoder.set_prefer_prosigns(s.prefer_prosigns);
+            decoder.set_alphabet(s.alphabet);
+            Arc::new(Mutex::new(decoder))
+        };
         
         // Start automatic keyer thread
         let keyer_state = Arc::clone(&state);
         let keyer_decoder = Arc::clone(&decoder);
-        let keyer_sink = Arc::clone(&sink);
+        let emulator_sidetone = sidetone.clone();
         
         thread::spawn(move || {
-            automatic_keyer_thread(keyer_state, keyer_decoder, keyer_sink);
+            automatic_keyer_thread(keyer_state, keyer_decoder, sidetone);
         });
         
-        let midi_conn = setup_midi(state_clone, decoder_clone);
+        // Connects to the remembered port and reconnects when it is plugged back in
+        let settings = Settings::load();
+        {
+            let mut s = state.lock().unwrap();
+            let saved = |key| settings.get(key).and_then(MidiBinding::from_setting);
+            if let Some(binding) = saved(MIDI_LEFT_SETTING) {
+                s.midi_mapping.left = binding;
+            }
+            if let Some(binding) = saved(MIDI_RIGHT_SETTING) {
+                s.midi_mapping.right = binding;
+            }
+            
+            if let Some(enabled) = settings.get(KEYBOARD_PADDLES_SETTING) {
+                s.keyboard_paddles = enabled == "true";
+            }
+            if let Some(key) = settings.get(KEY_LEFT_SETTING).and_then(PaddleKey::from_name) {
+                s.key_left = key;
+            }
+            if let Some(key) = settings.get(KEY_RIGHT_SETTING).and_then(PaddleKey::from_name) {
+                s.key_right = key;
+            }
+            // One key cannot work both paddles
+            if s.key_left == s.key_right {
+                s.key_left = DEFAULT_LEFT_KEY;
+                s.key_right = DEFAULT_RIGHT_KEY;
+            }
+            if let Some(enabled) = settings.get(KEYING_PLAYBACK_SETTING) {
+                s.keying_playback = enabled == "true";
+            }
+            if let Some(address) = settings.get(RIG_ADDRESS_SETTING) {
+                s.rig_address = address.to_string();
+            }
+            for (macro_text, key) in s.rig_macros.iter_mut().zip(RIG_MACRO_SETTINGS) {
+                if let Some(text) = settings.get(key) {
+                    *macro_text = text.to_string();
+                }
+            }
+            if let Some(server) = settings.get(MOPP_SERVER_SETTING) {
+                s.mopp_server = server.to_string();
+            }
+        }
+        
+        // Transmitter keying follows everything the sidetone sends
+        let saved_ms = |key| settings.get(key).and_then(|ms: &str| ms.parse::<u32>().ok()).unwrap_or(0);
+        let keying = KeyingOutput::start(
+            KeyingChoice::from_setting(settings.get(KEYING_OUTPUT_SETTING).unwrap_or("off")),
+            settings.get(KEYING_LINE_SETTING).and_then(KeyLine::from_name).unwrap_or(KeyLine::Dtr),
+            saved_ms(PTT_LEAD_IN_SETTING),
+            saved_ms(PTT_HANG_SETTING),
+        );
+        emulator_sidetone.mirror_to(keying.clone());
+        let midi_state = Arc::clone(&state);
+        let midi_release_state = Arc::clone(&state);
+        let midi = MidiInputManager::start(
+            settings.get(MIDI_PORT_SETTING).map(str::to_string),
+            Arc::new(move |message: &[u8]| handle_midi_message(&midi_state, message)),
+            Arc::new(move || midi_release_state.lock().unwrap().release_source(PaddleSource::Midi)),
+        );
+        
+        // Paddle contacts on the CTS/DSR lines of a serial port
+        let serial_state = Arc::clone(&state);
+        let serial = SerialInputManager::start(
+            SerialChoice::from_setting(settings.get(SERIAL_PORT_SETTING).unwrap_or("off")),
+            settings.get(SERIAL_SWAP_SETTING) == Some("true"),
+            Arc::new(move |side, pressed| {
+                serial_state.lock().unwrap().set_paddle(PaddleSource::Serial, side, pressed);
+            }),
+        );
+        
+        // WinKeyer: characters sent on its paddle arrive already decoded
+        let winkeyer_state = Arc::clone(&state);
+        let winkeyer = WinkeyerHost::start(
+            WinkeyerChoice::from_setting(settings.get(WINKEYER_PORT_SETTING).unwrap_or("off")),
+            state.lock().unwrap().wpm,
+            Arc::new(move |ch| {
+                let mut state = winkeyer_state.lock().unwrap();
+                let redundant_space = ch == ' '
+                    && (state.decoded_text.is_empty() || state.decoded_text.ends_with(' '));
+                if !redundant_space {
+                    state.decoded_text.push(ch);
+                }
+            }),
+        );
+        
+        // Loggers key the sidetone through the WinKeyer emulator; the paddles break in
+        let emulator_state = Arc::clone(&state);
+        let emulator = WinkeyerEmulator::start(
+            EmulatorChoice::from_setting(settings.get(WINKEYER_EMULATOR_SETTING).unwrap_or("off")),
+            state.lock().unwrap().wpm,
+            emulator_sidetone,
+            Arc::new(move || {
+                let s = emulator_state.lock().unwrap();
+                s.left_pressed || s.right_pressed
+            }),
+        );
+        
+        // CW over CAT through rigctld; the rig's keyer speed moves the WPM slider
+        let rig_state = Arc::clone(&state);
+        let rig_decoder = Arc::clone(&decoder);
+        let rig = {
+            let s = state.lock().unwrap();
+            RigControl::start(
+                settings.get(RIG_ENABLED_SETTING) == Some("true"),
+                s.rig_address.clone(),
+                s.wpm,
+                Arc::new(move |wpm| {
+                    // The UI thread takes the decoder lock before the state lock, never hold both here
+                    let (wpm, farnsworth_wpm) = {
+                        let mut s = rig_state.lock().unwrap();
+                        s.wpm = wpm.clamp(1, 40);
+                        s.farnsworth_wpm = s.farnsworth_wpm.min(s.wpm);
+                        (s.wpm, s.farnsworth_wpm)
+                    };
+                    let mut decoder = rig_decoder.lock().unwrap();
+                    decoder.update_wpm(wpm);
+                    decoder.update_farnsworth(farnsworth_wpm);
+                }),
+            )
+        };
+        rig.set_send_decoded(settings.get(RIG_SEND_DECODED_SETTING) != Some("false"));
+        rig.set_sync_speed(settings.get(RIG_SYNC_SPEED_SETTING) != Some("false"));
+        
+        // Internet CW: everything the decoder sees goes out, received stations are mixed
+        // straight into the output so they never reach the keying output
+        let mopp = MoppClient::start(
+            settings.get(MOPP_ENABLED_SETTING) == Some("true"),
+            state.lock().unwrap().mopp_server.clone(),
+            settings.get(MOPP_CHANNEL_SETTING).and_then(|c| c.parse().ok()).unwrap_or(0),
+            stream_handle.clone(),
+        );
+        let mopp_sender = mopp.clone();
+        decoder.lock().unwrap().set_listener(Some(Arc::new(move |symbol, wpm| {
+            mopp_sender.symbol(symbol, wpm);
+        })));
+        
+        // Practice net relay for a class on the LAN
+        let relay = NetRelay::start(
+            settings.get(NET_RELAY_ENABLED_SETTING) == Some("true"),
+            settings.get(NET_RELAY_PORT_SETTING).and_then(|p| p.parse().ok()).unwrap_or(practice_net::DEFAULT_PORT),
+        );
+        
+        // CW from a receiver on the sound card
+        let audio_input = AudioInputManager::start(
+            AudioInputChoice::from_setting(settings.get(AUDIO_INPUT_SETTING).unwrap_or("off")),
+        );
         
         PaddleDecoderApp {
             state,
-            _midi_conn: midi_conn,
+            midi,
+            serial,
+            winkeyer,
+            emulator,
+            keying,
+            rig,
+            mopp,
+            relay,
+            audio_input,
+            settings,
             decoder,
             _stream: stream,
             _stream_handle: stream_handle,
             playback_sink,
+            _sidetone_sink: sidetone_sink,
         }
     }
     
@@ -384,12 +619,11 @@ impl PaddleDecoderApp {
                     .show_ui(ui, |ui| {
                         for session in get_all_sessions() {
                             if ui.selectable_value(&mut state.current_session, session, 
-                                                   get_session_description(session)).clicked() {
-                                if state.training_session_active {
-                                    let sess = get_cumulative_session(state.current_session);
-                                    if let Some(item) = sess.get_random_item(state.current_practice_type) {
-                                        state.current_training_text = item.to_string();
-                                    }
+                                                   get_session_description(session)).clicked()
+                                && state.training_session_active {
+                                let sess = get_cumulative_session(state.current_session);
+                                if let Some(item) = sess.get_random_item(state.current_practice_type) {
+                                    state.current_training_text = item.to_string();
                                 }
                             }
                         }
@@ -404,12 +638,11 @@ impl PaddleDecoderApp {
                     .show_ui(ui, |ui| {
                         for practice_type in get_practice_types() {
                             if ui.selectable_value(&mut state.current_practice_type, practice_type,
-                                                   practice_type.as_str()).clicked() {
-                                if state.training_session_active {
-                                    let session = get_cumulative_session(state.current_session);
-                                    if let Some(item) = session.get_random_item(state.current_practice_type) {
-                                        state.current_training_text = item.to_string();
-                                    }
+                                                   practice_type.as_str()).clicked()
+                                && state.training_session_active {
+                                let session = get_cumulative_session(state.current_session);
+                                if let Some(item) = session.get_random_item(state.current_practice_type) {
+                                    state.current_training_text = item.to_string();
                                 }
                             }
                         }
@@ -420,15 +653,14 @@ impl PaddleDecoderApp {
             
             // Random Blocks Mode Toggle
             ui.horizontal(|ui| {
-                if ui.checkbox(&mut state.random_blocks_mode, "🎲 Random Blocks Mode").changed() {
-                    if state.random_blocks_mode && state.training_session_active {
-                        // Generate first random block
-                        state.current_training_text = generate_random_block(
-                            state.block_from_session,
-                            state.block_to_session,
-                            state.block_size
-                        );
-                    }
+                if ui.checkbox(&mut state.random_blocks_mode, "🎲 Random Blocks Mode").changed()
+                    && state.random_blocks_mode && state.training_session_active {
+                    // Generate first random block
+                    state.current_training_text = generate_random_block(
+                        state.block_from_session,
+                        state.block_to_session,
+                        state.block_size
+                    );
                 }
             });
             
@@ -448,15 +680,14 @@ impl PaddleDecoderApp {
                                         &mut state.block_from_session,
                                         session,
                                         format!("{}", session.as_number())
-                                    ).clicked() {
-                                        if state.training_session_active {
-                                            // Regenerate block
-                                            state.current_training_text = generate_random_block(
-                                                state.block_from_session,
-                                                state.block_to_session,
-                                                state.block_size
-                                            );
-                                        }
+                                    ).clicked()
+                                        && state.training_session_active {
+                                        // Regenerate block
+                                        state.current_training_text = generate_random_block(
+                                            state.block_from_session,
+                                            state.block_to_session,
+                                            state.block_size
+                                        );
                                     }
                                 }
                             });
@@ -470,15 +701,14 @@ impl PaddleDecoderApp {
                                         &mut state.block_to_session,
                                         session,
                                         format!("{}", session.as_number())
-                                    ).clicked() {
-                                        if state.training_session_active {
-                                            // Regenerate block
-                                            state.current_training_text = generate_random_block(
-                                                state.block_from_session,
-                                                state.block_to_session,
-                                                state.block_size
-                                            );
-                                        }
+                                    ).clicked()
+                                        && state.training_session_active {
+                                        // Regenerate block
+                                        state.current_training_text = generate_random_block(
+                                            state.block_from_session,
+                                            state.block_to_session,
+                                            state.block_size
+                                        );
                                     }
                                 }
                             });
@@ -494,15 +724,14 @@ impl PaddleDecoderApp {
                                         &mut state.block_size,
                                         size,
                                         size.as_str()
-                                    ).clicked() {
-                                        if state.training_session_active {
-                                            // Regenerate block
-                                            state.current_training_text = generate_random_block(
-                                                state.block_from_session,
-                                                state.block_to_session,
-                                                state.block_size
-                                            );
-                                        }
+                                    ).clicked()
+                                        && state.training_session_active {
+                                        // Regenerate block
+                                        state.current_training_text = generate_random_block(
+                                            state.block_from_session,
+                                            state.block_to_session,
+                                            state.block_size
+                                        );
                                     }
                                 }
                             });
@@ -545,6 +774,145 @@ impl PaddleDecoderApp {
             });
         });
         
+        ui.add_space(5.0);
+        
+        // Noise, fading and interference mixed into playback (and WAV export)
+        let band_title = if state.band_conditions.is_clean() {
+            "📡 Band Conditions (clean)"
+        } else {
+            "📡 Band Conditions (on)"
+        };
+        ui.collapsing(band_title, |ui| {
+            let conditions = &mut state.band_conditions;
+            
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut conditions.noise_enabled, "Noise");
+                ui.add_enabled(conditions.noise_enabled,
+                    egui::Slider::new(&mut conditions.snr_db, -10.0..=30.0)
+                        .step_by(1.0)
+                        .text("dB SNR"));
+                egui::ComboBox::from_id_source("noise_color")
+                    .selected_text(conditions.noise_color.as_str())
+                    .show_ui(ui, |ui| {
+                        for color in get_noise_colors() {
+                            ui.selectable_value(&mut conditions.noise_color, color, color.as_str());
+                        }
+                    });
+                ui.label("ℹ").on_hover_text(
+                    "Background hiss. SNR is measured over the whole audio band,\n\
+                     so 0 dB is hard but still readable.\n\
+                     Pink noise sounds like a receiver, white noise is harsher."
+                );
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("QSB:");
+                ui.add(egui::Slider::new(&mut conditions.qsb_depth, 0.0..=1.0)
+                    .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)));
+                ui.label("ℹ").on_hover_text("Slow fading of the signal (0% = off)");
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("QRM:");
+                ui.add(egui::Slider::new(&mut conditions.qrm_strength, 0.0..=1.0)
+                    .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)));
+                ui.label("ℹ").on_hover_text(
+                    "Another station a little off frequency (0% = off).\n\
+                     100% is as loud as the signal you are copying."
+                );
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("QRN:");
+                ui.add(egui::Slider::new(&mut conditions.qrn_strength, 0.0..=1.0)
+                    .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)));
+                ui.label("ℹ").on_hover_text("Static crashes from distant lightning (0% = off)");
+            });
+        });
+        
+        // Offline listening drills: render text to a WAV file
+        ui.collapsing("💾 Export to WAV", |ui| {
+            ui.label("Text (uses the WPM, Eff. WPM, frequency and alphabet from the main window):");
+            ui.add(egui::TextEdit::multiline(&mut state.export_text)
+                .desired_rows(3)
+                .desired_width(f32::INFINITY));
+            
+            ui.horizontal(|ui| {
+                if ui.button("🎲 Fill with 20 items").on_hover_text(
+                    "Generate items from the session or random block settings above"
+                ).clicked() {
+                    let items: Vec<String> = (0..20)
+                        .map(|_| Self::generate_training_item(&state))
+                        .collect();
+                    state.export_text = items.join(" ");
+                }
+                if ui.button("Use current item").clicked() {
+                    state.export_text = state.current_training_text.clone();
+                }
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut state.export_path);
+                
+                if ui.button("💾 Export").clicked() {
+                    let player = Self::make_player(&state);
+                    
+                    let path = std::path::PathBuf::from(state.export_path.trim());
+                    state.export_status = if state.export_text.trim().is_empty() {
+                        "Nothing to export - enter some text first".to_string()
+                    } else {
+                        match player.export_wav(&path, state.export_text.trim()) {
+                            Ok(()) => format!("✓ Saved {}", path.display()),
+                            Err(e) => {
+                                eprintln!("Failed to write {}: {}", path.display(), e);
+                                format!("✗ Failed to write {}: {}", path.display(), e)
+                            }
+                        }
+                    };
+                }
+            });
+            
+            if !state.export_status.is_empty() {
+                ui.label(egui::RichText::new(&state.export_status)
+                    .size(12.0)
+                    .color(egui::Color32::LIGHT_GRAY));
+            }
+        });
+        
+        // Text keyed by a WinKeyer on its own transmitter output and sidetone
+        ui.collapsing("⌨ WinKeyer", |ui| {
+            let connected = self.winkeyer.is_connected();
+            if !connected {
+                ui.label(egui::RichText::new("Choose a WinKeyer port in the main window first")
+                    .size(12.0)
+                    .color(egui::Color32::LIGHT_GRAY));
+            }
+            
+            ui.add_enabled_ui(connected, |ui| {
+                ui.checkbox(&mut state.winkeyer_playback, "Key \"Play Morse Code\" on the WinKeyer")
+                    .on_hover_text("Listening drills are sent by the WinKeyer at the main window WPM");
+                
+                ui.add(egui::TextEdit::multiline(&mut state.winkeyer_text)
+                    .desired_rows(2)
+                    .desired_width(f32::INFINITY));
+                ui.horizontal(|ui| {
+                    if ui.button("📤 Send").clicked() {
+                        self.winkeyer.send_text(&state.winkeyer_text);
+                    }
+                    if ui.button("Use current item").clicked() {
+                        state.winkeyer_text = state.current_training_text.clone();
+                    }
+                    if ui.button("⏹ Stop").clicked() {
+                        self.winkeyer.clear();
+                    }
+                    if self.winkeyer.is_busy() {
+                        ui.label("Sending...");
+                    }
+                });
+            });
+        });
+        
         ui.add_space(10.0);
         ui.separator();
         ui.add_space(10.0);
@@ -805,10 +1173,6 @@ impl PaddleDecoderApp {
                         
                         ui.add_space(5.0);
                         
-                        let sink_clone = Arc::clone(&self.playback_sink);
-                        let wpm = state.wpm;
-                        let farnsworth = state.farnsworth_wpm;
-                        let freq = state.frequency;
                         let training_text = state.current_training_text.clone();
                         
                         if ui.button(egui::RichText::new("▶ Play Morse Code")
@@ -827,12 +1191,7 @@ impl PaddleDecoderApp {
                             }
                             
                             // Play morse in background thread
-                            drop(state); // Release lock before spawning thread
-                            thread::spawn(move || {
-                                let player = MorsePlayer::new_with_farnsworth(freq as f32, wpm, farnsworth);
-                                let sink = sink_clone.lock().unwrap();
-                                player.play_morse(&sink, &training_text);
-                            });
+                            self.start_playback(&state, training_text, Duration::ZERO);
                             return;
                         }
                         
@@ -878,24 +1237,14 @@ impl PaddleDecoderApp {
                                         }
                                     } else {
                                         // Play again
-                                        let sink_clone = Arc::clone(&self.playback_sink);
                                         let training_text = state.current_training_text.clone();
-                                        let wpm_local = wpm;
-                                        let farnsworth_local = farnsworth;
-                                        let freq_local = freq;
                                         
                                         // Restart timeout timer
                                         if state.timeout_enabled {
                                             state.timeout_start = Some(Instant::now());
                                         }
                                         
-                                        drop(state); // Release lock before spawning thread
-                                        thread::spawn(move || {
-                                            thread::sleep(Duration::from_millis(1000));
-                                            let player = MorsePlayer::new_with_farnsworth(freq_local as f32, wpm_local, farnsworth_local);
-                                            let sink = sink_clone.lock().unwrap();
-                                            player.play_morse(&sink, &training_text);
-                                        });
+                                        self.start_playback(&state, training_text, Duration::from_millis(1000));
                                         return;
                                     }
                                 }
@@ -922,18 +1271,7 @@ impl PaddleDecoderApp {
                                     state.correct_answer = next_item.clone();
                                     state.timeout_start = Some(Instant::now());
                                     
-                                    let sink_clone = Arc::clone(&self.playback_sink);
-                                    let wpm_local = state.wpm;
-                                    let farnsworth_local = state.farnsworth_wpm;
-                                    let freq_local = state.frequency;
-                                    
-                                    drop(state); // Release lock before spawning thread
-                                    thread::spawn(move || {
-                                        let player = MorsePlayer::new_with_farnsworth(freq_local as f32, wpm_local, farnsworth_local);
-                                        let sink = sink_clone.lock().unwrap();
-                                        player.play_morse(&sink, &next_item);
-                                    });
-                                    return;
+                                    self.start_playback(&state, next_item, Duration::ZERO);
                                 }
                             }
                         }
@@ -951,14 +1289,26 @@ impl PaddleDecoderApp {
 
 impl eframe::App for PaddleDecoderApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.update_keyboard_paddles(ctx);
+        
         if let Some(decoded_str) = self.decoder.lock().unwrap().check_timeout() {
-            self.state.lock().unwrap().decoded_text.push_str(&decoded_str);
+            let mut state = self.state.lock().unwrap();
+            // No automatic space at the start or after a manual "Add Space"
+            let redundant_space = decoded_str == " "
+                && (state.decoded_text.is_empty() || state.decoded_text.ends_with(' '));
+            if !redundant_space {
+                state.decoded_text.push_str(&decoded_str);
+                self.rig.decoded(&decoded_str);
+            }
         }
         
         {
             let decoder = self.decoder.lock().unwrap();
             let mut state = self.state.lock().unwrap();
             state.current_sequence = decoder.current_sequence.clone();
+            self.mopp.set_tone(state.frequency as f32, state.envelope_ms);
+            self.mopp.set_decoding(state.alphabet, state.prefer_prosigns);
+            self.audio_input.set_decoding(state.alphabet, state.prefer_prosigns);
             
             // Check timeout in listening mode
             if state.listening_mode && state.timeout_enabled {
@@ -977,19 +1327,8 @@ impl eframe::App for PaddleDecoderApp {
                         state.result_display_start = None;
                         
                         // Auto-play the next item
-                        let sink_clone = Arc::clone(&self.playback_sink);
-                        let wpm = state.wpm;
-                        let farnsworth = state.farnsworth_wpm;
-                        let freq = state.frequency;
                         let training_text = state.current_training_text.clone();
-                        
-                        drop(state); // Release lock before spawning thread
-                        
-                        thread::spawn(move || {
-                            let player = MorsePlayer::new_with_farnsworth(freq as f32, wpm, farnsworth);
-                            let sink = sink_clone.lock().unwrap();
-                            player.play_morse(&sink, &training_text);
-                        });
+                        self.start_playback(&state, training_text, Duration::ZERO);
                         return; // Exit early to avoid borrowing issues
                     }
                 }
@@ -1049,67 +1388,650 @@ impl eframe::App for PaddleDecoderApp {
             ui.separator();
             ui.add_space(10.0);
             
-            let mut state = self.state.lock().unwrap();
-            let mut decoder = self.decoder.lock().unwrap();
-            
+            // MIDI paddle interface
             ui.horizontal(|ui| {
-                ui.label("WPM:");
-                if ui.add(egui::Slider::new(&mut state.wpm, 1..=40)
-                    .text("WPM")).changed() {
-                    decoder.update_wpm(state.wpm);
-                    // Ensure Farnsworth WPM doesn't exceed character WPM
-                    if state.farnsworth_wpm > state.wpm {
-                        state.farnsworth_wpm = state.wpm;
-                    }
+                ui.label("MIDI input:");
+                let preferred = self.midi.preferred_port();
+                let mut selected = preferred.clone();
+                let picker = egui::ComboBox::from_id_source("midi_port")
+                    .selected_text(selected.as_deref().unwrap_or("Auto"))
+                    .width(220.0)
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut selected, None, "Auto");
+                        let mut ports = self.midi.ports();
+                        // Keep showing the remembered port while it is unplugged
+                        if let Some(port) = preferred.as_ref().filter(|port| !ports.contains(port)) {
+                            ports.push(port.clone());
+                        }
+                        for port in ports {
+                            ui.selectable_value(&mut selected, Some(port.clone()), port);
+                        }
+                    });
+                // The list is only kept up to date while disconnected
+                if picker.response.clicked() {
+                    self.midi.rescan();
+                }
+                if selected != preferred {
+                    self.midi.set_preferred_port(selected.clone());
+                    self.settings.set(MIDI_PORT_SETTING, selected.as_deref());
                 }
+                
+                let status = self.midi.status();
+                let color = if status.is_connected() {
+                    egui::Color32::GREEN
+                } else {
+                    egui::Color32::from_rgb(255, 100, 100)
+                };
+                ui.label(egui::RichText::new(status.describe()).color(color));
+                ui.label("ℹ").on_hover_text(
+                    "Auto picks a MidiStomp / MIDI port, else the first one.\n\
+                     The choice is remembered. Unplug and replug the paddle\n\
+                     interface at any time - it reconnects by itself."
+                );
             });
             
+            // Serial paddle interface (CTS/DSR)
             ui.horizontal(|ui| {
-                ui.label("Farnsworth WPM:");
-                let max_farnsworth = state.wpm;
-                ui.add(egui::Slider::new(&mut state.farnsworth_wpm, 1..=max_farnsworth)
-                    .text("Eff. WPM"));
+                ui.label("Serial input:");
+                let current = self.serial.choice();
+                let mut selected = current.clone();
+                egui::ComboBox::from_id_source("serial_port")
+                    .selected_text(selected.as_str())
+                    .width(220.0)
+                    .show_ui(ui, |ui| {
+                        let mut choices = get_serial_choices(&self.serial.ports());
+                        // Keep showing the remembered port while it is unplugged
+                        if !choices.contains(&current) {
+                            choices.push(current.clone());
+                        }
+                        for choice in choices {
+                            let text = choice.as_str().to_string();
+                            ui.selectable_value(&mut selected, choice, text);
+                        }
+                    });
+                if selected != current {
+                    self.serial.set_choice(selected.clone());
+                    self.settings.set(SERIAL_PORT_SETTING, Some(&selected.to_setting()));
+                }
+                
+                let mut swap = self.serial.swap();
+                if ui.checkbox(&mut swap, "Swap").changed() {
+                    self.serial.set_swap(swap);
+                    self.settings.set(SERIAL_SWAP_SETTING, Some(&swap.to_string()));
+                }
+                
+                let status = self.serial.status();
+                if selected != SerialChoice::Off {
+                    let color = if status.is_connected() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
                 ui.label("ℹ").on_hover_text(
-                    "Effective WPM with extended spacing.\n\
-                     Characters sent at full WPM, but with extra spacing.\n\
-                     Lower = more time to think between characters."
+                    "Paddles wired to a USB-serial adapter: DTR and RTS are raised,\n\
+                     DSR = LEFT paddle, CTS = RIGHT paddle (Swap exchanges them).\n\
+                     Pseudo-terminal (test): a stand-in without hardware - write\n\
+                     D/d (DSR on/off) and C/c (CTS on/off) to the pty shown."
                 );
             });
             
+            // WinKeyer on a serial port
             ui.horizontal(|ui| {
-                ui.label("Frequency:");
-                ui.add(egui::Slider::new(&mut state.frequency, 300..=1000)
-                    .step_by(50.0)
-                    .text("Hz"));
+                ui.label("WinKeyer:");
+                let current = self.winkeyer.choice();
+                let mut selected = current.clone();
+                egui::ComboBox::from_id_source("winkeyer_port")
+                    .selected_text(selected.as_str())
+                    .width(220.0)
+                    .show_ui(ui, |ui| {
+                        let mut choices = get_winkeyer_choices(&self.serial.ports());
+                        if !choices.contains(&current) {
+                            choices.push(current.clone());
+                        }
+                        for choice in choices {
+                            let text = choice.as_str().to_string();
+                            ui.selectable_value(&mut selected, choice, text);
+                        }
+                    });
+                if selected != current {
+                    self.winkeyer.set_choice(selected.clone());
+                    self.settings.set(WINKEYER_PORT_SETTING, Some(&selected.to_setting()));
+                }
+                
+                let status = self.winkeyer.status();
+                if selected != WinkeyerChoice::Off {
+                    let color = if status.is_connected() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "K1EL WinKeyer: characters sent on its paddle appear in the\n\
+                     decoded text, its speed follows the WPM slider and the Training\n\
+                     window can send text for it to key.\n\
+                     Fake WinKeyer (test): a stand-in without hardware - write\n\
+                     text to the paddle pty shown to \"send\" it on the paddle."
+                );
             });
             
-            ui.add_space(10.0);
-            ui.separator();
-            ui.add_space(10.0);
-            
-            ui.heading("Paddle Status:");
+            // WinKeyer emulation for logging software
             ui.horizontal(|ui| {
-                let left_color = if state.left_pressed {
-                    egui::Color32::RED
-                } else {
-                    egui::Color32::DARK_GRAY
-                };
+                ui.label("Logger port:");
+                let current = self.emulator.choice();
+                let mut selected = current.clone();
+                egui::ComboBox::from_id_source("winkeyer_emulator")
+                    .selected_text(selected.as_str())
+                    .width(220.0)
+                    .show_ui(ui, |ui| {
+                        let mut choices = get_emulator_choices(&self.serial.ports());
+                        if !choices.contains(&current) {
+                            choices.push(current.clone());
+                        }
+                        for choice in choices {
+                            let text = choice.as_str().to_string();
+                            ui.selectable_value(&mut selected, choice, text);
+                        }
+                    });
+                if selected != current {
+                    self.emulator.set_choice(selected.clone());
+                    self.settings.set(WINKEYER_EMULATOR_SETTING, Some(&selected.to_setting()));
+                }
                 
-                let right_color = if state.right_pressed {
-                    egui::Color32::RED
-                } else {
-                    egui::Color32::DARK_GRAY
-                };
+                let status = self.emulator.status();
+                if selected != EmulatorChoice::Off {
+                    let color = if status.is_host_open() {
+                        egui::Color32::GREEN
+                    } else if status.is_error() {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    } else {
+                        egui::Color32::YELLOW
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "WinKeyer emulation: set your logger (N1MM, fldigi, ...) to a\n\
+                     WinKeyer on this port and its CW is keyed on the sidetone.\n\
+                     The WPM slider is the speed pot; touching a paddle stops the\n\
+                     logger's text. Virtual port (pty): use the path shown.\n\
+                     Windows: pick one end of a com0com pair, the logger the other."
+                );
+            });
+            
+            // Transmitter keying on a serial line or MIDI out
+            ui.horizontal(|ui| {
+                ui.label("Keying output:");
+                let current = self.keying.choice();
+                let mut selected = current.clone();
+                egui::ComboBox::from_id_source("keying_output")
+                    .selected_text(selected.describe())
+                    .width(220.0)
+                    .show_ui(ui, |ui| {
+                        let mut choices = self.keying.choices();
+                        if !choices.contains(&current) {
+                            choices.push(current.clone());
+                        }
+                        for choice in choices {
+                            let text = choice.describe();
+                            ui.selectable_value(&mut selected, choice, text);
+                        }
+                    });
+                if selected != current {
+                    self.keying.set_choice(selected.clone());
+                    self.settings.set(KEYING_OUTPUT_SETTING, Some(&selected.to_setting()));
+                }
                 
-                ui.label(egui::RichText::new("LEFT (Dah)")
-                    .size(20.0)
-                    .color(left_color));
+                if matches!(selected, KeyingChoice::Serial(_)) {
+                    let mut key_line = self.keying.key_line();
+                    egui::ComboBox::from_id_source("keying_line")
+                        .selected_text(format!("Key on {}", key_line.as_str()))
+                        .width(90.0)
+                        .show_ui(ui, |ui| {
+                            for line in get_key_lines() {
+                                ui.selectable_value(&mut key_line, line, line.as_str());
+                            }
+                        });
+                    if key_line != self.keying.key_line() {
+                        self.keying.set_key_line(key_line);
+                        self.settings.set(KEYING_LINE_SETTING, Some(key_line.as_str()));
+                    }
+                }
                 
-                ui.add_space(20.0);
+                let status = self.keying.status();
+                if selected != KeyingChoice::Off {
+                    let color = if status.is_connected() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Keys a transmitter with everything the sidetone sends (paddles,\n\
+                     straight key, logger CW) and the Training playback.\n\
+                     Serial: the key line through a keying interface, PTT on the other line.\n\
+                     MIDI: note 1 = key, note 2 = PTT, channel 1.\n\
+                     Check your interface with a dummy load before going on the air."
+                );
+            });
+            
+            if self.keying.choice() != KeyingChoice::Off {
+                ui.horizontal(|ui| {
+                    let (mut lead_in, mut hang) = self.keying.ptt_times();
+                    ui.label("PTT lead-in:");
+                    let lead_changed = ui.add(egui::Slider::new(&mut lead_in, 0..=MAX_LEAD_IN_MS)
+                        .suffix(" ms")).changed();
+                    ui.label("hang:");
+                    let hang_changed = ui.add(egui::Slider::new(&mut hang, 0..=MAX_HANG_MS)
+                        .suffix(" ms")).changed();
+                    if lead_changed || hang_changed {
+                        self.keying.set_ptt_times(lead_in, hang);
+                        self.settings.set(PTT_LEAD_IN_SETTING, Some(&lead_in.to_string()));
+                        self.settings.set(PTT_HANG_SETTING, Some(&hang.to_string()));
+                    }
+                    
+                    let mut state = self.state.lock().unwrap();
+                    if ui.checkbox(&mut state.keying_playback, "Key Training playback").changed() {
+                        self.settings.set(KEYING_PLAYBACK_SETTING, Some(&state.keying_playback.to_string()));
+                    }
+                    ui.label("ℹ").on_hover_text(
+                        "PTT goes on this long before the first element and off this\n\
+                         long after the last one. Both at 0: PTT is not used (QSK).\n\
+                         Training playback: the listening drills are sent on the air too."
+                    );
+                });
+            }
+            
+            // Hamlib rigctld: CW text over CAT
+            ui.horizontal(|ui| {
+                let mut enabled = self.rig.enabled();
+                if ui.checkbox(&mut enabled, "Rig (rigctld):").changed() {
+                    self.rig.set_enabled(enabled);
+                    self.settings.set(RIG_ENABLED_SETTING, Some(&enabled.to_string()));
+                }
+                
+                let mut state = self.state.lock().unwrap();
+                let response = ui.add(egui::TextEdit::singleline(&mut state.rig_address)
+                    .desired_width(140.0));
+                // Connect to a new address once editing is finished
+                if response.lost_focus() && state.rig_address.trim() != self.rig.address() {
+                    self.rig.set_address(&state.rig_address);
+                    self.settings.set(RIG_ADDRESS_SETTING, Some(state.rig_address.trim()));
+                }
+                drop(state);
+                
+                let status = self.rig.status();
+                if enabled {
+                    let color = if status.is_connected() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Send CW through the rig's own keyer over CAT, via Hamlib's rigctld\n\
+                     (e.g. `rigctld -m <model> -r <port>`, default port 4532).\n\
+                     Without a rig, `rigctld -m 1` is Hamlib's dummy rig."
+                );
+            });
+            
+            if self.rig.enabled() {
+                ui.horizontal(|ui| {
+                    let mut send_decoded = self.rig.send_decoded();
+                    if ui.checkbox(&mut send_decoded, "Send decoded text").on_hover_text(
+                        "Each decoded word is sent to the rig once its word space is seen"
+                    ).changed() {
+                        self.rig.set_send_decoded(send_decoded);
+                        self.settings.set(RIG_SEND_DECODED_SETTING, Some(&send_decoded.to_string()));
+                    }
+                    let mut sync_speed = self.rig.sync_speed();
+                    if ui.checkbox(&mut sync_speed, "Sync speed").on_hover_text(
+                        "The WPM slider and the rig's keyer speed (KEYSPD) follow each other;\n\
+                         on connecting the slider takes the rig's speed"
+                    ).changed() {
+                        self.rig.set_sync_speed(sync_speed);
+                        self.settings.set(RIG_SYNC_SPEED_SETTING, Some(&sync_speed.to_string()));
+                    }
+                });
                 
-                ui.label(egui::RichText::new("RIGHT (Dit)")
-                    .size(20.0)
-                    .color(right_color));
+                ui.collapsing("📻 Rig macros", |ui| {
+                    let mut state = self.state.lock().unwrap();
+                    let connected = self.rig.status().is_connected();
+                    
+                    ui.horizontal(|ui| {
+                        let response = ui.add(egui::TextEdit::singleline(&mut state.rig_text)
+                            .hint_text("Text to send")
+                            .desired_width(220.0));
+                        let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if ui.add_enabled(connected, egui::Button::new("📤 Send")).clicked()
+                            || (connected && entered) {
+                            self.rig.send_text(&state.rig_text);
+                            state.rig_text.clear();
+                        }
+                    });
+                    
+                    ui.horizontal(|ui| {
+                        for text in &state.rig_macros {
+                            if ui.add_enabled(connected, egui::Button::new(text)).clicked() {
+                                self.rig.send_text(text);
+                            }
+                        }
+                    });
+                    
+                    ui.collapsing("Edit macros", |ui| {
+                        for (i, (text, key)) in state.rig_macros.iter_mut().zip(RIG_MACRO_SETTINGS).enumerate() {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("Macro {}:", i + 1));
+                                if ui.text_edit_singleline(text).lost_focus() {
+                                    self.settings.set(key, Some(text));
+                                }
+                            });
+                        }
+                    });
+                });
+            }
+            
+            // Internet CW: keying goes to a MOPP server, other stations play on the sidetone
+            ui.horizontal(|ui| {
+                let mut enabled = self.mopp.enabled();
+                if ui.checkbox(&mut enabled, "Internet CW (MOPP):").changed() {
+                    self.mopp.set_enabled(enabled);
+                    self.settings.set(MOPP_ENABLED_SETTING, Some(&enabled.to_string()));
+                }
+                
+                let mut state = self.state.lock().unwrap();
+                let response = ui.add(egui::TextEdit::singleline(&mut state.mopp_server)
+                    .desired_width(140.0));
+                // Join the new server once editing is finished
+                if response.lost_focus() && state.mopp_server.trim() != self.mopp.server() {
+                    self.mopp.set_server(&state.mopp_server);
+                    self.settings.set(MOPP_SERVER_SETTING, Some(state.mopp_server.trim()));
+                }
+                drop(state);
+                
+                ui.label("channel:");
+                let mut channel = self.mopp.channel();
+                if ui.add(egui::DragValue::new(&mut channel).clamp_range(0..=MAX_CHANNEL)).changed() {
+                    self.mopp.set_channel(channel);
+                    self.settings.set(MOPP_CHANNEL_SETTING, Some(&channel.to_string()));
+                }
+                
+                let status = self.mopp.status();
+                if enabled {
+                    let color = if status.is_connected() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Send your keying to a MOPP server (Morserino-32 protocol, UDP port 7373)\n\
+                     and hear the other stations at their own speed. Channel 0 joins a plain\n\
+                     MOPP server; other channels pick a net on a relay that has several.\n\
+                     UDP has no connection - a refused port shows as an error once you key."
+                );
+            });
+            
+            if self.mopp.enabled() {
+                let stations = self.mopp.stations();
+                ui.collapsing(format!("📡 Stations ({})", stations.len()), |ui| {
+                    if stations.is_empty() {
+                        ui.label(egui::RichText::new("Nobody heard yet").italics());
+                    }
+                    // One pane per station, decoded on its own
+                    for station in &stations {
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new(station.label()).strong());
+                            ui.label(format!("{:.0} Hz", station.frequency));
+                        });
+                        egui::ScrollArea::vertical()
+                            .id_source(("mopp_station", station.number))
+                            .max_height(50.0)
+                            .stick_to_bottom(true)
+                            .show(ui, |ui| {
+                                ui.label(egui::RichText::new(&station.text)
+                                    .monospace()
+                                    .color(egui::Color32::LIGHT_BLUE));
+                            });
+                    }
+                    if !stations.is_empty() && ui.small_button("Clear").clicked() {
+                        self.mopp.clear_received();
+                    }
+                });
+            }
+            
+            // Practice net: relay the keying of every station on the LAN to the others
+            ui.horizontal(|ui| {
+                let mut enabled = self.relay.enabled();
+                if ui.checkbox(&mut enabled, "Host practice net:").changed() {
+                    self.relay.set_enabled(enabled);
+                    self.settings.set(NET_RELAY_ENABLED_SETTING, Some(&enabled.to_string()));
+                }
+                
+                ui.label("port:");
+                let mut port = self.relay.port();
+                if ui.add(egui::DragValue::new(&mut port).clamp_range(1024..=65535)).changed() {
+                    self.relay.set_port(port);
+                    self.settings.set(NET_RELAY_PORT_SETTING, Some(&port.to_string()));
+                }
+                
+                let status = self.relay.status();
+                if enabled {
+                    let color = if status.is_running() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Run a practice net for a class: students set Internet CW to this\n\
+                     machine's address and this port, and each one hears everybody else\n\
+                     at a pitch of their own. To key along, join it yourself at localhost.\n\
+                     Stations on different channels do not hear each other."
+                );
+            });
+            
+            if self.relay.status().is_running() {
+                let stations = self.relay.stations();
+                if !stations.is_empty() {
+                    ui.collapsing("🏫 Joined stations", |ui| {
+                        for station in &stations {
+                            ui.label(format!("Station {}: {} (channel {})",
+                                station.number, station.address, station.channel));
+                        }
+                    });
+                }
+            }
+            
+            let mut state = self.state.lock().unwrap();
+            let mut decoder = self.decoder.lock().unwrap();
+            
+            ui.collapsing("🎛 MIDI mapping", |ui| {
+                midi_binding_row(ui, &mut state, PaddleSide::Left, "LEFT paddle: ");
+                midi_binding_row(ui, &mut state, PaddleSide::Right, "RIGHT paddle:");
+                ui.horizontal(|ui| {
+                    if ui.button("Reset to default").clicked() {
+                        state.midi_mapping = MidiMapping::default();
+                        state.midi_mapping_changed = true;
+                    }
+                    ui.label("ℹ").on_hover_text(
+                        "Note: pressed on Note On, released on Note Off (or velocity 0).\n\
+                         CC: pressed at value 64 and above, like a sustain pedal.\n\
+                         Learn: click, then press the paddle - the message and its\n\
+                         channel are recorded. Default: note 1 / note 2, any channel."
+                    );
+                });
+            });
+            
+            // Save bindings edited here or learned from the MIDI thread
+            if std::mem::take(&mut state.midi_mapping_changed) {
+                self.settings.set(MIDI_LEFT_SETTING, Some(&state.midi_mapping.left.to_setting()));
+                self.settings.set(MIDI_RIGHT_SETTING, Some(&state.midi_mapping.right.to_setting()));
+            }
+            
+            ui.horizontal(|ui| {
+                ui.label("WPM:");
+                if ui.add(egui::Slider::new(&mut state.wpm, 1..=40)
+                    .text("WPM")).changed() {
+                    decoder.update_wpm(state.wpm);
+                    // Ensure Farnsworth WPM doesn't exceed character WPM
+                    if state.farnsworth_wpm > state.wpm {
+                        state.farnsworth_wpm = state.wpm;
+                    }
+                    decoder.update_farnsworth(state.farnsworth_wpm);
+                }
+                // Sent to the WinKeyer only when it changes; the emulator's speed pot
+                self.winkeyer.set_speed(state.wpm);
+                self.emulator.set_speed_pot(state.wpm);
+                self.rig.set_speed(state.wpm);
+                
+                ui.add_space(10.0);
+                ui.label("Keyer:");
+                egui::ComboBox::from_id_source("keyer_mode")
+                    .selected_text(state.keyer_mode.as_str())
+                    .show_ui(ui, |ui| {
+                        for mode in get_keyer_modes() {
+                            if ui.selectable_value(&mut state.keyer_mode, mode, mode.as_str()).changed() {
+                                // Timing learned from the previous input style no longer applies
+                                decoder.reset_speed_tracking();
+                            }
+                        }
+                    });
+                ui.label("ℹ").on_hover_text(
+                    "Simple: alternate while squeezed, stop on release.\n\
+                     Iambic A: finish the current element, nothing extra on release.\n\
+                     Iambic B: releasing a squeeze adds one opposite element.\n\
+                     Straight Key: either contact keys the tone directly.\n\
+                     Bug: LEFT makes automatic dits, RIGHT is a manual dah contact."
+                );
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("Paddle memory:");
+                ui.checkbox(&mut state.dit_memory, "Dit");
+                ui.checkbox(&mut state.dah_memory, "Dah");
+                ui.label("ℹ").on_hover_text(
+                    "Remember a tap on the opposite paddle while an element\n\
+                     is sounding and send it next, even if already released."
+                );
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("Farnsworth WPM:");
+                let max_farnsworth = state.wpm;
+                if ui.add(egui::Slider::new(&mut state.farnsworth_wpm, 1..=max_farnsworth)
+                    .text("Eff. WPM")).changed() {
+                    decoder.update_farnsworth(state.farnsworth_wpm);
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Effective WPM with extended spacing.\n\
+                     Characters sent at full WPM, but with extra spacing.\n\
+                     Lower = more time to think between characters."
+                );
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("Frequency:");
+                ui.add(egui::Slider::new(&mut state.frequency, 300..=1000)
+                    .step_by(50.0)
+                    .text("Hz"));
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("Tone edges:");
+                ui.add(egui::Slider::new(&mut state.envelope_ms, MIN_EDGE_MS..=MAX_EDGE_MS)
+                    .text("ms"));
+                ui.label("ℹ").on_hover_text(
+                    "Rise and fall time of the sidetone and playback tones.\n\
+                     Shaped edges avoid key clicks, like a real transceiver.\n\
+                     Shorter = crisper, longer = softer."
+                );
+            });
+            
+            ui.horizontal(|ui| {
+                ui.label("Alphabet:");
+                egui::ComboBox::from_id_source("alphabet")
+                    .selected_text(state.alphabet.as_str())
+                    .show_ui(ui, |ui| {
+                        for alphabet in get_alphabets() {
+                            if ui.selectable_value(&mut state.alphabet, alphabet, alphabet.as_str()).changed() {
+                                decoder.set_alphabet(state.alphabet);
+                            }
+                        }
+                    });
+                ui.label("ℹ").on_hover_text(
+                    "Letters used for decoding and playback.\n\
+                     Cyrillic, Greek and Wabun reuse the Latin codes for their own letters.\n\
+                     Wabun starts in kana; <DO> switches to kana, <SN> back to Latin."
+                );
+            });
+            
+            ui.add_space(10.0);
+            ui.separator();
+            ui.add_space(10.0);
+            
+            ui.heading("Paddle Status:");
+            // The indicators double as on-screen paddles: hold them down with the mouse
+            ui.horizontal(|ui| {
+                for (side, text) in [(PaddleSide::Left, "LEFT (Dah)"), (PaddleSide::Right, "RIGHT (Dit)")] {
+                    let pressed = if side.is_left() { state.left_pressed } else { state.right_pressed };
+                    let color = if pressed {
+                        egui::Color32::RED
+                    } else {
+                        egui::Color32::DARK_GRAY
+                    };
+                    
+                    let response = ui.add(egui::Button::new(egui::RichText::new(text)
+                            .size(20.0)
+                            .color(color))
+                        .min_size(egui::vec2(160.0, 50.0)));
+                    // A click shorter than a frame still counts as a tap
+                    if response.clicked() {
+                        state.set_paddle(PaddleSource::Mouse, side, true);
+                    }
+                    state.set_paddle(PaddleSource::Mouse, side, response.is_pointer_button_down_on());
+                    
+                    ui.add_space(20.0);
+                }
+            });
+            
+            ui.horizontal(|ui| {
+                let before = (state.keyboard_paddles, state.key_left, state.key_right);
+                
+                ui.checkbox(&mut state.keyboard_paddles, "Keyboard paddles:");
+                for (side, id) in [(PaddleSide::Left, "key_left"), (PaddleSide::Right, "key_right")] {
+                    ui.label(if side.is_left() { "LEFT" } else { "RIGHT" });
+                    let other = if side.is_left() { state.key_right } else { state.key_left };
+                    let key = if side.is_left() { &mut state.key_left } else { &mut state.key_right };
+                    egui::ComboBox::from_id_source(id)
+                        .selected_text(key.as_str())
+                        .width(90.0)
+                        .show_ui(ui, |ui| {
+                            // The other paddle's key is not offered
+                            for choice in get_paddle_keys().into_iter().filter(|choice| *choice != other) {
+                                ui.selectable_value(key, choice, choice.as_str());
+                            }
+                        });
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Key the paddles from the computer keyboard, or hold the\n\
+                     LEFT / RIGHT buttons above with the mouse.\n\
+                     Keys are ignored while typing into a text field.\n\
+                     Left/Right Ctrl and [ / ] cannot be told apart here:\n\
+                     Ctrl, Shift and Alt each work for one paddle only."
+                );
+                
+                if (state.keyboard_paddles, state.key_left, state.key_right) != before {
+                    self.settings.set(KEYBOARD_PADDLES_SETTING, Some(&state.keyboard_paddles.to_string()));
+                    self.settings.set(KEY_LEFT_SETTING, Some(state.key_left.as_str()));
+                    self.settings.set(KEY_RIGHT_SETTING, Some(state.key_right.as_str()));
+                }
             });
             
             ui.add_space(10.0);
@@ -1126,27 +2048,198 @@ impl eframe::App for PaddleDecoderApp {
             ui.separator();
             ui.add_space(10.0);
             
-            ui.heading("Decoded Text:");
-            
-            egui::ScrollArea::vertical()
-                .max_height(150.0)
-                .show(ui, |ui| {
-                    ui.label(egui::RichText::new(&state.decoded_text)
-                        .size(18.0)
-                        .monospace()
-                        .color(egui::Color32::LIGHT_GREEN));
-                });
+            // Sound card CW gets a panel of its own beside the keyed text
+            let audio_text = (self.audio_input.choice() != AudioInputChoice::Off).then(|| self.audio_input.text());
+            ui.columns(if audio_text.is_some() { 2 } else { 1 }, |columns| {
+                columns[0].heading("Decoded Text:");
+                egui::ScrollArea::vertical()
+                    .max_height(150.0)
+                    .show(&mut columns[0], |ui| {
+                        ui.label(egui::RichText::new(&state.decoded_text)
+                            .size(18.0)
+                            .monospace()
+                            .color(egui::Color32::LIGHT_GREEN));
+                    });
+                
+                if let Some(text) = &audio_text {
+                    columns[1].horizontal(|ui| {
+                        ui.heading("🎧 Audio Decoded:");
+                        if ui.small_button("Clear").clicked() {
+                            self.audio_input.clear_text();
+                        }
+                    });
+                    egui::ScrollArea::vertical()
+                        .id_source("audio_decoded_text")
+                        .max_height(150.0)
+                        .stick_to_bottom(true)
+                        .show(&mut columns[1], |ui| {
+                            ui.label(egui::RichText::new(text)
+                                .size(18.0)
+                                .monospace()
+                                .color(egui::Color32::LIGHT_YELLOW));
+                        });
+                }
+            });
             
             ui.add_space(10.0);
             
             ui.horizontal(|ui| {
                 if ui.button("Add Space").clicked() {
                     state.decoded_text.push(' ');
+                    self.rig.decoded(" ");
+                    self.mopp.symbol(Symbol::WordSpace, state.wpm);
                 }
                 
                 if ui.button("Clear Text").clicked() {
                     state.decoded_text.clear();
                 }
+                
+                ui.add_space(10.0);
+                let mut word_space_changed = ui.checkbox(&mut state.auto_word_space, "Auto word space").changed();
+                if state.auto_word_space {
+                    word_space_changed |= ui.add(egui::Slider::new(&mut state.word_space_dits, 4.0..=14.0)
+                        .step_by(0.5)
+                        .text("dits")).changed();
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Insert a space when the pause after a letter is longer than\n\
+                     this many dits (standard word gap: 7). Farnsworth spacing\n\
+                     stretches the gap the same way it stretches playback."
+                );
+                if word_space_changed {
+                    decoder.set_word_space(state.auto_word_space, state.word_space_dits);
+                }
+            });
+            
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut state.prefer_prosigns, "Decode prosigns").changed() {
+                    decoder.set_prefer_prosigns(state.prefer_prosigns);
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Prosigns share their code with a character:\n\
+                     <BT> = '=', <AR> = '+', <KN> = '(', <AS> = '&'.\n\
+                     When checked, the prosign is shown instead of the character\n\
+                     (useful for CW Academy sessions 8-10)."
+                );
+            });
+            
+            // Receiver audio on the sound card
+            ui.horizontal(|ui| {
+                ui.label("Audio input:");
+                let current = self.audio_input.choice();
+                let mut selected = current.clone();
+                egui::ComboBox::from_id_source("audio_input")
+                    .selected_text(selected.as_str())
+                    .width(220.0)
+                    .show_ui(ui, |ui| {
+                        let mut choices = get_audio_input_choices(&self.audio_input.devices());
+                        // Keep showing the remembered device while it is unplugged
+                        if !choices.contains(&current) {
+                            choices.push(current.clone());
+                        }
+                        for choice in choices {
+                            let text = choice.as_str().to_string();
+                            ui.selectable_value(&mut selected, choice, text);
+                        }
+                    });
+                if selected != current {
+                    self.audio_input.set_choice(selected.clone());
+                    self.settings.set(AUDIO_INPUT_SETTING, Some(&selected.to_setting()));
+                }
+                
+                let status = self.audio_input.status();
+                if selected != AudioInputChoice::Off {
+                    let color = if status.is_connected() {
+                        egui::Color32::GREEN
+                    } else {
+                        egui::Color32::from_rgb(255, 100, 100)
+                    };
+                    ui.label(egui::RichText::new(status.describe()).color(color));
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Decode on-air CW from a receiver plugged into the sound card.\n\
+                     Click the signal on the spectrum to decode it, or Auto to\n\
+                     pick the strongest tone. The text appears under Audio Decoded;\n\
+                     the alphabet and prosign settings above are used."
+                );
+            });
+            
+            if self.audio_input.status().is_connected() {
+                ui.horizontal(|ui| {
+                    let pitch = self.audio_input.pitch();
+                    if let Some(clicked) = spectrum_view(ui, &self.audio_input.spectrum(), pitch) {
+                        self.audio_input.set_pitch(Some(clicked));
+                    }
+                    ui.vertical(|ui| {
+                        ui.label(match pitch {
+                            Some(pitch) => format!("Pitch: {:.0} Hz", pitch),
+                            None => "Searching...".to_string(),
+                        });
+                        if ui.button("Auto").clicked() {
+                            self.audio_input.set_pitch(None);
+                        }
+                    });
+                });
+            }
+            
+            // CW from a recording, through the same decoder settings
+            ui.collapsing("📂 Decode file…", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("WAV file:");
+                    ui.text_edit_singleline(&mut state.decode_file_path);
+                    
+                    let decode = ui.add_enabled(!state.decode_file_busy, egui::Button::new("🔍 Decode"));
+                    if decode.clicked() {
+                        state.decode_file_busy = true;
+                        state.decode_file_status = "Decoding...".to_string();
+                        let path = std::path::PathBuf::from(state.decode_file_path.trim());
+                        let (wpm, alphabet, prefer_prosigns) = (state.wpm, state.alphabet, state.prefer_prosigns);
+                        let file_state = Arc::clone(&self.state);
+                        thread::spawn(move || {
+                            let result = decode_wav(&path, wpm, alphabet, prefer_prosigns);
+                            let mut state = file_state.lock().unwrap();
+                            state.decode_file_busy = false;
+                            match result {
+                                Ok(Some(decoded)) => {
+                                    state.decode_file_status = format!("✓ {} - tone at {:.0} Hz{}",
+                                        path.display(), decoded.pitch,
+                                        decoded.wpm.map(|wpm| format!(", about {} WPM", wpm)).unwrap_or_default());
+                                    state.decode_file_text = decoded.text;
+                                }
+                                Ok(None) => {
+                                    state.decode_file_status = format!("✗ No CW tone found in {}", path.display());
+                                    state.decode_file_text.clear();
+                                }
+                                Err(e) => {
+                                    eprintln!("Failed to read {}: {}", path.display(), e);
+                                    state.decode_file_status = format!("✗ Failed to read {}: {}", path.display(), e);
+                                    state.decode_file_text.clear();
+                                }
+                            }
+                        });
+                    }
+                    ui.label("ℹ").on_hover_text(
+                        "Decode CW from a WAV recording (any sample rate, mono or stereo).\n\
+                         The pitch and the sender's speed are found automatically;\n\
+                         the alphabet and prosign settings above are used."
+                    );
+                });
+                
+                if !state.decode_file_status.is_empty() {
+                    ui.label(egui::RichText::new(&state.decode_file_status)
+                        .size(12.0)
+                        .color(egui::Color32::LIGHT_GRAY));
+                }
+                if !state.decode_file_text.is_empty() {
+                    egui::ScrollArea::vertical()
+                        .id_source("decode_file_text")
+                        .max_height(100.0)
+                        .show(ui, |ui| {
+                            ui.label(egui::RichText::new(&state.decode_file_text)
+                                .monospace()
+                                .color(egui::Color32::LIGHT_GREEN));
+                        });
+                }
             });
             
             ui.add_space(10.0);
@@ -1155,11 +2248,30 @@ impl eframe::App for PaddleDecoderApp {
             
             ui.heading("Timing:");
             ui.horizontal(|ui| {
-                ui.label(format!("Dit: {}ms", decoder.dit_length_ms));
+                ui.label(format!("Dit: {}ms", decoder.dit_length_ms()));
+                ui.add_space(10.0);
+                ui.label(format!("Dah: {}ms", decoder.dit_length_ms() * 3));
                 ui.add_space(10.0);
-                ui.label(format!("Dah: {}ms", decoder.dit_length_ms * 3));
+                ui.label(format!("Letter gap: {}ms", decoder.letter_space_ms()));
                 ui.add_space(10.0);
-                ui.label(format!("Letter gap: {}ms", decoder.letter_space_ms));
+                ui.label(format!("Word gap: {}ms", decoder.word_space_ms()));
+                ui.add_space(10.0);
+                if let Some(estimated_wpm) = decoder.estimated_wpm() {
+                    ui.label(egui::RichText::new(format!("Est. speed: {} WPM", estimated_wpm))
+                        .color(egui::Color32::from_rgb(100, 200, 255)));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut state.adaptive_decoding, "Track sender speed").changed() {
+                    decoder.set_adaptive(state.adaptive_decoding);
+                }
+                ui.label("ℹ").on_hover_text(
+                    "Estimate the real dit and dah lengths of hand-keyed code\n\
+                     (straight key, bug) and adjust the decoder thresholds to it."
+                );
+                if ui.button("Reset").clicked() {
+                    decoder.reset_speed_tracking();
+                }
             });
             
             ui.add_space(10.0);
@@ -1196,189 +2308,259 @@ impl eframe::App for PaddleDecoderApp {
         self.state.lock().unwrap().show_training_window = show_training_window;
     }
 }
+// How much queued audio the keyer leaves before deciding on the next element
+const KEYER_LOOKAHEAD_MS: f64 = 3.0;
+
 fn automatic_keyer_thread(
     state: Arc<Mutex<AppState>>,
     decoder: Arc<Mutex<MorseDecoder>>,
-    sink: Arc<Mutex<Sink>>,
+    sidetone: ToneQueue,
 ) {
-    let mut last_element_was_dit = false;
+    let mut keyer = IambicKeyer::new(KeyerMode::Simple);
+    let mut manual_key = ManualKey::default();
+    let mut manual_down_since: Option<Instant> = None;
+    // Whether the audio still sounding is this thread's last element, not e.g. a WinKeyer
+    // macro on the same queue
+    let mut queued_by_keyer = false;
     
     loop {
-        thread::sleep(Duration::from_millis(10));
+        // Iambic and manual modes need a tighter poll so short presses are caught promptly.
+        // While the keyer's last gap is still sounding, decide right away so the next
+        // element follows it without a break.
+        if !queued_by_keyer || sidetone.is_idle() {
+            let poll_ms = if keyer.mode() == KeyerMode::Simple { 10 } else { 1 };
+            thread::sleep(Duration::from_millis(poll_ms));
+        }
+        queued_by_keyer = false;
         
-        let (left_pressed, right_pressed, wpm, frequency) = {
-            let s = state.lock().unwrap();
-            (s.left_pressed, s.right_pressed, s.wpm, s.frequency)
+        let (left_pressed, right_pressed, wpm) = {
+            let mut s = state.lock().unwrap();
+            keyer.set_mode(s.keyer_mode);
+            keyer.set_memory(s.dit_memory, s.dah_memory);
+            sidetone.set_frequency(s.frequency as f32);
+            sidetone.set_edge_ms(s.envelope_ms);
+            if keyer.mode().is_iambic() {
+                let (left, right) = s.take_paddles();
+                (left, right, s.wpm)
+            } else {
+                // Simple and the manual contacts follow the real key level, edges only
+                // matter to the iambic keyer
+                s.take_paddles();
+                (s.left_pressed, s.right_pressed, s.wpm)
+            }
         };
         
-        let dit_ms = 1200 / wpm.max(1);
-        let dah_ms = dit_ms * 3;
+        let dit_ms = 1200.0 / wpm.max(1) as f64;
         
-        // Iambic keyer logic - handles squeeze keying
-        if left_pressed && right_pressed {
-            // Both paddles pressed (squeeze) - alternate between dit and dah
-            if last_element_was_dit {
-                // Last was dit, now send dah
-                decoder.lock().unwrap().add_element(false);
-                
-                {
-                    let snk = sink.lock().unwrap();
-                    snk.stop();
-                    let tone = ToneGenerator::new(frequency as f32);
-                    snk.append(tone);
-                    snk.play();
-                }
-                
-                thread::sleep(Duration::from_millis(dah_ms as u64));
-                sink.lock().unwrap().stop();
-                thread::sleep(Duration::from_millis(dit_ms as u64));
-                
-                last_element_was_dit = false;
-            } else {
-                // Last was dah (or start), now send dit
-                decoder.lock().unwrap().add_element(true);
-                
-                {
-                    let snk = sink.lock().unwrap();
-                    snk.stop();
-                    let tone = ToneGenerator::new(frequency as f32);
-                    snk.append(tone);
-                    snk.play();
-                }
-                
-                thread::sleep(Duration::from_millis(dit_ms as u64));
-                sink.lock().unwrap().stop();
-                thread::sleep(Duration::from_millis(dit_ms as u64));
-                
-                last_element_was_dit = true;
+        // Straight key and bug contacts, or releasing a manual key-down after a mode change
+        match manual_key.update(keyer.mode(), left_pressed, right_pressed) {
+            Some(ManualAction::KeyDown) => {
+                sidetone.key(true);
+                manual_down_since = Some(Instant::now());
             }
-        } else if left_pressed {
-            // Only left paddle (DIT)
-            decoder.lock().unwrap().add_element(true);
-            
-            {
-                let snk = sink.lock().unwrap();
-                snk.stop();
-                let tone = ToneGenerator::new(frequency as f32);
-                snk.append(tone);
-                snk.play();
+            Some(ManualAction::KeyUp) => {
+                // The decoder classifies the element from the measured key-down time
+                sidetone.key(false);
+                if let Some(start) = manual_down_since.take() {
+                    decoder.lock().unwrap().add_timed_element(start.elapsed().as_millis() as u32);
+                }
             }
-            
-            thread::sleep(Duration::from_millis(dit_ms as u64));
-            sink.lock().unwrap().stop();
-            thread::sleep(Duration::from_millis(dit_ms as u64));
-            
-            last_element_was_dit = true;
-        } else if right_pressed {
-            // Only right paddle (DAH)
-            decoder.lock().unwrap().add_element(false);
-            
-            {
-                let snk = sink.lock().unwrap();
-                snk.stop();
-                let tone = ToneGenerator::new(frequency as f32);
-                snk.append(tone);
-                snk.play();
+            Some(ManualAction::Dit) => {
+                decoder.lock().unwrap().add_element(true);
+                sidetone.push(true, dit_ms);
+                sidetone.push(false, dit_ms);
+                wait_for_queue(&sidetone, || {});
+                queued_by_keyer = true;
             }
-            
-            thread::sleep(Duration::from_millis(dah_ms as u64));
-            sink.lock().unwrap().stop();
-            thread::sleep(Duration::from_millis(dit_ms as u64));
-            
-            last_element_was_dit = false;
+            None => {}
+        }
+        if keyer.mode().is_manual() {
+            continue;
         }
+        
+        // LEFT paddle sends dits, RIGHT paddle sends dahs
+        let element = match keyer.next_element(left_pressed, right_pressed) {
+            Some(element) => element,
+            None => continue,
+        };
+        
+        let element_ms = if element.is_dit() { dit_ms } else { dit_ms * 3.0 };
+        
+        decoder.lock().unwrap().add_element(element.is_dit());
+        sidetone.push(true, element_ms);
+        sidetone.push(false, dit_ms);
+        
+        // Feed paddle presses to the keyer while the element and its gap are sounding
+        wait_for_queue(&sidetone, || {
+            let (left, right) = state.lock().unwrap().take_paddles();
+            keyer.sample_paddles(left, right);
+        });
+        queued_by_keyer = true;
     }
 }
 
-// Setup MIDI connection
-fn setup_midi(
-    state: Arc<Mutex<AppState>>,
-    _decoder: Arc<Mutex<MorseDecoder>>,
-) -> Option<MidiInputConnection<()>> {
-    let midi_in = match MidiInput::new("paddle-decoder-gui") {
-        Ok(m) => m,
-        Err(e) => {
-            eprintln!("Failed to create MIDI input: {}", e);
-            return None;
-        }
-    };
+// Wait until the queued audio is about to run out, calling `sample` every millisecond.
+// Returning slightly early leaves time to queue the next element before the gap ends.
+fn wait_for_queue(queue: &ToneQueue, mut sample: impl FnMut()) {
+    while queue.queued_ms() > KEYER_LOOKAHEAD_MS {
+        thread::sleep(Duration::from_millis(1));
+        sample();
+    }
+}
+
+// Move the paddle a MIDI message is mapped to, or record it while learning
+fn handle_midi_message(state: &Arc<Mutex<AppState>>, message: &[u8]) {
+    let mut s = state.lock().unwrap();
     
-    let ports = midi_in.ports();
+    // MIDI learn: the next press becomes the binding for that paddle
+    if let Some(side) = s.midi_learn {
+        if let Some(binding) = learn_binding(message) {
+            println!("Learned {:?} paddle: {}", side, binding.describe());
+            *s.midi_mapping.binding_mut(side) = binding;
+            s.midi_learn = None;
+            s.midi_mapping_changed = true;
+        }
+        return;
+    }
     
-    if ports.is_empty() {
-        eprintln!("No MIDI ports found!");
-        return None;
+    if let Some((side, pressed)) = s.midi_mapping.interpret(message) {
+        s.set_paddle(PaddleSource::Midi, side, pressed);
     }
+}
+
+// One paddle's MIDI binding: message type, number, channel and a learn button
+fn midi_binding_row(ui: &mut egui::Ui, state: &mut AppState, side: PaddleSide, label: &str) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let learning = state.midi_learn == Some(side);
+        let binding = state.midi_mapping.binding_mut(side);
+        let before = *binding;
+        
+        egui::ComboBox::from_id_source(("midi_trigger", label))
+            .selected_text(binding.trigger.as_str())
+            .width(60.0)
+            .show_ui(ui, |ui| {
+                let number = binding.trigger.number();
+                ui.selectable_value(&mut binding.trigger, MidiTrigger::Note(number), "Note");
+                ui.selectable_value(&mut binding.trigger, MidiTrigger::ControlChange(number), "CC");
+            });
+        
+        let mut number = binding.trigger.number();
+        if ui.add(egui::DragValue::new(&mut number).clamp_range(0..=127)).changed() {
+            binding.trigger = binding.trigger.with_number(number);
+        }
+        
+        ui.label("Channel:");
+        egui::ComboBox::from_id_source(("midi_channel", label))
+            .selected_text(binding.channel.map_or("Any".to_string(), |c| (c + 1).to_string()))
+            .width(50.0)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut binding.channel, None, "Any");
+                for channel in 0..16u8 {
+                    ui.selectable_value(&mut binding.channel, Some(channel), (channel + 1).to_string());
+                }
+            });
+        
+        if *binding != before {
+            state.midi_mapping_changed = true;
+        }
+        
+        let learn_text = if learning {
+            egui::RichText::new("Press the paddle...").color(egui::Color32::YELLOW)
+        } else {
+            egui::RichText::new("🎯 Learn")
+        };
+        if ui.button(learn_text).clicked() {
+            // Click again to cancel
+            state.midi_learn = if learning { None } else { Some(side) };
+        }
+    });
+}
+
+// Small spectrum of the audio input with the decoded pitch marked; returns the frequency
+// clicked on
+fn spectrum_view(ui: &mut egui::Ui, spectrum: &[(f32, f32)], pitch: Option<f32>) -> Option<f32> {
+    // Levels in dB below the strongest tone, this much range shown
+    const RANGE_DB: f32 = 40.0;
     
-    println!("Available MIDI ports:");
-    for (i, port) in ports.iter().enumerate() {
-        println!("  {}: {}", i, midi_in.port_name(port).unwrap_or_default());
+    let (response, painter) = ui.allocate_painter(egui::vec2(360.0, 60.0), egui::Sense::click());
+    let rect = response.rect;
+    let (low, high) = (tone_detector::MIN_PITCH_HZ, tone_detector::MAX_PITCH_HZ);
+    let x = |frequency: f32| rect.left() + (frequency - low) / (high - low) * rect.width();
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(20));
+    
+    let peak = spectrum.iter().map(|&(_, level)| level).fold(0.0, f32::max);
+    if peak > 0.0 {
+        let half_bar = rect.width() / spectrum.len() as f32 / 2.0;
+        for &(frequency, level) in spectrum {
+            let db = 20.0 * (level / peak).max(1e-6).log10();
+            let height = ((db + RANGE_DB) / RANGE_DB).clamp(0.0, 1.0) * rect.height();
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(x(frequency) - half_bar, rect.bottom() - height),
+                    egui::pos2(x(frequency) + half_bar, rect.bottom()),
+                ),
+                0.0,
+                egui::Color32::from_rgb(60, 140, 60),
+            );
+        }
+    }
+    if let Some(pitch) = pitch {
+        painter.vline(x(pitch), rect.y_range(), egui::Stroke::new(1.5, egui::Color32::YELLOW));
     }
     
-    let port = ports.iter()
-        .find(|p| {
-            let name = midi_in.port_name(p).unwrap_or_default();
-            name.contains("MidiStomp") || name.contains("MIDI")
-        })
-        .or(ports.first())
-        .cloned();
+    let response = response.on_hover_text(format!("{:.0} - {:.0} Hz: click on a signal to decode it", low, high));
+    if !response.clicked() {
+        return None;
+    }
+    let position = response.interact_pointer_pos()?;
+    Some(low + (position.x - rect.left()) / rect.width() * (high - low))
+}
+
+// egui's built-in fonts cover Latin, Greek and Cyrillic, but not Japanese kana.
+// Add a CJK system font as a fallback so Wabun text renders.
+fn install_fallback_fonts(ctx: &egui::Context) {
+    const CJK_FONT_PATHS: &[&str] = &[
+        // Linux
+        "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/truetype/droid/DroidSansFallbackFull.ttf",
+        "/usr/share/fonts/truetype/fonts-japanese-gothic.ttf",
+        // Windows
+        "C:\\Windows\\Fonts\\msgothic.ttc",
+        "C:\\Windows\\Fonts\\YuGothR.ttc",
+        "C:\\Windows\\Fonts\\meiryo.ttc",
+        // macOS
+        "/System/Library/Fonts/ヒラギノ角ゴシック W3.ttc",
+        "/System/Library/Fonts/Hiragino Sans GB.ttc",
+        "/Library/Fonts/Arial Unicode.ttf",
+    ];
     
-    let port = match port {
-        Some(p) => p,
+    let font_bytes = CJK_FONT_PATHS.iter().find_map(|path| std::fs::read(path).ok());
+    let font_bytes = match font_bytes {
+        Some(bytes) => bytes,
         None => {
-            eprintln!("No suitable MIDI port found");
-            return None;
+            eprintln!("No Japanese font found - Wabun text may not display");
+            return;
         }
     };
     
-    println!("Connecting to: {}", midi_in.port_name(&port).unwrap_or_default());
-    
-    let conn = midi_in.connect(
-        &port,
-        "paddle-decoder-gui",
-        move |_timestamp, message, _| {
-            if message.len() >= 3 {
-                let status = message[0];
-                let note = message[1];
-                let velocity = message[2];
-                
-                if status == 0x90 {
-                    let mut s = state.lock().unwrap();
-                    
-                    if velocity > 0 {
-                        // Paddle pressed
-                        match note {
-                            1 => s.left_pressed = true,
-                            2 => s.right_pressed = true,
-                            _ => {}
-                        }
-                    } else {
-                        // Paddle released
-                        match note {
-                            1 => s.left_pressed = false,
-                            2 => s.right_pressed = false,
-                            _ => {}
-                        }
-                    }
-                }
-            }
-        },
-        (),
-    );
-    
-    match conn {
-        Ok(c) => {
-            println!("MIDI connection established!");
-            Some(c)
-        },
-        Err(e) => {
-            eprintln!("Failed to connect to MIDI port: {}", e);
-            None
-        }
+    let mut fonts = egui::FontDefinitions::default();
+    fonts.font_data.insert("cjk_fallback".to_owned(), egui::FontData::from_owned(font_bytes));
+    for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+        fonts.families.entry(family).or_default().push("cjk_fallback".to_owned());
     }
+    ctx.set_fonts(fonts);
 }
 
 fn main() -> Result<(), eframe::Error> {
+    // Commands run without a window (see cli.rs); anything else opens it
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| cli::is_command(arg)) {
+        std::process::exit(cli::run(&args));
+    }
+    
     println!("===========================================");
     println!("    Paddle Decoder with Auto Keyer");
     println!("===========================================\n");
@@ -1398,3 +2580,67 @@ fn main() -> Result<(), eframe::Error> {
         Box::new(|cc| Box::new(PaddleDecoderApp::new(cc))),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paddle_stays_down_while_another_source_holds_it() {
+        let mut state = AppState::default();
+        state.set_paddle(PaddleSource::Midi, PaddleSide::Left, true);
+        state.set_paddle(PaddleSource::Keyboard, PaddleSide::Left, true);
+        state.set_paddle(PaddleSource::Midi, PaddleSide::Left, false);
+        assert!(state.left_pressed);
+        assert_eq!(state.take_paddles(), (true, false));
+
+        state.set_paddle(PaddleSource::Keyboard, PaddleSide::Left, false);
+        assert!(!state.left_pressed);
+        assert_eq!(state.take_paddles(), (false, false));
+    }
+
+    #[test]
+    fn held_paddle_is_not_pressed_again_after_release() {
+        let mut state = AppState::default();
+        state.set_paddle(PaddleSource::Keyboard, PaddleSide::Left, true);
+        assert_eq!(state.take_paddles(), (true, false));
+        assert_eq!(state.take_paddles(), (true, false));
+        state.set_paddle(PaddleSource::Keyboard, PaddleSide::Left, false);
+        assert_eq!(state.take_paddles(), (false, false));
+    }
+
+    #[test]
+    fn midi_disconnect_releases_only_midi_contacts() {
+        let mut state = AppState::default();
+        state.set_paddle(PaddleSource::Midi, PaddleSide::Left, true);
+        state.set_paddle(PaddleSource::Midi, PaddleSide::Right, true);
+        state.set_paddle(PaddleSource::Keyboard, PaddleSide::Right, true);
+        state.take_paddles();
+
+        state.release_source(PaddleSource::Midi);
+        assert!(!state.left_pressed);
+        assert!(state.right_pressed);
+        assert_eq!(state.take_paddles(), (false, true));
+    }
+
+    #[test]
+    fn short_tap_is_latched_until_taken() {
+        let mut state = AppState::default();
+        state.set_paddle(PaddleSource::Mouse, PaddleSide::Right, true);
+        state.set_paddle(PaddleSource::Mouse, PaddleSide::Right, false);
+        assert!(!state.right_pressed);
+        assert_eq!(state.take_paddles(), (false, true));
+        assert_eq!(state.take_paddles(), (false, false));
+    }
+
+    #[test]
+    fn second_source_pressing_a_held_paddle_is_not_a_new_press() {
+        let mut state = AppState::default();
+        state.set_paddle(PaddleSource::Midi, PaddleSide::Left, true);
+        assert_eq!(state.take_paddles(), (true, false));
+        state.set_paddle(PaddleSource::Serial, PaddleSide::Left, true);
+        state.set_paddle(PaddleSource::Midi, PaddleSide::Left, false);
+        state.set_paddle(PaddleSource::Serial, PaddleSide::Left, false);
+        assert_eq!(state.take_paddles(), (false, false));
+    }
+}
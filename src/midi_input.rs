@@ -0,0 +1,302 @@
+// MIDI paddle input
+// A watcher thread keeps the MIDI connection alive: it connects to the chosen port (or
+// picks one the way the app always has - a "MidiStomp"/"MIDI" port, else the first),
+// notices when the port disappears, and reconnects as soon as it is plugged back in.
+// While connected it only checks that its port is still there; the port list is
+// refreshed while disconnected or when asked for.
+use midir::{MidiInput, MidiInputConnection};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CLIENT_NAME: &str = "paddle-decoder-gui";
+
+// How often the ports are checked for unplugged / replugged devices
+const SCAN_INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MidiStatus {
+    Searching,          // Automatic choice, no port found yet
+    Waiting(String),    // Chosen port is not plugged in
+    Connected(String),
+    Error(String),
+}
+
+impl MidiStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            MidiStatus::Searching => "No MIDI paddle found".to_string(),
+            MidiStatus::Waiting(port) => format!("Waiting for {}", port),
+            MidiStatus::Connected(port) => format!("Connected: {}", port),
+            MidiStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, MidiStatus::Connected(_))
+    }
+}
+
+// Called from the MIDI thread with every incoming message
+pub type MidiHandler = Arc<dyn Fn(&[u8]) + Send + Sync>;
+// Called when the connection is dropped, so paddles held at the time are let go
+pub type ReleaseHandler = Arc<dyn Fn() + Send + Sync>;
+
+struct Shared {
+    ports: Vec<String>,
+    preferred: Option<String>,  // None = pick automatically
+    status: MidiStatus,
+    reconnect: bool,            // Preferred port changed, drop the current connection
+    rescan: bool,               // Refresh the port list even while connected
+}
+
+#[derive(Clone)]
+pub struct MidiInputManager {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl MidiInputManager {
+    // Start watching for ports and connect as soon as a suitable one shows up
+    pub fn start(preferred: Option<String>, handler: MidiHandler, release: ReleaseHandler) -> Self {
+        let manager = MidiInputManager {
+            shared: Arc::new(Mutex::new(Shared {
+                ports: Vec::new(),
+                preferred,
+                status: MidiStatus::Searching,
+                reconnect: false,
+                rescan: false,
+            })),
+        };
+
+        let watcher = manager.clone();
+        thread::spawn(move || watcher.watch(handler, release));
+        manager
+    }
+
+    pub fn ports(&self) -> Vec<String> {
+        self.shared.lock().unwrap().ports.clone()
+    }
+
+    pub fn preferred_port(&self) -> Option<String> {
+        self.shared.lock().unwrap().preferred.clone()
+    }
+
+    pub fn set_preferred_port(&self, port: Option<String>) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.preferred != port {
+            shared.preferred = port;
+            shared.reconnect = true;
+        }
+    }
+
+    // Refresh the port list at once, e.g. when the port picker is opened
+    pub fn rescan(&self) {
+        self.shared.lock().unwrap().rescan = true;
+    }
+
+    pub fn status(&self) -> MidiStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    fn set_status(&self, status: MidiStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn watch(&self, handler: MidiHandler, release: ReleaseHandler) {
+        let mut connection: Option<(String, MidiInputConnection<()>)> = None;
+        // Kept for listing ports; connecting uses up a client, so that gets a new one
+        let mut scanner: Option<MidiInput> = None;
+
+        loop {
+            let names = match &scanner {
+                Some(midi_in) => port_names(midi_in),
+                None => match MidiInput::new(CLIENT_NAME) {
+                    Ok(midi_in) => {
+                        let names = port_names(&midi_in);
+                        scanner = Some(midi_in);
+                        names
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to create MIDI input: {}", e);
+                        self.set_status(MidiStatus::Error(e.to_string()));
+                        self.wait_for_next_scan();
+                        continue;
+                    }
+                },
+            };
+
+            let (preferred, reconnect, rescan) = {
+                let mut shared = self.shared.lock().unwrap();
+                (shared.preferred.clone(), std::mem::take(&mut shared.reconnect),
+                 std::mem::take(&mut shared.rescan))
+            };
+
+            drop_lost_connection(&mut connection, &names, reconnect, &release);
+
+            if connection.is_none() || rescan {
+                let mut shared = self.shared.lock().unwrap();
+                if shared.ports != names {
+                    println!("Available MIDI ports:");
+                    for (i, name) in names.iter().enumerate() {
+                        println!("  {}: {}", i, name);
+                    }
+                    shared.ports = names.clone();
+                }
+            }
+
+            if connection.is_none() {
+                match pick_port(&names, preferred.as_deref()) {
+                    Some(name) => {
+                        let name = name.to_string();
+                        let connected = MidiInput::new(CLIENT_NAME)
+                            .map_err(|e| e.to_string())
+                            .and_then(|midi_in| connect(midi_in, &name, Arc::clone(&handler)));
+                        match connected {
+                            Ok(conn) => {
+                                println!("MIDI connection established: {}", name);
+                                self.set_status(MidiStatus::Connected(name.clone()));
+                                connection = Some((name, conn));
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to connect to MIDI port {}: {}", name, e);
+                                self.set_status(MidiStatus::Error(e));
+                            }
+                        }
+                    }
+                    None => self.set_status(match preferred {
+                        Some(port) => MidiStatus::Waiting(port),
+                        None => MidiStatus::Searching,
+                    }),
+                }
+            }
+
+            self.wait_for_next_scan();
+        }
+    }
+
+    // Sleep until the next scan, or until a different port is chosen or a rescan asked for
+    fn wait_for_next_scan(&self) {
+        for _ in 0..SCAN_INTERVAL_MS / 50 {
+            thread::sleep(Duration::from_millis(50));
+            let shared = self.shared.lock().unwrap();
+            if shared.reconnect || shared.rescan {
+                break;
+            }
+        }
+    }
+}
+
+fn port_names(midi_in: &MidiInput) -> Vec<String> {
+    midi_in.ports().iter()
+        .filter_map(|port| midi_in.port_name(port).ok())
+        .collect()
+}
+
+// Drop the connection when its device is gone or another port was chosen. No key-up
+// will come from a dropped connection, so the paddles it held are released.
+fn drop_lost_connection<C>(
+    connection: &mut Option<(String, C)>,
+    names: &[String],
+    reconnect: bool,
+    release: &ReleaseHandler,
+) {
+    if let Some((name, _)) = connection {
+        if !names.contains(name) {
+            println!("MIDI port disconnected: {}", name);
+        } else if !reconnect {
+            return;
+        }
+        *connection = None;
+        release();
+    }
+}
+
+// The chosen port, or without a choice a "MidiStomp"/"MIDI" port, else the first one
+fn pick_port<'a>(names: &'a [String], preferred: Option<&str>) -> Option<&'a str> {
+    match preferred {
+        Some(preferred) => names.iter().find(|name| name.as_str() == preferred),
+        None => names.iter()
+            .find(|name| name.contains("MidiStomp") || name.contains("MIDI"))
+            .or(names.first()),
+    }.map(|name| name.as_str())
+}
+
+fn connect(midi_in: MidiInput, name: &str, handler: MidiHandler) -> Result<MidiInputConnection<()>, String> {
+    let port = midi_in.ports().into_iter()
+        .find(|port| midi_in.port_name(port).ok().as_deref() == Some(name))
+        .ok_or_else(|| "port disappeared".to_string())?;
+
+    midi_in.connect(
+        &port,
+        CLIENT_NAME,
+        move |_timestamp, message, _| handler(message),
+        (),
+    ).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn remembered_port_is_matched_exactly() {
+        let ports = names(&["Digispark MIDI", "MidiStomp 1", "MidiStomp"]);
+        assert_eq!(pick_port(&ports, Some("MidiStomp")), Some("MidiStomp"));
+        assert_eq!(pick_port(&ports, Some("Digispark MIDI")), Some("Digispark MIDI"));
+    }
+
+    #[test]
+    fn missing_remembered_port_is_waited_for() {
+        let ports = names(&["Digispark MIDI", "MidiStomp 1"]);
+        // Not a near match and not some other device
+        assert_eq!(pick_port(&ports, Some("MidiStomp")), None);
+        assert_eq!(pick_port(&[], Some("MidiStomp")), None);
+    }
+
+    #[test]
+    fn automatic_choice() {
+        assert_eq!(pick_port(&names(&["Through", "USB MIDI Interface"]), None), Some("USB MIDI Interface"));
+        assert_eq!(pick_port(&names(&["Through", "MidiStomp"]), None), Some("MidiStomp"));
+        assert_eq!(pick_port(&names(&["Through", "Synth"]), None), Some("Through"));
+        assert_eq!(pick_port(&[], None), None);
+    }
+
+    // Release handler counting its calls
+    fn counting_release() -> (ReleaseHandler, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&count);
+        (Arc::new(move || { counter.fetch_add(1, Ordering::Relaxed); }), count)
+    }
+
+    #[test]
+    fn unplugged_port_drops_the_connection_and_releases() {
+        let (release, released) = counting_release();
+        let mut connection = Some(("MidiStomp".to_string(), ()));
+
+        drop_lost_connection(&mut connection, &names(&["MidiStomp", "Other"]), false, &release);
+        assert!(connection.is_some());
+        assert_eq!(released.load(Ordering::Relaxed), 0);
+
+        drop_lost_connection(&mut connection, &names(&["Other"]), false, &release);
+        assert!(connection.is_none());
+        assert_eq!(released.load(Ordering::Relaxed), 1);
+
+        // Nothing more to release while disconnected
+        drop_lost_connection(&mut connection, &names(&["Other"]), false, &release);
+        assert_eq!(released.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn choosing_another_port_drops_the_connection_and_releases() {
+        let (release, released) = counting_release();
+        let mut connection = Some(("MidiStomp".to_string(), ()));
+        drop_lost_connection(&mut connection, &names(&["MidiStomp", "Other"]), true, &release);
+        assert!(connection.is_none());
+        assert_eq!(released.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,240 @@
+// MIDI message to paddle mapping
+// Each paddle is bound to a note or a control change, on one channel or any channel.
+// Notes press on Note On (velocity > 0) and release on Note Off or Note On with
+// velocity 0; controllers press at value 64 and above, like a sustain pedal.
+
+use crate::paddle_input::PaddleSide;
+
+// Controller values at or above this count as pressed
+const CC_PRESS_THRESHOLD: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiTrigger {
+    Note(u8),
+    ControlChange(u8),
+}
+
+impl MidiTrigger {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MidiTrigger::Note(_) => "Note",
+            MidiTrigger::ControlChange(_) => "CC",
+        }
+    }
+
+    pub fn number(&self) -> u8 {
+        match self {
+            MidiTrigger::Note(n) | MidiTrigger::ControlChange(n) => *n,
+        }
+    }
+
+    pub fn with_number(&self, number: u8) -> MidiTrigger {
+        match self {
+            MidiTrigger::Note(_) => MidiTrigger::Note(number),
+            MidiTrigger::ControlChange(_) => MidiTrigger::ControlChange(number),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiBinding {
+    pub trigger: MidiTrigger,
+    pub channel: Option<u8>,  // 0-15, None = any channel
+}
+
+impl MidiBinding {
+    pub fn describe(&self) -> String {
+        let channel = match self.channel {
+            Some(channel) => format!("ch {}", channel + 1),
+            None => "any ch".to_string(),
+        };
+        format!("{} {} ({})", self.trigger.as_str(), self.trigger.number(), channel)
+    }
+
+    // Settings file form: "note 1 any", "cc 64 3" (channel 1-16)
+    pub fn to_setting(self) -> String {
+        let kind = match self.trigger {
+            MidiTrigger::Note(_) => "note",
+            MidiTrigger::ControlChange(_) => "cc",
+        };
+        let channel = match self.channel {
+            Some(channel) => (channel + 1).to_string(),
+            None => "any".to_string(),
+        };
+        format!("{} {} {}", kind, self.trigger.number(), channel)
+    }
+
+    pub fn from_setting(text: &str) -> Option<MidiBinding> {
+        let mut parts = text.split_whitespace();
+        let kind = parts.next()?;
+        let number = parts.next()?.parse::<u8>().ok().filter(|&n| n < 128)?;
+        let trigger = match kind {
+            "note" => MidiTrigger::Note(number),
+            "cc" => MidiTrigger::ControlChange(number),
+            _ => return None,
+        };
+        let channel = match parts.next()? {
+            "any" => None,
+            channel => Some(channel.parse::<u8>().ok().filter(|c| (1..=16).contains(c))? - 1),
+        };
+        Some(MidiBinding { trigger, channel })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiMapping {
+    pub left: MidiBinding,
+    pub right: MidiBinding,
+}
+
+impl Default for MidiMapping {
+    // The Digispark paddle interface: note 1 = LEFT, note 2 = RIGHT, any channel
+    fn default() -> Self {
+        MidiMapping {
+            left: MidiBinding { trigger: MidiTrigger::Note(1), channel: None },
+            right: MidiBinding { trigger: MidiTrigger::Note(2), channel: None },
+        }
+    }
+}
+
+impl MidiMapping {
+    pub fn binding_mut(&mut self, side: PaddleSide) -> &mut MidiBinding {
+        match side {
+            PaddleSide::Left => &mut self.left,
+            PaddleSide::Right => &mut self.right,
+        }
+    }
+
+    // Which paddle the message moves and whether it is now pressed
+    pub fn interpret(&self, message: &[u8]) -> Option<(PaddleSide, bool)> {
+        let (trigger, channel, pressed) = parse_message(message)?;
+        [(PaddleSide::Left, &self.left), (PaddleSide::Right, &self.right)]
+            .into_iter()
+            .find(|(_, binding)| {
+                binding.trigger == trigger && binding.channel.unwrap_or(channel) == channel
+            })
+            .map(|(side, _)| (side, pressed))
+    }
+}
+
+// Binding for MIDI learn: the first press seen, on the channel it came from
+pub fn learn_binding(message: &[u8]) -> Option<MidiBinding> {
+    match parse_message(message)? {
+        (trigger, channel, true) => Some(MidiBinding { trigger, channel: Some(channel) }),
+        _ => None,
+    }
+}
+
+// Trigger, channel and pressed state of a Note On / Note Off / Control Change message
+fn parse_message(message: &[u8]) -> Option<(MidiTrigger, u8, bool)> {
+    if message.len() < 3 {
+        return None;
+    }
+    let channel = message[0] & 0x0F;
+    let number = message[1];
+    let value = message[2];
+
+    match message[0] & 0xF0 {
+        0x80 => Some((MidiTrigger::Note(number), channel, false)),
+        // Velocity 0 means the paddle was released
+        0x90 => Some((MidiTrigger::Note(number), channel, value > 0)),
+        0xB0 => Some((MidiTrigger::ControlChange(number), channel, value >= CC_PRESS_THRESHOLD)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE_OFF: u8 = 0x80;
+    const NOTE_ON: u8 = 0x90;
+    const CONTROL_CHANGE: u8 = 0xB0;
+
+    fn binding(trigger: MidiTrigger, channel: Option<u8>) -> MidiBinding {
+        MidiBinding { trigger, channel }
+    }
+
+    #[test]
+    fn note_on_presses_and_note_off_releases() {
+        let mapping = MidiMapping::default();
+        assert_eq!(mapping.interpret(&[NOTE_ON, 1, 100]), Some((PaddleSide::Left, true)));
+        assert_eq!(mapping.interpret(&[NOTE_OFF, 1, 64]), Some((PaddleSide::Left, false)));
+        assert_eq!(mapping.interpret(&[NOTE_ON, 2, 1]), Some((PaddleSide::Right, true)));
+        assert_eq!(mapping.interpret(&[NOTE_OFF | 5, 2, 0]), Some((PaddleSide::Right, false)));
+    }
+
+    #[test]
+    fn note_on_with_velocity_zero_releases() {
+        let mapping = MidiMapping::default();
+        assert_eq!(mapping.interpret(&[NOTE_ON, 1, 0]), Some((PaddleSide::Left, false)));
+    }
+
+    #[test]
+    fn controller_presses_from_64() {
+        let mapping = MidiMapping {
+            left: binding(MidiTrigger::ControlChange(64), None),
+            right: binding(MidiTrigger::ControlChange(65), None),
+        };
+        assert_eq!(mapping.interpret(&[CONTROL_CHANGE, 64, 63]), Some((PaddleSide::Left, false)));
+        assert_eq!(mapping.interpret(&[CONTROL_CHANGE, 64, 64]), Some((PaddleSide::Left, true)));
+        assert_eq!(mapping.interpret(&[CONTROL_CHANGE, 65, 127]), Some((PaddleSide::Right, true)));
+        assert_eq!(mapping.interpret(&[CONTROL_CHANGE, 65, 0]), Some((PaddleSide::Right, false)));
+        // A note with the same number is not the controller
+        assert_eq!(mapping.interpret(&[NOTE_ON, 64, 100]), None);
+    }
+
+    #[test]
+    fn channel_filter() {
+        let mapping = MidiMapping {
+            left: binding(MidiTrigger::Note(1), Some(3)),
+            right: binding(MidiTrigger::Note(2), None),
+        };
+        assert_eq!(mapping.interpret(&[NOTE_ON | 3, 1, 100]), Some((PaddleSide::Left, true)));
+        assert_eq!(mapping.interpret(&[NOTE_ON | 4, 1, 100]), None);
+        assert_eq!(mapping.interpret(&[NOTE_ON, 1, 100]), None);
+        for channel in 0..16 {
+            assert_eq!(mapping.interpret(&[NOTE_ON | channel, 2, 100]), Some((PaddleSide::Right, true)));
+        }
+    }
+
+    #[test]
+    fn other_messages_are_ignored() {
+        let mapping = MidiMapping::default();
+        assert_eq!(mapping.interpret(&[NOTE_ON, 3, 100]), None);
+        assert_eq!(mapping.interpret(&[NOTE_ON, 1]), None);
+        // Pitch bend
+        assert_eq!(mapping.interpret(&[0xE0, 1, 100]), None);
+    }
+
+    #[test]
+    fn learn_takes_the_first_press_and_its_channel() {
+        assert_eq!(learn_binding(&[NOTE_OFF | 2, 60, 0]), None);
+        assert_eq!(learn_binding(&[NOTE_ON | 2, 60, 0]), None);
+        assert_eq!(learn_binding(&[CONTROL_CHANGE, 64, 10]), None);
+        assert_eq!(learn_binding(&[NOTE_ON | 2, 60, 90]), Some(binding(MidiTrigger::Note(60), Some(2))));
+        assert_eq!(learn_binding(&[CONTROL_CHANGE | 15, 64, 127]),
+                   Some(binding(MidiTrigger::ControlChange(64), Some(15))));
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        for binding in [
+            binding(MidiTrigger::Note(1), None),
+            binding(MidiTrigger::Note(127), Some(0)),
+            binding(MidiTrigger::ControlChange(64), Some(15)),
+            binding(MidiTrigger::ControlChange(0), None),
+        ] {
+            assert_eq!(MidiBinding::from_setting(&binding.to_setting()), Some(binding));
+        }
+        assert_eq!(binding(MidiTrigger::ControlChange(64), Some(2)).to_setting(), "cc 64 3");
+        assert_eq!(binding(MidiTrigger::Note(1), None).to_setting(), "note 1 any");
+    }
+
+    #[test]
+    fn bad_settings_are_rejected() {
+        for text in ["note 128 any", "cc 1 17", "cc 1 0", "pc 1 any", "note 1", "note x any", ""] {
+            assert_eq!(MidiBinding::from_setting(text), None, "{text:?}");
+        }
+    }
+}
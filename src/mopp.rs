@@ -0,0 +1,720 @@
+// Internet CW over MOPP (Morse Over Packet Protocol)
+// MOPP is the UDP protocol of the Morserino-32 and the MOPP chat servers. Each packet holds
+// a header and a run of 2-bit symbols:
+//
+//   2 bits protocol version (01) | 6 bits serial number | 6 bits speed (WPM)
+//   then 01 dit, 10 dah, 00 end of letter, 11 end of word, zero-padded to a whole byte
+//
+// Everything the decoder sees is sent one letter per packet, and the word end on its own.
+// Incoming packets are played at the sender's speed on a tone queue per station, each at
+// its own pitch, so the spacing comes through unchanged and nothing received reaches the
+// keying output. Each station has a decoder for its text.
+//
+// A server relays each packet to the other stations that joined it. Stations join by
+// sending a word ("hi"), and the channel number follows it ("hi 3") to pick a net on a
+// relay that has several, like the practice net relay. That relay sends each station's
+// packets from the server port plus the station number, which tells the stations apart. For testing, any UDP echo works
+// as a server that plays your own keying back: `socat UDP-RECVFROM:7373,fork EXEC:cat`.
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rodio::OutputStreamHandle;
+
+use crate::morse_decoder::{MorseDecoder, Symbol};
+use crate::morse_table::{Alphabet, MorseTable};
+use crate::tone_queue::ToneQueue;
+
+pub const DEFAULT_SERVER: &str = "localhost:7373";
+pub const MAX_CHANNEL: u32 = 99;
+
+const PROTOCOL_VERSION: u8 = 1;
+const MAX_WPM: u32 = 63;
+
+// Servers forget stations that stay quiet, so the join is repeated after this long
+const REJOIN_SECS: u64 = 300;
+
+// Silence before playback starts after a pause, so a late packet does not stretch a gap
+const PLAYOUT_DELAY_MS: f64 = 150.0;
+
+// Received text kept for display, per station
+const MAX_RECEIVED_CHARS: usize = 2000;
+
+// Pitch spacing between stations, so several at once can be told apart
+const PITCH_STEP_HZ: f32 = 60.0;
+const MIN_PITCH_HZ: f32 = 200.0;
+
+const RETRY_MS: u64 = 2000;
+const POLL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoppPacket {
+    pub serial: u8,
+    pub wpm: u32,
+    pub symbols: Vec<Symbol>,
+}
+
+impl MoppPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.push(PROTOCOL_VERSION as u32, 2);
+        bits.push(self.serial as u32 & 0x3F, 6);
+        bits.push(self.wpm.min(MAX_WPM), 6);
+        for symbol in &self.symbols {
+            let code = match symbol {
+                Symbol::Dit => 0b01,
+                Symbol::Dah => 0b10,
+                Symbol::LetterSpace => 0b00,
+                Symbol::WordSpace => 0b11,
+            };
+            bits.push(code, 2);
+        }
+        bits.bytes
+    }
+
+    // None for anything that is not a MOPP packet of the known version
+    pub fn decode(bytes: &[u8]) -> Option<MoppPacket> {
+        if bytes.len() < 2 || bytes[0] >> 6 != PROTOCOL_VERSION {
+            return None;
+        }
+        let serial = bytes[0] & 0x3F;
+        let wpm = (bytes[1] >> 2) as u32;
+
+        let mut symbols: Vec<Symbol> = Vec::new();
+        for bit in (14..bytes.len() * 8).step_by(2) {
+            let code = (bytes[bit / 8] >> (6 - bit % 8)) & 0b11;
+            let symbol = match code {
+                0b01 => Symbol::Dit,
+                0b10 => Symbol::Dah,
+                0b00 => Symbol::LetterSpace,
+                _ => Symbol::WordSpace,
+            };
+            // Padding reads as letter ends; one after another they mean nothing
+            let after_space = matches!(symbols.last(), None | Some(Symbol::LetterSpace | Symbol::WordSpace));
+            if symbol == Symbol::LetterSpace && after_space {
+                continue;
+            }
+            symbols.push(symbol);
+        }
+        Some(MoppPacket { serial, wpm, symbols })
+    }
+
+    // Text as whole letters ending in a word space, e.g. the join word
+    pub fn from_text(serial: u8, wpm: u32, text: &str) -> MoppPacket {
+        let table = MorseTable::standard();
+        let mut symbols = Vec::new();
+        for word in text.split_whitespace() {
+            for code in word.chars().filter_map(|ch| table.encode_char(ch)) {
+                symbols.extend(code.chars().map(|c| if c == '.' { Symbol::Dit } else { Symbol::Dah }));
+                symbols.push(Symbol::LetterSpace);
+            }
+            if let Some(last) = symbols.last_mut() {
+                *last = Symbol::WordSpace;
+            }
+        }
+        MoppPacket { serial, wpm, symbols }
+    }
+
+    // Letters as text, words separated by spaces
+    pub fn text(&self) -> String {
+        let table = MorseTable::standard();
+        let mut text = String::new();
+        let mut code = String::new();
+        for symbol in &self.symbols {
+            match symbol {
+                Symbol::Dit => code.push('.'),
+                Symbol::Dah => code.push('-'),
+                Symbol::LetterSpace | Symbol::WordSpace => {
+                    if !code.is_empty() {
+                        text.push_str(table.decode(&code).unwrap_or("?"));
+                        code.clear();
+                    }
+                    if *symbol == Symbol::WordSpace {
+                        text.push(' ');
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+// Station a packet came from: the practice net relay (practice_net.rs) sends station N's
+// packets from the server port + N. Station 0 is the server port itself, as for a plain
+// MOPP server; None for packets from another host.
+pub fn station_number(server: SocketAddr, from: SocketAddr) -> Option<u8> {
+    if from.ip() != server.ip() {
+        return None;
+    }
+    let offset = from.port().wrapping_sub(server.port());
+    Some(u8::try_from(offset).unwrap_or(0))
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u32,  // Bits filled in the last byte, 0 when it is full
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+}
+
+// Word a station sends to join, with the channel after it
+pub fn join_text(channel: u32) -> String {
+    if channel == 0 { "hi".to_string() } else { format!("hi {}", channel) }
+}
+
+// Channel of a join packet, None for anything else. Keyed text never matches: it is sent
+// a letter per packet, the join word comes whole.
+pub fn join_channel(packet: &MoppPacket) -> Option<u32> {
+    let text = packet.text();
+    let mut words = text.split_whitespace();
+    if words.next() != Some("HI") || packet.symbols.last() != Some(&Symbol::WordSpace) {
+        return None;
+    }
+    let channel = match words.next() {
+        Some(number) => number.parse().ok().filter(|&channel| channel <= MAX_CHANNEL)?,
+        None => 0,
+    };
+    words.next().is_none().then_some(channel)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoppStatus {
+    Off,
+    Joined(String),
+    Error(String),
+}
+
+impl MoppStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            MoppStatus::Off => "Off".to_string(),
+            MoppStatus::Joined(server) => format!("Joined: {}", server),
+            MoppStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, MoppStatus::Joined(_))
+    }
+}
+
+struct Shared {
+    enabled: bool,
+    server: String,
+    channel: u32,
+    reconnect: bool,
+    status: MoppStatus,
+    serial: u8,
+    letter: Vec<Symbol>,  // Elements keyed since the last letter end
+    word_ended: bool,     // Word space already sent
+    outgoing: VecDeque<Vec<u8>>,
+    stations: Vec<Station>,
+    frequency: f32,
+    edge_ms: u32,
+    alphabet: Alphabet,
+    prefer_prosigns: bool,
+}
+
+impl Shared {
+    fn pitch(&self, station: &Station) -> f32 {
+        (self.frequency + station.pitch_offset).max(MIN_PITCH_HZ)
+    }
+}
+
+// A station heard through the server, with its own pitch, playback and decoder
+struct Station {
+    number: u8,
+    pitch_offset: f32,
+    queue: ToneQueue,
+    playback: Playback,
+    decoder: MorseDecoder,
+    text: String,
+}
+
+impl Station {
+    fn receive(&mut self, packet: &MoppPacket) {
+        self.playback.play(&self.queue, packet);
+        for symbol in &packet.symbols {
+            match symbol {
+                Symbol::Dit => self.decoder.add_element(true),
+                Symbol::Dah => self.decoder.add_element(false),
+                Symbol::LetterSpace | Symbol::WordSpace => {
+                    if let Some(letter) = self.decoder.finish_letter() {
+                        self.text.push_str(&letter);
+                    }
+                    if *symbol == Symbol::WordSpace && !self.text.is_empty() && !self.text.ends_with(' ') {
+                        self.text.push(' ');
+                    }
+                }
+            }
+        }
+        let excess = self.text.chars().count().saturating_sub(MAX_RECEIVED_CHARS);
+        if excess > 0 {
+            self.text = self.text.chars().skip(excess).collect();
+        }
+    }
+}
+
+// What the UI shows of a station
+#[derive(Debug, Clone)]
+pub struct StationInfo {
+    pub number: u8,
+    pub frequency: f32,
+    pub text: String,
+}
+
+impl StationInfo {
+    pub fn label(&self) -> String {
+        if self.number == 0 {
+            "Server".to_string()
+        } else {
+            format!("Station {}", self.number)
+        }
+    }
+}
+
+// Stations take turns above and below the sidetone pitch: 0, +60, -60, +120, ... Hz
+fn pitch_offset(index: usize) -> f32 {
+    let steps = index.div_ceil(2) as f32 * PITCH_STEP_HZ;
+    if index % 2 == 1 { steps } else { -steps }
+}
+
+#[derive(Clone)]
+pub struct MoppClient {
+    shared: Arc<Mutex<Shared>>,
+    audio: OutputStreamHandle,
+}
+
+impl MoppClient {
+    // Each station heard is mixed into `audio` at its own pitch
+    pub fn start(enabled: bool, server: String, channel: u32, audio: OutputStreamHandle) -> Self {
+        let client = MoppClient {
+            shared: Arc::new(Mutex::new(Shared {
+                enabled,
+                server,
+                channel: channel.min(MAX_CHANNEL),
+                reconnect: false,
+                status: MoppStatus::Off,
+                serial: 0,
+                letter: Vec::new(),
+                word_ended: true,
+                outgoing: VecDeque::new(),
+                stations: Vec::new(),
+                frequency: 600.0,
+                edge_ms: 5,
+                alphabet: Alphabet::Latin,
+                prefer_prosigns: false,
+            })),
+            audio,
+        };
+
+        let runner = client.clone();
+        thread::spawn(move || runner.run());
+        client
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.shared.lock().unwrap().enabled
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.enabled != enabled {
+            shared.enabled = enabled;
+            shared.reconnect = true;
+        }
+    }
+
+    pub fn server(&self) -> String {
+        self.shared.lock().unwrap().server.clone()
+    }
+
+    pub fn set_server(&self, server: &str) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.server != server.trim() {
+            shared.server = server.trim().to_string();
+            shared.reconnect = true;
+        }
+    }
+
+    pub fn channel(&self) -> u32 {
+        self.shared.lock().unwrap().channel
+    }
+
+    pub fn set_channel(&self, channel: u32) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.channel != channel.min(MAX_CHANNEL) {
+            shared.channel = channel.min(MAX_CHANNEL);
+            shared.reconnect = true;
+        }
+    }
+
+    pub fn status(&self) -> MoppStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    // Stations in the order they were first heard
+    pub fn stations(&self) -> Vec<StationInfo> {
+        let shared = self.shared.lock().unwrap();
+        shared.stations.iter()
+            .map(|station| StationInfo {
+                number: station.number,
+                frequency: shared.pitch(station),
+                text: station.text.clone(),
+            })
+            .collect()
+    }
+
+    pub fn clear_received(&self) {
+        for station in &mut self.shared.lock().unwrap().stations {
+            station.text.clear();
+        }
+    }
+
+    // Sidetone pitch and edges; each station plays at an offset from this pitch
+    pub fn set_tone(&self, frequency: f32, edge_ms: u32) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.frequency = frequency;
+        shared.edge_ms = edge_ms;
+        for station in &shared.stations {
+            station.queue.set_frequency(shared.pitch(station));
+            station.queue.set_edge_ms(edge_ms);
+        }
+    }
+
+    // Letters the station decoders use, as for the main decoder
+    pub fn set_decoding(&self, alphabet: Alphabet, prefer_prosigns: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.alphabet != alphabet || shared.prefer_prosigns != prefer_prosigns {
+            shared.alphabet = alphabet;
+            shared.prefer_prosigns = prefer_prosigns;
+            for station in &mut shared.stations {
+                station.decoder.set_alphabet(alphabet);
+                station.decoder.set_prefer_prosigns(prefer_prosigns);
+            }
+        }
+    }
+
+    // Keyed symbol from the decoder; each letter and word end goes out as a packet
+    pub fn symbol(&self, symbol: Symbol, wpm: u32) {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.status.is_connected() {
+            return;
+        }
+        let symbols = match symbol {
+            Symbol::Dit | Symbol::Dah => {
+                shared.letter.push(symbol);
+                shared.word_ended = false;
+                return;
+            }
+            Symbol::LetterSpace if shared.letter.is_empty() => return,
+            Symbol::WordSpace if shared.word_ended => return,
+            Symbol::LetterSpace => {
+                let mut letter = std::mem::take(&mut shared.letter);
+                letter.push(Symbol::LetterSpace);
+                letter
+            }
+            Symbol::WordSpace => {
+                shared.word_ended = true;
+                let mut letter = std::mem::take(&mut shared.letter);
+                letter.push(Symbol::WordSpace);
+                letter
+            }
+        };
+        let packet = MoppPacket { serial: shared.serial, wpm, symbols };
+        shared.serial = (shared.serial + 1) & 0x3F;
+        shared.outgoing.push_back(packet.encode());
+    }
+
+    fn set_status(&self, status: MoppStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self) {
+        loop {
+            let (enabled, server, channel) = {
+                let mut shared = self.shared.lock().unwrap();
+                shared.reconnect = false;
+                (shared.enabled, shared.server.clone(), shared.channel)
+            };
+
+            if enabled {
+                match open_socket(&server) {
+                    Ok((socket, address)) => {
+                        println!("MOPP server: {} (channel {})", server, channel);
+                        self.set_status(MoppStatus::Joined(server.clone()));
+                        if let Err(e) = self.session(&socket, address, channel) {
+                            eprintln!("MOPP error: {}", e);
+                            self.set_status(MoppStatus::Error(e.to_string()));
+                        }
+                    }
+                    Err(e) => self.set_status(MoppStatus::Error(format!("{}: {}", server, e))),
+                }
+                let mut shared = self.shared.lock().unwrap();
+                shared.outgoing.clear();
+                shared.letter.clear();
+                shared.word_ended = true;
+                // Station numbers belong to the server; their sounds end once played out
+                for station in shared.stations.drain(..) {
+                    station.queue.close();
+                }
+            } else {
+                self.set_status(MoppStatus::Off);
+            }
+
+            // Retry after an error, or wait to be enabled
+            for _ in 0..RETRY_MS / 50 {
+                if self.shared.lock().unwrap().reconnect {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    // Joined to one server: returns when disabled or moved, or on error
+    fn session(&self, socket: &UdpSocket, server: SocketAddr, channel: u32) -> io::Result<()> {
+        let mut last_sent: Option<Instant> = None;
+        let mut buffer = [0u8; 512];
+
+        loop {
+            let (packet, reconnect) = {
+                let mut shared = self.shared.lock().unwrap();
+                (shared.outgoing.pop_front(), shared.reconnect)
+            };
+            if reconnect {
+                return Ok(());
+            }
+
+            if last_sent.is_none_or(|sent| sent.elapsed().as_secs() >= REJOIN_SECS) {
+                send_join(socket, server, self.next_serial(), channel)?;
+                last_sent = Some(Instant::now());
+            }
+            if let Some(packet) = packet {
+                socket.send_to(&packet, server)?;
+                last_sent = Some(Instant::now());
+            }
+
+            // The read timeout paces the loop
+            if let Some((number, packet)) = receive_packet(socket, server, &mut buffer)? {
+                self.receive(number, &packet);
+            }
+        }
+    }
+
+    fn next_serial(&self) -> u8 {
+        let mut shared = self.shared.lock().unwrap();
+        let serial = shared.serial;
+        shared.serial = (serial + 1) & 0x3F;
+        serial
+    }
+
+    fn receive(&self, number: u8, packet: &MoppPacket) {
+        let mut shared = self.shared.lock().unwrap();
+        let index = match shared.stations.iter().position(|station| station.number == number) {
+            Some(index) => index,
+            None => {
+                let station = self.new_station(&shared, number);
+                shared.stations.push(station);
+                shared.stations.len() - 1
+            }
+        };
+        shared.stations[index].receive(packet);
+    }
+
+    // First packet from a station: its tone is mixed in from now on
+    fn new_station(&self, shared: &Shared, number: u8) -> Station {
+        let pitch_offset = pitch_offset(shared.stations.len());
+        let queue = ToneQueue::new((shared.frequency + pitch_offset).max(MIN_PITCH_HZ), shared.edge_ms);
+        if let Err(e) = self.audio.play_raw(queue.source()) {
+            eprintln!("No audio for station {}: {}", number, e);
+        }
+
+        // Letter ends come with the packets, no gaps are timed here
+        let mut decoder = MorseDecoder::new(20);
+        decoder.set_word_space(false, 7.0);
+        decoder.set_alphabet(shared.alphabet);
+        decoder.set_prefer_prosigns(shared.prefer_prosigns);
+
+        Station {
+            number,
+            pitch_offset,
+            queue,
+            playback: Playback::default(),
+            decoder,
+            text: String::new(),
+        }
+    }
+}
+
+// Turns received symbols back into tones: the standard 1-3-7 spacing at the packet's speed
+#[derive(Default)]
+struct Playback {
+    gap_dits: u32,  // Spacing already queued after the last element
+}
+
+impl Playback {
+    fn play(&mut self, output: &ToneQueue, packet: &MoppPacket) {
+        let dit_ms = 1200.0 / packet.wpm.clamp(5, MAX_WPM) as f64;
+        let has_elements = packet.symbols.iter().any(|s| matches!(s, Symbol::Dit | Symbol::Dah));
+        if output.is_idle() {
+            self.gap_dits = 7;
+            if has_elements {
+                output.push(false, PLAYOUT_DELAY_MS);
+            }
+        }
+
+        for symbol in &packet.symbols {
+            let (mark_dits, gap_dits) = match symbol {
+                Symbol::Dit => (1, 1),
+                Symbol::Dah => (3, 1),
+                Symbol::LetterSpace => (0, 3),
+                Symbol::WordSpace => (0, 7),
+            };
+            if mark_dits > 0 {
+                output.push(true, dit_ms * mark_dits as f64);
+                output.push(false, dit_ms);
+                self.gap_dits = 1;
+            } else if gap_dits > self.gap_dits {
+                // Stretch the gap already queued up to the letter or word space
+                output.push(false, dit_ms * (gap_dits - self.gap_dits) as f64);
+                self.gap_dits = gap_dits;
+            }
+        }
+    }
+}
+
+// The join word, as one packet
+fn send_join(socket: &UdpSocket, server: SocketAddr, serial: u8, channel: u32) -> io::Result<()> {
+    socket.send_to(&MoppPacket::from_text(serial, 20, &join_text(channel)).encode(), server)?;
+    Ok(())
+}
+
+// Next packet and its sending station; None when the read times out, or the bytes are not
+// a MOPP packet from the server's host
+fn receive_packet(socket: &UdpSocket, server: SocketAddr, buffer: &mut [u8]) -> io::Result<Option<(u8, MoppPacket)>> {
+    match socket.recv_from(buffer) {
+        Ok((length, from)) => Ok(station_number(server, from)
+            .and_then(|number| MoppPacket::decode(&buffer[..length]).map(|packet| (number, packet)))),
+        // Windows reports an unreachable server on the next receive
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                           | io::ErrorKind::ConnectionReset) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Not connected to the server: the practice net relay answers from other ports too
+fn open_socket(server: &str) -> io::Result<(UdpSocket, SocketAddr)> {
+    let address = server.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not found"))?;
+    let local = if address.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(POLL))?;
+    Ok((socket, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = MoppPacket::from_text(5, 25, "CQ DE DL1ABC");
+        let decoded = MoppPacket::decode(&packet.encode()).unwrap();
+        // The padding at the end is not read as extra letter ends
+        assert_eq!(decoded, packet);
+        assert_eq!(decoded.text(), "CQ DE DL1ABC ");
+
+        // Serial numbers have 6 bits, speeds stop at 63 WPM
+        let decoded = MoppPacket::decode(&MoppPacket::from_text(70, 80, "E").encode()).unwrap();
+        assert_eq!((decoded.serial, decoded.wpm, decoded.text().as_str()), (6, 63, "E "));
+    }
+
+    #[test]
+    fn malformed_packets() {
+        assert_eq!(MoppPacket::decode(&[]), None);
+        assert_eq!(MoppPacket::decode(&[0x45]), None);
+        // Other protocol versions
+        assert_eq!(MoppPacket::decode(&[0x00, 0x50, 0x40]), None);
+        assert_eq!(MoppPacket::decode(&[0xC5, 0x50, 0x40]), None);
+        // A header on its own is an empty packet
+        let empty = MoppPacket::decode(&[0x45, 0x50]).unwrap();
+        assert_eq!((empty.serial, empty.wpm, empty.symbols.len()), (5, 20, 0));
+    }
+
+    #[test]
+    fn join_words() {
+        for channel in [0, 3, MAX_CHANNEL] {
+            assert_eq!(join_channel(&MoppPacket::from_text(1, 20, &join_text(channel))), Some(channel));
+        }
+        assert_eq!(join_channel(&MoppPacket::from_text(1, 20, "hi 100")), None);
+        assert_eq!(join_channel(&MoppPacket::from_text(1, 20, "hi there")), None);
+        // Keyed text comes a letter per packet, without the word end
+        let mut keyed = MoppPacket::from_text(1, 20, "hi");
+        keyed.symbols.pop();
+        assert_eq!(join_channel(&keyed), None);
+    }
+
+    #[test]
+    fn station_numbers_from_ports() {
+        let server: SocketAddr = "192.168.1.20:7373".parse().unwrap();
+        let from = |address: &str| station_number(server, address.parse().unwrap());
+        assert_eq!(from("192.168.1.20:7373"), Some(0));
+        assert_eq!(from("192.168.1.20:7374"), Some(1));
+        assert_eq!(from("192.168.1.20:7628"), Some(255));
+        // Other ports of the server count as the server itself, other hosts not at all
+        assert_eq!(from("192.168.1.20:7629"), Some(0));
+        assert_eq!(from("192.168.1.20:5000"), Some(0));
+        assert_eq!(from("192.168.1.21:7374"), None);
+    }
+
+    #[test]
+    fn join_over_udp_echo() {
+        // An echo server, and a second socket answering as practice net station 4
+        let echo = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = echo.local_addr().unwrap();
+        let station = UdpSocket::bind(("127.0.0.1", server.port() + 4)).unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((length, from)) = echo.recv_from(&mut buffer) {
+                echo.send_to(&buffer[..length], from).unwrap();
+                station.send_to(&buffer[..length], from).unwrap();
+            }
+        });
+
+        let (socket, address) = open_socket(&server.to_string()).unwrap();
+        assert_eq!(address, server);
+        send_join(&socket, address, 9, 3).unwrap();
+        let mut buffer = [0u8; 512];
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while received.len() < 2 && Instant::now() < deadline {
+            if let Some(packet) = receive_packet(&socket, address, &mut buffer).unwrap() {
+                received.push(packet);
+            }
+        }
+        assert_eq!(received.len(), 2);
+        received.sort_by_key(|(number, _)| *number);
+        for (expected_station, (station, packet)) in [0, 4].into_iter().zip(received) {
+            assert_eq!(station, expected_station);
+            assert_eq!((packet.serial, join_channel(&packet)), (9, Some(3)));
+        }
+    }
+}
@@ -0,0 +1,546 @@
+// Morse code decoder
+// Collects dits and dahs into a sequence and decodes it once the letter gap has passed,
+// and reports a word space once the longer word gap has passed as well.
+// Hand-keyed elements (straight key, bug) also feed a speed tracker that estimates the
+// sender's real dit length, so thresholds follow the sender instead of the WPM slider.
+// A listener can follow the same elements and gaps, e.g. to send them over the network.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::morse_player::farnsworth_extra_per_unit_ms;
+use crate::morse_table::{Alphabet, MorseTable, WABUN_START, WABUN_END};
+
+// Number of recent key-down / key-up durations used for speed tracking
+const TIMING_HISTORY_LEN: usize = 30;
+
+// Keep estimates inside a sane 5-60 WPM range
+const MIN_DIT_MS: f32 = 20.0;
+const MAX_DIT_MS: f32 = 240.0;
+
+// What the decoder has seen, in order: elements and the gaps that end letters and words
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symbol {
+    Dit,
+    Dah,
+    LetterSpace,
+    WordSpace,
+}
+
+// Called with each symbol and the speed the decoder is timing with, in WPM
+pub type SymbolListener = Arc<dyn Fn(Symbol, u32) + Send + Sync>;
+
+pub struct MorseDecoder {
+    pub current_sequence: String,
+    table: Arc<MorseTable>,
+    alphabet: Alphabet,
+    kana_mode: bool,        // Wabun: between <DO> and <SN>
+    prefer_prosigns: bool,  // Decode -...- as <BT> instead of =, .-.-. as <AR> instead of +
+    last_element_time: Option<Instant>,  // Start of the last element
+    wpm: u32,
+    dit_length_ms: u32,
+    letter_space_ms: u32,
+    // Automatic word spacing
+    auto_word_space: bool,
+    word_space_dits: f32,     // Word gap in dits (standard: 7)
+    farnsworth_wpm: u32,      // Effective speed, stretches the word gap like the player does
+    word_space_ms: u32,
+    word_gap_start: Option<Instant>,  // Start of the last element of the last decoded letter
+    // Adaptive speed tracking
+    adaptive: bool,
+    last_key_up: Option<Instant>,
+    mark_history: VecDeque<f32>,   // Key-down durations (ms)
+    space_history: VecDeque<f32>,  // Key-up durations between elements (ms)
+    estimated_dit_ms: Option<f32>,
+    listener: Option<SymbolListener>,
+}
+
+impl MorseDecoder {
+    pub fn new(wpm: u32) -> Self {
+        let dit_length_ms = 1200 / wpm.max(1);
+        let letter_space_ms = dit_length_ms * 5;
+
+        MorseDecoder {
+            current_sequence: String::new(),
+            table: MorseTable::standard(),
+            alphabet: Alphabet::Latin,
+            kana_mode: true,
+            prefer_prosigns: false,
+            last_element_time: None,
+            wpm,
+            dit_length_ms,
+            letter_space_ms,
+            auto_word_space: true,
+            word_space_dits: 7.0,
+            farnsworth_wpm: wpm,
+            word_space_ms: dit_length_ms * 7,
+            word_gap_start: None,
+            adaptive: true,
+            last_key_up: None,
+            mark_history: VecDeque::with_capacity(TIMING_HISTORY_LEN),
+            space_history: VecDeque::with_capacity(TIMING_HISTORY_LEN),
+            estimated_dit_ms: None,
+            listener: None,
+        }
+    }
+
+    pub fn update_wpm(&mut self, wpm: u32) {
+        self.wpm = wpm.clamp(5, 40);
+        self.update_thresholds();
+    }
+
+    pub fn update_farnsworth(&mut self, farnsworth_wpm: u32) {
+        self.farnsworth_wpm = farnsworth_wpm;
+        self.update_thresholds();
+    }
+
+    pub fn set_word_space(&mut self, enabled: bool, word_space_dits: f32) {
+        self.auto_word_space = enabled;
+        self.word_space_dits = word_space_dits;
+        if !enabled {
+            self.word_gap_start = None;
+        }
+        self.update_thresholds();
+    }
+
+    pub fn set_alphabet(&mut self, alphabet: Alphabet) {
+        self.alphabet = alphabet;
+        self.table = MorseTable::for_alphabet(alphabet);
+        self.kana_mode = true;
+    }
+
+    pub fn set_prefer_prosigns(&mut self, prefer_prosigns: bool) {
+        self.prefer_prosigns = prefer_prosigns;
+    }
+
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+        self.update_thresholds();
+    }
+
+    pub fn set_listener(&mut self, listener: Option<SymbolListener>) {
+        self.listener = listener;
+    }
+
+    fn emit(&self, symbol: Symbol) {
+        if let Some(listener) = &self.listener {
+            listener(symbol, (1200 / self.dit_length_ms.max(1)).max(1));
+        }
+    }
+
+    pub fn dit_length_ms(&self) -> u32 {
+        self.dit_length_ms
+    }
+
+    pub fn letter_space_ms(&self) -> u32 {
+        self.letter_space_ms
+    }
+
+    pub fn word_space_ms(&self) -> u32 {
+        self.word_space_ms
+    }
+
+    // Sender speed estimated from hand-keyed elements, if any have been seen
+    pub fn estimated_wpm(&self) -> Option<u32> {
+        self.estimated_dit_ms.map(|dit| (1200.0 / dit).round() as u32)
+    }
+
+    // Forget the tracked speed, e.g. when a different operator takes the key
+    pub fn reset_speed_tracking(&mut self) {
+        self.mark_history.clear();
+        self.space_history.clear();
+        self.estimated_dit_ms = None;
+        self.last_key_up = None;
+        self.update_thresholds();
+    }
+
+    fn update_thresholds(&mut self) {
+        self.dit_length_ms = match self.estimated_dit_ms {
+            Some(dit) if self.adaptive => dit.round() as u32,
+            _ => 1200 / self.wpm.max(1),
+        };
+        self.letter_space_ms = self.dit_length_ms * 5;
+
+        // Word gap counted from the start of the last element, like the letter gap.
+        // Farnsworth spacing stretches every spacing unit, so stretch the word gap too.
+        let extra_ms = farnsworth_extra_per_unit_ms(self.wpm, self.farnsworth_wpm);
+        let word_space = self.word_space_dits * (self.dit_length_ms as f32 + extra_ms);
+        self.word_space_ms = (word_space as u32).max(self.letter_space_ms + self.dit_length_ms);
+    }
+
+    // Element from the automatic keyer, sent at exactly the slider speed
+    pub fn add_element(&mut self, is_dit: bool) {
+        if is_dit {
+            self.current_sequence.push('.');
+            self.emit(Symbol::Dit);
+        } else {
+            self.current_sequence.push('-');
+            self.emit(Symbol::Dah);
+        }
+        self.last_element_time = Some(Instant::now());
+        self.word_gap_start = None;
+    }
+
+    // Element from a manual contact (straight key / bug), classified by its key-down time.
+    // Called on key-up.
+    pub fn add_timed_element(&mut self, duration_ms: u32) {
+        let key_up = Instant::now();
+        self.add_timed(duration_ms, key_up);
+        // Letter gap counts from the start of the element, as for keyer elements
+        self.last_element_time = Some(key_up - Duration::from_millis(duration_ms as u64));
+    }
+
+    // Timed element whose key-up was at the given time, for keying that is not happening
+    // now (a recording, buffered audio).
+    //
+    // The letter gap is timed from the start of the last element (letter_space_ms, 5 dits).
+    // After a dit that is 4 dits past key-up, but machine-sent code starts the next letter
+    // 3 dits after key-up, and it is only checked for as the next element arrives - so the
+    // letters would run together. The start is therefore back-dated to 3 dits before
+    // key-up whatever the element's length, as if it were a dah: the letter ends 2 dits
+    // after key-up, between the 1-dit element gap and the 3-dit letter gap, and the word
+    // (word space - 3) dits after it.
+    pub fn add_timed_element_at(&mut self, duration_ms: u32, key_up: Instant) {
+        self.add_timed(duration_ms, key_up);
+        self.last_element_time = Some(key_up - Duration::from_millis(self.dit_length_ms as u64 * 3));
+    }
+
+    fn add_timed(&mut self, duration_ms: u32, key_up: Instant) {
+        let key_down = key_up - Duration::from_millis(duration_ms as u64);
+
+        // Key-up time since the previous element, ignoring pauses between words
+        if let Some(last_up) = self.last_key_up {
+            let space_ms = key_down.saturating_duration_since(last_up).as_millis() as f32;
+            if space_ms < self.dit_length_ms as f32 * 7.0 {
+                push_limited(&mut self.space_history, space_ms);
+            }
+        }
+        push_limited(&mut self.mark_history, duration_ms as f32);
+        self.last_key_up = Some(key_up);
+
+        if self.adaptive {
+            self.estimate_speed();
+        }
+
+        // Halfway between a 1-dit dit and a 3-dit dah
+        let is_dit = duration_ms < self.dit_length_ms * 2;
+        self.add_element(is_dit);
+    }
+
+    // Estimate the dit length by splitting key-down times into a dit and a dah cluster,
+    // refined with the gaps between elements (which should be one dit long)
+    fn estimate_speed(&mut self) {
+        let current_dit = self.dit_length_ms as f32;
+
+        let mark_dit = match two_means(&self.mark_history) {
+            // Both dits and dahs present - weight each cluster by its size
+            (Some((short_mean, short_n)), Some((long_mean, long_n))) if long_mean > short_mean * 2.0 => {
+                (short_mean * short_n as f32 + long_mean / 3.0 * long_n as f32)
+                    / (short_n + long_n) as f32
+            }
+            // Only one kind of element so far - decide which from the current estimate
+            (Some(_), _) => {
+                let mean = mean(&self.mark_history);
+                if mean < current_dit * 2.0 { mean } else { mean / 3.0 }
+            }
+            _ => return,
+        };
+
+        // Element gaps form the short cluster of key-up times
+        let gap_dit = match two_means(&self.space_history) {
+            (Some((short_mean, _)), Some((long_mean, _))) if long_mean > short_mean * 2.0 => Some(short_mean),
+            (Some(_), _) => Some(mean(&self.space_history)).filter(|&m| m < current_dit * 2.0),
+            _ => None,
+        };
+
+        let dit = match gap_dit {
+            Some(gap) => mark_dit * 0.7 + gap * 0.3,
+            None => mark_dit,
+        };
+
+        self.estimated_dit_ms = Some(dit.clamp(MIN_DIT_MS, MAX_DIT_MS));
+        self.update_thresholds();
+    }
+
+    pub fn check_timeout(&mut self) -> Option<String> {
+        self.check_timeout_at(Instant::now())
+    }
+
+    // Letter or word ended by the given time, for elements added with add_timed_element_at
+    pub fn check_timeout_at(&mut self, now: Instant) -> Option<String> {
+        if let Some(last_time) = self.last_element_time {
+            let elapsed = now.saturating_duration_since(last_time).as_millis() as u32;
+
+            if elapsed > self.letter_space_ms && !self.current_sequence.is_empty() {
+                let decoded = self.decode_sequence();
+                self.current_sequence.clear();
+                self.last_element_time = None;
+                self.emit(Symbol::LetterSpace);
+                if self.auto_word_space {
+                    self.word_gap_start = Some(last_time);
+                }
+                return Some(decoded);
+            }
+        }
+
+        // Keep waiting after a letter - a long enough pause ends the word
+        if let Some(gap_start) = self.word_gap_start {
+            if now.saturating_duration_since(gap_start).as_millis() as u32 > self.word_space_ms {
+                self.word_gap_start = None;
+                self.emit(Symbol::WordSpace);
+                return Some(" ".to_string());
+            }
+        }
+        None
+    }
+
+    // Decode the elements so far right away, for input that marks its own letter ends
+    // (e.g. network packets) instead of being timed here
+    pub fn finish_letter(&mut self) -> Option<String> {
+        if self.current_sequence.is_empty() {
+            return None;
+        }
+        let decoded = self.decode_sequence();
+        self.current_sequence.clear();
+        self.last_element_time = None;
+        self.emit(Symbol::LetterSpace);
+        Some(decoded)
+    }
+
+    fn decode_sequence(&mut self) -> String {
+        let code = self.current_sequence.as_str();
+
+        // Wabun shift prosigns only switch tables, they are not text
+        let table = if self.alphabet == Alphabet::Wabun {
+            if self.table.encode(WABUN_START) == Some(code) {
+                self.kana_mode = true;
+                return String::new();
+            }
+            if self.table.encode(WABUN_END) == Some(code) {
+                self.kana_mode = false;
+                return String::new();
+            }
+            if self.kana_mode { self.table.clone() } else { MorseTable::standard() }
+        } else {
+            self.table.clone()
+        };
+
+        let decoded = if self.prefer_prosigns {
+            table.decode_preferring_prosigns(code)
+        } else {
+            table.decode(code)
+        };
+        decoded.unwrap_or("?").to_string()
+    }
+}
+
+fn push_limited(history: &mut VecDeque<f32>, value: f32) {
+    if history.len() >= TIMING_HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+fn mean(values: &VecDeque<f32>) -> f32 {
+    values.iter().sum::<f32>() / values.len().max(1) as f32
+}
+
+// Mean and number of values in a cluster
+type Cluster = (f32, usize);
+
+// One-dimensional 2-means clustering.
+// Returns the short and the long cluster; long is None if all values agree.
+fn two_means(values: &VecDeque<f32>) -> (Option<Cluster>, Option<Cluster>) {
+    let (min, max) = values.iter().fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+    if values.is_empty() {
+        return (None, None);
+    }
+
+    let mut short_center = min;
+    let mut long_center = max;
+    let mut short = (0.0, 0);
+    let mut long = (0.0, 0);
+
+    for _ in 0..10 {
+        let split = (short_center + long_center) / 2.0;
+        short = (0.0, 0);
+        long = (0.0, 0);
+        for &v in values {
+            if v <= split {
+                short = (short.0 + v, short.1 + 1);
+            } else {
+                long = (long.0 + v, long.1 + 1);
+            }
+        }
+        if long.1 == 0 {
+            break;
+        }
+        short_center = short.0 / short.1 as f32;
+        long_center = long.0 / long.1 as f32;
+    }
+
+    let short_cluster = (short.1 > 0).then(|| (short.0 / short.1 as f32, short.1));
+    let long_cluster = (long.1 > 0).then(|| (long.0 / long.1 as f32, long.1));
+    (short_cluster, long_cluster)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[f32]) -> VecDeque<f32> {
+        values.iter().copied().collect()
+    }
+
+    // Key the elements (key-down, key-up after it, in ms) starting at `start`; returns
+    // the time the keying ends
+    fn key(decoder: &mut MorseDecoder, start: Instant, elements: &[(u32, u32)]) -> Instant {
+        let mut at = start;
+        for &(down, up) in elements {
+            at += Duration::from_millis(down as u64);
+            decoder.add_timed_element_at(down, at);
+            at += Duration::from_millis(up as u64);
+        }
+        at
+    }
+
+    // Dits and dahs at the given dit length, one dit apart
+    fn elements(code: &str, dit_ms: u32) -> Vec<(u32, u32)> {
+        code.chars().map(|c| (if c == '.' { dit_ms } else { dit_ms * 3 }, dit_ms)).collect()
+    }
+
+    #[test]
+    fn two_means_splits_dits_and_dahs() {
+        let (short, long) = two_means(&values(&[58.0, 62.0, 180.0, 60.0, 184.0, 176.0, 60.0]));
+        assert_eq!(short, Some((60.0, 4)));
+        assert_eq!(long, Some((180.0, 3)));
+    }
+
+    #[test]
+    fn two_means_of_one_kind() {
+        assert_eq!(two_means(&values(&[60.0; 5])), (Some((60.0, 5)), None));
+        assert_eq!(two_means(&values(&[])), (None, None));
+    }
+
+    #[test]
+    fn tracks_a_slower_sender() {
+        // Slider at 20 WPM (60 ms dits), sender at 15 WPM (80 ms dits)
+        let mut decoder = MorseDecoder::new(20);
+        let start = Instant::now();
+        let end = key(&mut decoder, start, &elements("-.-..--.", 80));
+        assert_eq!(decoder.estimated_wpm(), Some(15));
+        assert_eq!(decoder.dit_length_ms(), 80);
+        // A 160 ms dah would be a dit at 20 WPM
+        assert_eq!(decoder.current_sequence, "-.-..--.");
+        assert!(decoder.check_timeout_at(end + Duration::from_millis(400)).is_some());
+    }
+
+    #[test]
+    fn tracks_all_dits_and_all_dahs() {
+        // Only one kind of element: which one follows from the current dit length
+        let mut decoder = MorseDecoder::new(20);
+        key(&mut decoder, Instant::now(), &elements(".....", 80));
+        assert_eq!(decoder.estimated_wpm(), Some(15));
+        assert_eq!(decoder.current_sequence, ".....");
+
+        let mut decoder = MorseDecoder::new(20);
+        key(&mut decoder, Instant::now(), &elements("-----", 80));
+        assert_eq!(decoder.estimated_wpm(), Some(15));
+        assert_eq!(decoder.current_sequence, "-----");
+    }
+
+    #[test]
+    fn estimate_stays_in_range() {
+        let mut decoder = MorseDecoder::new(20);
+        key(&mut decoder, Instant::now(), &elements("....", 5));
+        assert_eq!(decoder.dit_length_ms(), MIN_DIT_MS as u32);
+    }
+
+    #[test]
+    fn reset_and_fixed_speed() {
+        let mut decoder = MorseDecoder::new(20);
+        key(&mut decoder, Instant::now(), &elements("-.-.", 80));
+        assert_eq!(decoder.dit_length_ms(), 80);
+
+        // Switched off, the slider speed applies; the estimate is kept for later
+        decoder.set_adaptive(false);
+        assert_eq!(decoder.dit_length_ms(), 60);
+        decoder.set_adaptive(true);
+        assert_eq!(decoder.dit_length_ms(), 80);
+
+        // A new keyer mode starts over from the slider
+        decoder.reset_speed_tracking();
+        assert_eq!(decoder.estimated_wpm(), None);
+        assert_eq!((decoder.dit_length_ms(), decoder.letter_space_ms()), (60, 300));
+
+        // Without tracking, elements are classified at the slider speed: 10 WPM dits
+        // are too long for 20 WPM
+        let mut decoder = MorseDecoder::new(20);
+        decoder.set_adaptive(false);
+        key(&mut decoder, Instant::now(), &elements("-.-.", 120));
+        assert_eq!(decoder.estimated_wpm(), None);
+        assert_eq!(decoder.current_sequence, "----");
+    }
+
+    // A fixed-speed decoder with one dit keyed; returns its key-up time
+    fn after_a_dit(wpm: u32) -> (MorseDecoder, Instant) {
+        let mut decoder = MorseDecoder::new(wpm);
+        decoder.set_adaptive(false);
+        let dit_ms = 1200 / wpm;
+        let start = Instant::now();
+        decoder.add_timed_element_at(dit_ms, start + Duration::from_millis(dit_ms as u64));
+        (decoder, start + Duration::from_millis(dit_ms as u64))
+    }
+
+    fn at(key_up: Instant, ms: f32) -> Instant {
+        key_up + Duration::from_millis(ms.round() as u64)
+    }
+
+    #[test]
+    fn word_space_after_the_word_gap_only() {
+        let (mut decoder, key_up) = after_a_dit(20);
+        assert_eq!(decoder.word_space_ms(), 420);
+
+        // Letter gap: the letter, no space
+        assert_eq!(decoder.check_timeout_at(at(key_up, 180.0)).as_deref(), Some("E"));
+        assert_eq!(decoder.check_timeout_at(at(key_up, 180.0)), None);
+        // Timed from the back-dated element start, 3 dits before key-up
+        assert_eq!(decoder.check_timeout_at(at(key_up, 240.0)), None);
+        assert_eq!(decoder.check_timeout_at(at(key_up, 250.0)).as_deref(), Some(" "));
+        // One space per gap
+        assert_eq!(decoder.check_timeout_at(at(key_up, 2000.0)), None);
+    }
+
+    #[test]
+    fn configured_word_space_dits() {
+        let (mut decoder, key_up) = after_a_dit(20);
+        decoder.set_word_space(true, 10.0);
+        assert_eq!(decoder.word_space_ms(), 600);
+        assert_eq!(decoder.check_timeout_at(at(key_up, 180.0)).as_deref(), Some("E"));
+        // A standard 7-dit word gap is not a word space any more
+        assert_eq!(decoder.check_timeout_at(at(key_up, 400.0)), None);
+        assert_eq!(decoder.check_timeout_at(at(key_up, 430.0)).as_deref(), Some(" "));
+    }
+
+    #[test]
+    fn farnsworth_stretches_the_word_gap() {
+        let (mut decoder, key_up) = after_a_dit(20);
+        decoder.update_farnsworth(10);
+        let unit = 60.0 + farnsworth_extra_per_unit_ms(20, 10);
+        assert_eq!(decoder.word_space_ms(), (7.0 * unit) as u32);
+
+        // A stretched letter gap is far longer than 7 plain dits, but still no word space
+        assert!(3.0 * unit > 420.0);
+        assert_eq!(decoder.check_timeout_at(at(key_up, 3.0 * unit)).as_deref(), Some("E"));
+        assert_eq!(decoder.check_timeout_at(at(key_up, 3.0 * unit)), None);
+        assert_eq!(decoder.check_timeout_at(at(key_up, 7.0 * unit)).as_deref(), Some(" "));
+    }
+
+    #[test]
+    fn word_space_detection_off() {
+        let (mut decoder, key_up) = after_a_dit(20);
+        decoder.set_word_space(false, 7.0);
+        assert_eq!(decoder.check_timeout_at(at(key_up, 180.0)).as_deref(), Some("E"));
+        assert_eq!(decoder.check_timeout_at(at(key_up, 5000.0)), None);
+    }
+}
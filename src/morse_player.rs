@@ -1,95 +1,104 @@
 // Add morse code player module
-use std::collections::HashMap;
-use rodio::{Sink, Source};
-use std::time::Duration;
-use std::thread;
+use rand::Rng;
+use rodio::Sink;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::band_conditions::{BandConditions, BandSource};
+use crate::envelope::DEFAULT_EDGE_MS;
+use crate::keying_output::KeyingOutput;
+use crate::morse_table::{Alphabet, MorseTable, WABUN_START, WABUN_END, is_kana};
+use crate::tone_queue::{ToneQueue, SAMPLE_RATE};
 
 pub struct MorsePlayer {
-    morse_map: HashMap<char, &'static str>,
+    table: Arc<MorseTable>,
+    alphabet: Alphabet,
     frequency: f32,
     char_wpm: u32,        // Character speed (actual morse element speed)
     effective_wpm: u32,   // Effective speed (with Farnsworth spacing)
+    edge_ms: u32,         // Rise/fall time of each tone
+    conditions: BandConditions,  // Noise, fading and interference mixed into the audio
+    keying: Option<KeyingOutput>,  // Transmitter keyed along with the playback
 }
 
 impl MorsePlayer {
-    pub fn new(frequency: f32, char_wpm: u32) -> Self {
-        Self::new_with_farnsworth(frequency, char_wpm, char_wpm)
-    }
-    
-    pub fn new_with_farnsworth(frequency: f32, char_wpm: u32, effective_wpm: u32) -> Self {
-        let mut morse_map = HashMap::new();
-        
-        // Letters
-        morse_map.insert('A', ".-");
-        morse_map.insert('B', "-...");
-        morse_map.insert('C', "-.-.");
-        morse_map.insert('D', "-..");
-        morse_map.insert('E', ".");
-        morse_map.insert('F', "..-.");
-        morse_map.insert('G', "--.");
-        morse_map.insert('H', "....");
-        morse_map.insert('I', "..");
-        morse_map.insert('J', ".---");
-        morse_map.insert('K', "-.-");
-        morse_map.insert('L', ".-..");
-        morse_map.insert('M', "--");
-        morse_map.insert('N', "-.");
-        morse_map.insert('O', "---");
-        morse_map.insert('P', ".--.");
-        morse_map.insert('Q', "--.-");
-        morse_map.insert('R', ".-.");
-        morse_map.insert('S', "...");
-        morse_map.insert('T', "-");
-        morse_map.insert('U', "..-");
-        morse_map.insert('V', "...-");
-        morse_map.insert('W', ".--");
-        morse_map.insert('X', "-..-");
-        morse_map.insert('Y', "-.--");
-        morse_map.insert('Z', "--..");
-        
-        // Numbers
-        morse_map.insert('0', "-----");
-        morse_map.insert('1', ".----");
-        morse_map.insert('2', "..---");
-        morse_map.insert('3', "...--");
-        morse_map.insert('4', "....-");
-        morse_map.insert('5', ".....");
-        morse_map.insert('6', "-....");
-        morse_map.insert('7', "--...");
-        morse_map.insert('8', "---..");
-        morse_map.insert('9', "----.");
-        
-        // Punctuation
-        morse_map.insert('.', ".-.-.-");
-        morse_map.insert(',', "--..--");
-        morse_map.insert('?', "..--..");
-        morse_map.insert('/', "-..-.");
-        
+    pub fn new_with_alphabet(frequency: f32, char_wpm: u32, effective_wpm: u32, alphabet: Alphabet) -> Self {
         MorsePlayer { 
-            morse_map,
+            table: MorseTable::for_alphabet(alphabet),
+            alphabet,
             frequency,
             char_wpm: char_wpm.max(5),
             effective_wpm: effective_wpm.max(5).min(char_wpm), // Effective can't be faster than character
+            edge_ms: DEFAULT_EDGE_MS,
+            conditions: BandConditions::default(),
+            keying: None,
         }
     }
     
+    pub fn set_edge_ms(&mut self, edge_ms: u32) {
+        self.edge_ms = edge_ms;
+    }
+    
+    pub fn set_band_conditions(&mut self, conditions: BandConditions) {
+        self.conditions = conditions;
+    }
+    
+    // Key the transmitter with what play_morse plays (the clean signal only)
+    pub fn set_keying_output(&mut self, output: KeyingOutput) {
+        self.keying = Some(output);
+    }
+    
     pub fn text_to_morse(&self, text: &str) -> Vec<MorseElement> {
         let mut elements = Vec::new();
+        let mut rest = text;
+        // Wabun starts in kana; <DO> and <SN> are inserted where the script changes
+        let mut kana_mode = true;
         
-        for ch in text.chars() {
+        while let Some(ch) = rest.chars().next() {
             if ch == ' ' {
                 elements.push(MorseElement::WordSpace);
+                rest = &rest[1..];
+                continue;
+            }
+            
+            // Prosign written as <XX> - one character with no gaps between its letters
+            if let Some(token) = prosign_token(rest) {
+                rest = &rest[token.len()..];
+                if let Some(morse) = self.prosign_code(token) {
+                    push_code(&mut elements, &morse);
+                    elements.push(MorseElement::LetterSpace);
+                }
                 continue;
             }
             
-            if let Some(morse) = self.morse_map.get(&ch.to_ascii_uppercase()) {
-                for symbol in morse.chars() {
-                    match symbol {
-                        '.' => elements.push(MorseElement::Dit),
-                        '-' => elements.push(MorseElement::Dah),
-                        _ => {}
+            rest = &rest[ch.len_utf8()..];
+            
+            if self.alphabet == Alphabet::Wabun {
+                let shift = if is_kana(ch) && !kana_mode {
+                    Some(WABUN_START)
+                } else if ch.is_ascii_alphabetic() && kana_mode {
+                    Some(WABUN_END)
+                } else {
+                    None
+                };
+                if let Some(morse) = shift.and_then(|prosign| self.table.encode(prosign)) {
+                    push_code(&mut elements, morse);
+                    elements.push(MorseElement::LetterSpace);
+                    kana_mode = !kana_mode;
+                }
+                
+                // Latin letters share their codes with kana, so look them up in the standard table
+                if !kana_mode {
+                    if let Some(morse) = MorseTable::standard().encode_char(ch) {
+                        push_code(&mut elements, morse);
+                        elements.push(MorseElement::LetterSpace);
                     }
+                    continue;
                 }
+            }
+            
+            if let Some(morse) = self.table.encode_char(ch) {
+                push_code(&mut elements, morse);
                 elements.push(MorseElement::LetterSpace);
             }
         }
@@ -97,75 +106,171 @@ impl MorsePlayer {
         elements
     }
     
-    pub fn play_morse(&self, sink: &Sink, text: &str) {
-        // Character timing (dit/dah speed)
-        let dit_ms = 1200 / self.char_wpm.max(1);
+    // Code for a <XX> prosign: from the table if listed, otherwise its letters run together
+    fn prosign_code(&self, token: &str) -> Option<String> {
+        if let Some(code) = self.table.encode(token) {
+            return Some(code.to_string());
+        }
         
-        // Calculate Farnsworth spacing if effective speed is slower
-        let (letter_space_ms, word_space_ms) = if self.effective_wpm < self.char_wpm {
-            // Farnsworth timing: keep characters fast, extend spacing
-            // PARIS has 31 element dits and 19 spacing dits (4 letter spaces @ 3 + 1 word space @ 7)
-            
-            // Time for character elements at character speed
-            let char_time_per_paris = 31.0 * 1.2 / self.char_wpm as f32;  // seconds
-            
-            // Total time per PARIS at effective speed
-            let total_time_per_paris = 60.0 / self.effective_wpm as f32;  // seconds
-            
-            // Extra time that needs to be distributed across spacing
-            let extra_spacing_time = total_time_per_paris - char_time_per_paris;  // seconds
-            
-            // Distribute extra time proportionally across 19 spacing units in PARIS
-            let extra_per_spacing_unit = extra_spacing_time / 19.0;  // seconds per unit
-            
-            // Calculate actual spacing times
-            // Standard letter space = 3 dits, word space = 7 dits
-            let letter_space = (3.0 * dit_ms as f32 / 1000.0 + extra_per_spacing_unit * 3.0) * 1000.0;
-            let word_space = (7.0 * dit_ms as f32 / 1000.0 + extra_per_spacing_unit * 7.0) * 1000.0;
-            
-            (letter_space as u32, word_space as u32)
-        } else {
-            // Standard timing (3 dit for letter, 7 dit for word)
-            (dit_ms * 3, dit_ms * 7)
+        // Multi-letter symbols such as CH in the extended Latin alphabet
+        let letters = &token[1..token.len() - 1];
+        if let Some(code) = self.table.encode(letters) {
+            return Some(code.to_string());
+        }
+        
+        letters.chars()
+            .map(|ch| self.table.encode_char(ch))
+            .collect::<Option<String>>()
+    }
+    
+    // Play the text and return once it has finished sounding
+    pub fn play_morse(&self, sink: &Sink, text: &str) {
+        sink.append(self.render(text, self.keying.as_ref()));
+        sink.play();
+        sink.sleep_until_end();
+    }
+    
+    // Render the text to a 16-bit mono WAV file instead of playing it
+    pub fn export_wav(&self, path: &Path, text: &str) -> Result<(), hound::Error> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
         };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for sample in self.render(text, None) {
+            writer.write_sample((sample * i16::MAX as f32) as i16)?;
+        }
+        writer.finalize()
+    }
+    
+    // Audio for the text with the band conditions applied
+    fn render(&self, text: &str, keying: Option<&KeyingOutput>) -> BandSource {
+        let queue = ToneQueue::new(self.frequency, self.edge_ms);
+        if let Some(output) = keying {
+            queue.mirror_to(output.clone());
+        }
+        self.queue_morse(&queue, text);
+        queue.close();
+        
+        let qrm = (self.conditions.qrm_strength > 0.0).then(|| self.qrm_queue(queue.queued_ms()).source());
+        BandSource::new(queue.source(), qrm, self.conditions)
+    }
+    
+    // Another station working a little off frequency, at its own speed, for at least
+    // as long as the text being played
+    fn qrm_queue(&self, duration_ms: f64) -> ToneQueue {
+        let mut rng = rand::thread_rng();
+        let offset = rng.gen_range(80.0..300.0) * if rng.gen() { 1.0 } else { -1.0 };
+        let wpm = rng.gen_range(15..30);
+        let other = MorsePlayer::new_with_alphabet((self.frequency + offset).max(200.0), wpm, wpm, Alphabet::Latin);
+        
+        let queue = ToneQueue::new(other.frequency, self.edge_ms);
+        // Start part way into a transmission
+        queue.push(false, rng.gen_range(0.0..1500.0));
+        while queue.queued_ms() < duration_ms {
+            other.queue_morse(&queue, &random_qso_text(&mut rng));
+        }
+        queue.close();
+        queue
+    }
+    
+    // Schedule the text on a tone queue with exact element and gap lengths
+    pub fn queue_morse(&self, queue: &ToneQueue, text: &str) {
+        self.queue_morse_after(queue, text, false);
+    }
+    
+    // Same, continuing text queued earlier: `after_letter` says whether the queue ends
+    // in a letter space. Returns that for the text just queued, so text can be queued
+    // a character at a time.
+    pub fn queue_morse_after(&self, queue: &ToneQueue, text: &str, after_letter: bool) -> bool {
+        // Character timing (dit/dah speed)
+        let dit_ms = 1200.0 / self.char_wpm.max(1) as f64;
         
-        let elements = self.text_to_morse(text);
+        // Farnsworth timing: keep characters fast, stretch every spacing unit.
+        // Standard letter space = 3 dits, word space = 7 dits, plus the extra time per unit
+        let extra_ms = farnsworth_extra_per_unit_ms(self.char_wpm, self.effective_wpm) as f64;
+        let letter_space_ms = 3.0 * (dit_ms + extra_ms);
+        let word_space_ms = 7.0 * (dit_ms + extra_ms);
         
-        for element in elements {
+        let mut after_letter = after_letter;
+        for element in self.text_to_morse(text) {
             match element {
                 MorseElement::Dit => {
-                    sink.stop();
-                    let tone = ToneGen::new(self.frequency, dit_ms);
-                    sink.append(tone);
-                    sink.play();
-                    thread::sleep(Duration::from_millis(dit_ms as u64));
-                    sink.stop();
-                    thread::sleep(Duration::from_millis(dit_ms as u64));
+                    queue.push(true, dit_ms);
+                    queue.push(false, dit_ms);
                 }
                 MorseElement::Dah => {
-                    sink.stop();
-                    let tone = ToneGen::new(self.frequency, dit_ms * 3);
-                    sink.append(tone);
-                    sink.play();
-                    thread::sleep(Duration::from_millis((dit_ms * 3) as u64));
-                    sink.stop();
-                    thread::sleep(Duration::from_millis(dit_ms as u64));
-                }
-                MorseElement::LetterSpace => {
-                    // Already have 1 dit space after element, add remaining
-                    let remaining = letter_space_ms.saturating_sub(dit_ms);
-                    if remaining > 0 {
-                        thread::sleep(Duration::from_millis(remaining as u64));
-                    }
-                }
-                MorseElement::WordSpace => {
-                    // Already have 1 dit space after element, add remaining
-                    let remaining = word_space_ms.saturating_sub(dit_ms);
-                    if remaining > 0 {
-                        thread::sleep(Duration::from_millis(remaining as u64));
-                    }
+                    queue.push(true, dit_ms * 3.0);
+                    queue.push(false, dit_ms);
                 }
+                // Already have 1 dit space after element, add remaining
+                MorseElement::LetterSpace => queue.push(false, letter_space_ms - dit_ms),
+                // A word space follows the last letter's space, which already counts
+                MorseElement::WordSpace if after_letter => queue.push(false, word_space_ms - letter_space_ms),
+                MorseElement::WordSpace => queue.push(false, word_space_ms - dit_ms),
             }
+            after_letter = matches!(element, MorseElement::LetterSpace);
+        }
+        after_letter
+    }
+}
+
+// Extra time (ms) added to every spacing unit so that PARIS takes as long as at the
+// effective speed while its characters are still sent at the character speed
+pub fn farnsworth_extra_per_unit_ms(char_wpm: u32, effective_wpm: u32) -> f32 {
+    let char_wpm = char_wpm.max(1);
+    let effective_wpm = effective_wpm.clamp(1, char_wpm);
+    
+    // PARIS has 31 element dits and 19 spacing dits (4 letter spaces @ 3 + 1 word space @ 7)
+    
+    // Time for character elements at character speed
+    let char_time_per_paris = 31.0 * 1.2 / char_wpm as f32;  // seconds
+    
+    // Total time per PARIS at effective speed
+    let total_time_per_paris = 60.0 / effective_wpm as f32;  // seconds
+    
+    // Time left for spacing, distributed across the 19 spacing units in PARIS
+    let spacing_unit = (total_time_per_paris - char_time_per_paris) / 19.0;  // seconds per unit
+    
+    // Everything beyond the normal one-dit unit is the Farnsworth extra
+    let dit = 1.2 / char_wpm as f32;  // seconds
+    ((spacing_unit - dit) * 1000.0).max(0.0)
+}
+
+// Random callsign and exchange for the QRM station
+fn random_qso_text(rng: &mut impl Rng) -> String {
+    const PREFIXES: [&str; 8] = ["K", "W", "N", "G", "DL", "F", "JA", "VK"];
+    const PHRASES: [&str; 6] = ["CQ CQ DE {} {} K", "TU 5NN {} ", "QRZ DE {} K",
+                                "R R FB OM UR RST 579 {} ", "73 TU {} SK ", "QSL {} "];
+    
+    let letters: String = (0..rng.gen_range(2..=3))
+        .map(|_| rng.gen_range(b'A'..=b'Z') as char)
+        .collect();
+    let call = format!("{}{}{}", PREFIXES[rng.gen_range(0..PREFIXES.len())], rng.gen_range(0..10), letters);
+    PHRASES[rng.gen_range(0..PHRASES.len())].replace("{}", &call) + " "
+}
+
+// "<XX>" at the start of the text, if the text starts with a prosign
+fn prosign_token(text: &str) -> Option<&str> {
+    if !text.starts_with('<') {
+        return None;
+    }
+    let end = text.find('>')?;
+    let letters = &text[1..end];
+    if letters.is_empty() || !letters.chars().all(|c| c.is_alphanumeric()) {
+        return None;
+    }
+    Some(&text[..=end])
+}
+
+fn push_code(elements: &mut Vec<MorseElement>, morse: &str) {
+    for symbol in morse.chars() {
+        match symbol {
+            '.' => elements.push(MorseElement::Dit),
+            '-' => elements.push(MorseElement::Dah),
+            _ => {}
         }
     }
 }
@@ -178,57 +283,74 @@ pub enum MorseElement {
     WordSpace,
 }
 
-// Simple tone generator for morse playback
-struct ToneGen {
-    frequency: f32,
-    duration_samples: usize,
-    sample_rate: u32,
-    phase: f32,
-    samples_played: usize,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl ToneGen {
-    fn new(frequency: f32, duration_ms: u32) -> Self {
-        let sample_rate = 48000;
-        let duration_samples = (sample_rate as u64 * duration_ms as u64 / 1000) as usize;
-        
-        ToneGen {
-            frequency,
-            duration_samples,
-            sample_rate,
-            phase: 0.0,
-            samples_played: 0,
-        }
+    fn player(alphabet: Alphabet) -> MorsePlayer {
+        MorsePlayer::new_with_alphabet(600.0, 20, 20, alphabet)
     }
-}
 
-impl Iterator for ToneGen {
-    type Item = f32;
-    
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.samples_played >= self.duration_samples {
-            return None;
+    // Elements written out: dits and dahs, a space between letters and / between words
+    fn written(elements: &[MorseElement]) -> String {
+        elements.iter().map(|element| match element {
+            MorseElement::Dit => '.',
+            MorseElement::Dah => '-',
+            MorseElement::LetterSpace => ' ',
+            MorseElement::WordSpace => '/',
+        }).collect()
+    }
+
+    fn morse(alphabet: Alphabet, text: &str) -> String {
+        written(&player(alphabet).text_to_morse(text))
+    }
+
+    #[test]
+    fn prosigns_run_together() {
+        for (text, code) in [("<AR>", ".-.-."), ("<BT>", "-...-"), ("<SK>", "...-.-"), ("<BK>", "-...-.-")] {
+            assert_eq!(morse(Alphabet::Latin, text), format!("{code} "), "{text}");
         }
-        
-        let sample = (self.phase * 2.0 * std::f32::consts::PI).sin() * 0.3;
-        self.phase += self.frequency / self.sample_rate as f32;
-        if self.phase >= 1.0 {
-            self.phase -= 1.0;
+        assert_eq!(morse(Alphabet::Latin, "K <AR> K"), "-.- /.-.-. /-.- ");
+        assert_eq!(morse(Alphabet::Latin, "E<SK>E"), ". ...-.- . ");
+    }
+
+    #[test]
+    fn prosign_tokens() {
+        assert_eq!(prosign_token("<AR> K"), Some("<AR>"));
+        assert_eq!(prosign_token("<CH>"), Some("<CH>"));
+        for text in ["AR", "<", "<>", "<A B>", "<AR", "<A-R>"] {
+            assert_eq!(prosign_token(text), None, "{text:?}");
         }
-        self.samples_played += 1;
-        Some(sample)
     }
-}
 
-impl Source for ToneGen {
-    fn current_frame_len(&self) -> Option<usize> { 
-        Some(self.duration_samples - self.samples_played) 
+    #[test]
+    fn malformed_prosigns_are_plain_characters() {
+        assert_eq!(morse(Alphabet::Latin, "<"), "");
+        assert_eq!(morse(Alphabet::Latin, "<>"), "");
+        assert_eq!(morse(Alphabet::Latin, "<A B>"), ".- /-... ");
+        assert_eq!(morse(Alphabet::Latin, "<AR"), ".- .-. ");
+        // Letters the table does not know are skipped
+        assert_eq!(morse(Alphabet::Latin, "<ĦĦ>"), "");
     }
-    fn channels(&self) -> u16 { 1 }
-    fn sample_rate(&self) -> u32 { self.sample_rate }
-    fn total_duration(&self) -> Option<Duration> { 
-        Some(Duration::from_millis(
-            (self.duration_samples * 1000 / self.sample_rate as usize) as u64
-        ))
+
+    #[test]
+    fn wabun_shifts_at_script_changes() {
+        let table = MorseTable::for_alphabet(Alphabet::Wabun);
+        let start = table.encode(WABUN_START).unwrap();
+        let end = table.encode(WABUN_END).unwrap();
+        let i = table.encode_char('イ').unwrap();
+
+        // Wabun starts in kana, so kana text needs no shift
+        assert_eq!(morse(Alphabet::Wabun, "イイ"), format!("{i} {i} "));
+        assert_eq!(morse(Alphabet::Wabun, "イ QSL イ"),
+                   format!("{i} /{end} --.- ... .-.. /{start} {i} "));
+        assert_eq!(morse(Alphabet::Wabun, "CQ"), format!("{end} -.-. --.- "));
+        // Digits and spaces do not change the script
+        assert_eq!(morse(Alphabet::Wabun, "A 5"), format!("{end} .- /..... "));
+    }
+
+    #[test]
+    fn other_alphabets_never_shift() {
+        assert_eq!(morse(Alphabet::Latin, "CQ"), "-.-. --.- ");
     }
 }
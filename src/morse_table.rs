@@ -0,0 +1,349 @@
+// Morse code table shared by the decoder and the player
+// The built-in table lives in data/morse_table.txt; a morse_table.txt in the working
+// directory or next to the program adds (or replaces) entries at startup.
+// Alphabet profiles (data/alphabet_*.txt) are laid over that table when selected.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+const BUILTIN_TABLE: &str = include_str!("../data/morse_table.txt");
+const USER_TABLE_FILE: &str = "morse_table.txt";
+
+// Wabun shift prosigns
+pub const WABUN_START: &str = "<DO>";
+pub const WABUN_END: &str = "<SN>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Alphabet {
+    Latin,
+    ExtendedLatin,  // Ä Ö Ü CH É Ñ ...
+    Cyrillic,       // Russian
+    Greek,
+    Wabun,          // Japanese kana
+}
+
+impl Alphabet {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Alphabet::Latin => "Latin (International)",
+            Alphabet::ExtendedLatin => "Extended Latin (Ä Ö Ü CH É Ñ)",
+            Alphabet::Cyrillic => "Cyrillic (Russian)",
+            Alphabet::Greek => "Greek",
+            Alphabet::Wabun => "Wabun (Japanese)",
+        }
+    }
+
+    // What the alphabet's letters win over when decoding. The Latin, Cyrillic and Greek
+    // profiles are letters within ordinary text, so a letter sharing a code with
+    // punctuation or a prosign (Ĥ and "(", Ŝ and <SN>) is only sent, never decoded.
+    // Wabun's kana mode is a script of its own and takes over every code.
+    fn overlay_replaces(&self) -> Replace {
+        match self {
+            Alphabet::Wabun => Replace::All,
+            _ => Replace::Letters,
+        }
+    }
+
+    // Letters laid over the standard table, if any
+    fn overlay(&self) -> Option<&'static str> {
+        match self {
+            Alphabet::Latin => None,
+            Alphabet::ExtendedLatin => Some(include_str!("../data/alphabet_extended_latin.txt")),
+            Alphabet::Cyrillic => Some(include_str!("../data/alphabet_cyrillic.txt")),
+            Alphabet::Greek => Some(include_str!("../data/alphabet_greek.txt")),
+            Alphabet::Wabun => Some(include_str!("../data/alphabet_wabun.txt")),
+        }
+    }
+}
+
+pub fn get_alphabets() -> Vec<Alphabet> {
+    vec![
+        Alphabet::Latin,
+        Alphabet::ExtendedLatin,
+        Alphabet::Cyrillic,
+        Alphabet::Greek,
+        Alphabet::Wabun,
+    ]
+}
+
+// Wabun text switches between kana and Latin letters
+pub fn is_kana(ch: char) -> bool {
+    matches!(ch, '\u{3040}'..='\u{30FF}' | '、' | '」' | '（' | '）')
+}
+
+// Which existing codes new entries take over for decoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Replace {
+    Nothing,  // Codes already in the table keep their symbol
+    Letters,  // Only codes that decode to a letter or digit
+    All,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MorseTable {
+    to_code: HashMap<String, String>,    // Symbol -> dits and dahs
+    from_code: HashMap<String, String>,  // Dits and dahs -> symbol
+    prosigns: HashMap<String, String>,   // Dits and dahs -> <XX>, even if shared with a character
+}
+
+impl MorseTable {
+    // Built-in table plus the optional user file, loaded once and shared
+    pub fn standard() -> Arc<MorseTable> {
+        static STANDARD: OnceLock<Arc<MorseTable>> = OnceLock::new();
+        STANDARD.get_or_init(|| {
+            let mut table = MorseTable::parse(BUILTIN_TABLE);
+            for path in user_table_paths() {
+                if path.is_file() {
+                    match table.load_file(&path) {
+                        Ok(count) => println!("Loaded {} Morse codes from {}", count, path.display()),
+                        Err(e) => eprintln!("Failed to read {}: {}", path.display(), e),
+                    }
+                }
+            }
+            Arc::new(table)
+        }).clone()
+    }
+
+    // Standard table with the alphabet's letters laid over it
+    pub fn for_alphabet(alphabet: Alphabet) -> Arc<MorseTable> {
+        static TABLES: OnceLock<Mutex<HashMap<Alphabet, Arc<MorseTable>>>> = OnceLock::new();
+
+        let overlay = match alphabet.overlay() {
+            Some(overlay) => overlay,
+            None => return MorseTable::standard(),
+        };
+
+        let mut tables = TABLES.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+        tables.entry(alphabet).or_insert_with(|| {
+            let mut table = (*MorseTable::standard()).clone();
+            table.add_entries(overlay, alphabet.overlay_replaces());
+            Arc::new(table)
+        }).clone()
+    }
+
+    // Parse a table in the data file format; the first symbol listed for a code decodes
+    pub fn parse(text: &str) -> Self {
+        let mut table = MorseTable::default();
+        table.add_entries(text, Replace::Nothing);
+        table
+    }
+
+    // Add entries from a file; they replace existing symbols and codes
+    pub fn load_file(&mut self, path: &Path) -> std::io::Result<usize> {
+        let text = fs::read_to_string(path)?;
+        Ok(self.add_entries(&text, Replace::All))
+    }
+
+    // `replace` says which codes already in the table the entries win over
+    // (within the text itself the first symbol listed for a code still decodes)
+    fn add_entries(&mut self, text: &str, replace: Replace) -> usize {
+        let mut count = 0;
+        let mut seen_codes = HashSet::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(symbol), Some(code), None) if is_valid_code(code) => {
+                    let replace = match replace {
+                        _ if !seen_codes.insert(code.to_string()) => Replace::Nothing,
+                        replace => replace,
+                    };
+                    self.insert(symbol, code, replace);
+                    count += 1;
+                }
+                _ => eprintln!("Ignoring Morse table line {}: {}", line_number + 1, line),
+            }
+        }
+
+        count
+    }
+
+    fn insert(&mut self, symbol: &str, code: &str, replace: Replace) {
+        let symbol = normalize_symbol(symbol);
+        let replace_decode = match (replace, self.from_code.get(code)) {
+            (_, None) | (Replace::All, _) => true,
+            (Replace::Letters, Some(existing)) => is_letter(existing),
+            (Replace::Nothing, Some(_)) => false,
+        };
+        if replace_decode {
+            self.from_code.insert(code.to_string(), symbol.clone());
+        }
+        if is_prosign(&symbol) && (replace == Replace::All || !self.prosigns.contains_key(code)) {
+            self.prosigns.insert(code.to_string(), symbol.clone());
+        }
+        self.to_code.insert(symbol, code.to_string());
+    }
+
+    // Dits and dahs for a character or prosign, case-insensitive
+    pub fn encode(&self, symbol: &str) -> Option<&str> {
+        self.to_code.get(&normalize_symbol(symbol)).map(|code| code.as_str())
+    }
+
+    pub fn encode_char(&self, ch: char) -> Option<&str> {
+        let mut buf = [0u8; 4];
+        self.encode(ch.encode_utf8(&mut buf))
+    }
+
+    pub fn decode(&self, code: &str) -> Option<&str> {
+        self.from_code.get(code).map(|symbol| symbol.as_str())
+    }
+
+    // Like decode, but a prosign wins over a character with the same code (<BT> over =)
+    pub fn decode_preferring_prosigns(&self, code: &str) -> Option<&str> {
+        self.prosigns.get(code)
+            .map(|symbol| symbol.as_str())
+            .or_else(|| self.decode(code))
+    }
+}
+
+fn is_prosign(symbol: &str) -> bool {
+    symbol.len() > 2 && symbol.starts_with('<') && symbol.ends_with('>')
+}
+
+fn is_letter(symbol: &str) -> bool {
+    let mut chars = symbol.chars();
+    matches!((chars.next(), chars.next()), (Some(ch), None) if ch.is_alphanumeric())
+}
+
+fn normalize_symbol(symbol: &str) -> String {
+    symbol.to_uppercase()
+}
+
+fn is_valid_code(code: &str) -> bool {
+    !code.is_empty() && code.chars().all(|c| c == '.' || c == '-')
+}
+
+// Look for the user table in the working directory and next to the executable
+fn user_table_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(USER_TABLE_FILE)];
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+        let beside_exe = dir.join(USER_TABLE_FILE);
+        let same_file = fs::canonicalize(&beside_exe).ok() == fs::canonicalize(&paths[0]).ok()
+            && beside_exe.exists();
+        if !same_file {
+            paths.push(beside_exe);
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_latin_keeps_punctuation_and_prosigns() {
+        let table = MorseTable::for_alphabet(Alphabet::ExtendedLatin);
+        // Ĥ and Ŝ are sent with the codes of ( and <SN>, which still decode as before
+        assert_eq!(table.encode("Ĥ"), Some("-.--."));
+        assert_eq!(table.encode("Ŝ"), Some("...-."));
+        assert_eq!(table.decode("-.--."), Some("("));
+        assert_eq!(table.decode_preferring_prosigns("-.--."), Some("<KN>"));
+        assert_eq!(table.decode("...-."), Some("<SN>"));
+        // Letters of their own, and the first of two sharing a code
+        assert_eq!(table.decode("---."), Some("Ö"));
+        assert_eq!(table.decode(".-.-"), Some("Ä"));
+        assert_eq!(table.encode("æ"), Some(".-.-"));
+        assert_eq!(table.decode("----"), Some("CH"));
+        // The standard letters are unchanged
+        assert_eq!(table.decode(".-"), Some("A"));
+    }
+
+    #[test]
+    fn cyrillic_and_greek_replace_latin_letters() {
+        let table = MorseTable::for_alphabet(Alphabet::Cyrillic);
+        assert_eq!(table.decode(".-"), Some("А"));
+        assert_eq!(table.decode("--.-"), Some("Щ"));
+        assert_eq!(table.encode("я"), Some(".-.-"));
+        // Latin letters can still be sent, numbers and punctuation decode as before
+        assert_eq!(table.encode("Q"), Some("--.-"));
+        assert_eq!(table.decode("..---"), Some("2"));
+        assert_eq!(table.decode("..--.."), Some("?"));
+
+        let table = MorseTable::for_alphabet(Alphabet::Greek);
+        assert_eq!(table.decode(".-"), Some("Α"));
+        assert_eq!(table.decode("-.--."), Some("("));
+    }
+
+    #[test]
+    fn wabun_kana_take_over_every_code() {
+        let table = MorseTable::for_alphabet(Alphabet::Wabun);
+        assert_eq!(table.decode(".-"), Some("イ"));
+        assert_eq!(table.decode("-.--."), Some("ル"));
+        assert_eq!(table.decode("-...-"), Some("メ"));
+        assert_eq!(table.encode(WABUN_START), Some("-..---"));
+        assert_eq!(table.encode(WABUN_END), Some("...-."));
+    }
+
+    #[test]
+    fn overlays_leave_the_standard_table_alone() {
+        MorseTable::for_alphabet(Alphabet::Cyrillic);
+        assert_eq!(MorseTable::standard().decode(".-"), Some("A"));
+        assert!(Arc::ptr_eq(&MorseTable::for_alphabet(Alphabet::Latin), &MorseTable::standard()));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let mut table = MorseTable::default();
+        let text = "# comment\n\n  A .-  \nB\nC -.-. extra\nD -x.\nE .\n<AR> .-.-.\n";
+        assert_eq!(table.add_entries(text, Replace::Nothing), 3);
+        assert_eq!(table.decode(".-"), Some("A"));
+        assert_eq!(table.encode("B"), None);
+        assert_eq!(table.encode("C"), None);
+        assert_eq!(table.encode("D"), None);
+        assert_eq!(table.decode("."), Some("E"));
+        assert_eq!(table.encode("<ar>"), Some(".-.-."));
+    }
+
+    #[test]
+    fn first_symbol_listed_for_a_code_decodes() {
+        let table = MorseTable::parse("= -...-\n<BT> -...-\nq --.-\n");
+        assert_eq!(table.decode("-...-"), Some("="));
+        assert_eq!(table.decode_preferring_prosigns("-...-"), Some("<BT>"));
+        // Both can be sent; symbols are upper case
+        assert_eq!(table.encode("="), Some("-...-"));
+        assert_eq!(table.encode("<bt>"), Some("-...-"));
+        assert_eq!(table.decode("--.-"), Some("Q"));
+        assert_eq!(table.encode_char('q'), Some("--.-"));
+    }
+
+    #[test]
+    fn builtin_table_parses_completely() {
+        let entries = BUILTIN_TABLE.lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .count();
+        assert_eq!(MorseTable::default().add_entries(BUILTIN_TABLE, Replace::Nothing), entries);
+        let table = MorseTable::parse(BUILTIN_TABLE);
+        assert_eq!(table.decode(".-.-."), Some("+"));
+        assert_eq!(table.decode_preferring_prosigns(".-.-."), Some("<AR>"));
+        assert_eq!(table.encode("<BK>"), Some("-...-.-"));
+    }
+
+    #[test]
+    fn user_file_wins_over_the_builtin_table() {
+        let path = std::env::temp_dir().join(format!("paddle_decoder_table_{}.txt", std::process::id()));
+        fs::write(&path, "# user table\n<BT> -...-\n<BT2> -...-\nÄ .-.-\n? ..--..\nbad line here\n").unwrap();
+
+        let mut table = MorseTable::parse(BUILTIN_TABLE);
+        let loaded = table.load_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), 4);
+
+        // The user's first symbol for a code decodes, over the builtin one
+        assert_eq!(table.decode("-...-"), Some("<BT>"));
+        assert_eq!(table.decode_preferring_prosigns("-...-"), Some("<BT>"));
+        assert_eq!(table.encode("="), Some("-...-"));
+        assert_eq!(table.decode(".-.-"), Some("Ä"));
+        assert_eq!(table.decode("..--.."), Some("?"));
+        // Untouched builtin entries stay
+        assert_eq!(table.decode(".-"), Some("A"));
+
+        assert!(MorseTable::default().load_file(Path::new("/nonexistent/morse_table.txt")).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+// Paddle input sources
+// MIDI, a serial port, the computer keyboard and the on-screen paddles can all hold a
+// paddle down. Each source keeps its own contact state and a paddle counts as pressed
+// while any source holds it, so releasing one source never cancels another.
+use eframe::egui;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaddleSide {
+    Left,
+    Right,
+}
+
+impl PaddleSide {
+    pub fn is_left(&self) -> bool {
+        *self == PaddleSide::Left
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaddleSource {
+    Midi,
+    Keyboard,
+    Mouse,
+    Serial,
+}
+
+impl PaddleSource {
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+// Which sources currently hold each paddle down
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PaddleContacts {
+    left: u8,
+    right: u8,
+}
+
+impl PaddleContacts {
+    pub fn set(&mut self, source: PaddleSource, side: PaddleSide, pressed: bool) {
+        let contacts = match side {
+            PaddleSide::Left => &mut self.left,
+            PaddleSide::Right => &mut self.right,
+        };
+        if pressed {
+            *contacts |= source.bit();
+        } else {
+            *contacts &= !source.bit();
+        }
+    }
+
+    pub fn is_down(&self, side: PaddleSide) -> bool {
+        match side {
+            PaddleSide::Left => self.left != 0,
+            PaddleSide::Right => self.right != 0,
+        }
+    }
+}
+
+// Keyboard key used as a paddle. The usual keyboard paddles of other CW programs, Left and
+// Right Ctrl or [ and ], cannot be offered: egui 0.24 reports Ctrl, Shift and Alt without
+// their side and has no key codes for [ and ]. Each modifier can be bound to one paddle
+// only, and the two paddles always get different keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaddleKey {
+    Ctrl,
+    Shift,
+    Alt,
+    Key(egui::Key),
+}
+
+impl PaddleKey {
+    pub fn as_str(&self) -> &str {
+        match self {
+            PaddleKey::Ctrl => "Ctrl",
+            PaddleKey::Shift => "Shift",
+            PaddleKey::Alt => "Alt",
+            PaddleKey::Key(key) => key.name(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PaddleKey> {
+        get_paddle_keys().into_iter().find(|key| key.as_str() == name)
+    }
+
+    pub fn is_down(&self, input: &egui::InputState) -> bool {
+        match self {
+            PaddleKey::Ctrl => input.modifiers.ctrl,
+            PaddleKey::Shift => input.modifiers.shift,
+            PaddleKey::Alt => input.modifiers.alt,
+            PaddleKey::Key(key) => input.key_down(*key),
+        }
+    }
+}
+
+// Z and X sit side by side under the left hand, like [ and ] under the right, and no
+// widget uses them (the arrow keys also move a focused slider)
+pub const DEFAULT_LEFT_KEY: PaddleKey = PaddleKey::Key(egui::Key::Z);
+pub const DEFAULT_RIGHT_KEY: PaddleKey = PaddleKey::Key(egui::Key::X);
+
+pub fn get_paddle_keys() -> Vec<PaddleKey> {
+    vec![
+        PaddleKey::Key(egui::Key::ArrowLeft),
+        PaddleKey::Key(egui::Key::ArrowRight),
+        PaddleKey::Key(egui::Key::ArrowUp),
+        PaddleKey::Key(egui::Key::ArrowDown),
+        PaddleKey::Ctrl,
+        PaddleKey::Shift,
+        PaddleKey::Alt,
+        PaddleKey::Key(egui::Key::Space),
+        PaddleKey::Key(egui::Key::Z),
+        PaddleKey::Key(egui::Key::X),
+        PaddleKey::Key(egui::Key::C),
+        PaddleKey::Key(egui::Key::N),
+        PaddleKey::Key(egui::Key::M),
+        PaddleKey::Key(egui::Key::J),
+        PaddleKey::Key(egui::Key::K),
+        PaddleKey::Key(egui::Key::Num1),
+        PaddleKey::Key(egui::Key::Num2),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releasing_one_source_keeps_another() {
+        let mut contacts = PaddleContacts::default();
+        contacts.set(PaddleSource::Midi, PaddleSide::Left, true);
+        contacts.set(PaddleSource::Keyboard, PaddleSide::Left, true);
+        contacts.set(PaddleSource::Midi, PaddleSide::Left, false);
+        assert!(contacts.is_down(PaddleSide::Left));
+        assert!(!contacts.is_down(PaddleSide::Right));
+
+        contacts.set(PaddleSource::Keyboard, PaddleSide::Left, false);
+        assert!(!contacts.is_down(PaddleSide::Left));
+    }
+
+    #[test]
+    fn sides_are_independent() {
+        let mut contacts = PaddleContacts::default();
+        contacts.set(PaddleSource::Serial, PaddleSide::Left, true);
+        contacts.set(PaddleSource::Serial, PaddleSide::Right, true);
+        contacts.set(PaddleSource::Serial, PaddleSide::Right, false);
+        assert!(contacts.is_down(PaddleSide::Left));
+        assert!(!contacts.is_down(PaddleSide::Right));
+        // Releasing a paddle another source never pressed changes nothing
+        contacts.set(PaddleSource::Mouse, PaddleSide::Left, false);
+        assert!(contacts.is_down(PaddleSide::Left));
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        for key in get_paddle_keys() {
+            assert_eq!(PaddleKey::from_name(key.as_str()), Some(key), "{}", key.as_str());
+        }
+        assert_eq!(PaddleKey::from_name("Nope"), None);
+    }
+
+    #[test]
+    fn default_keys_are_offered_and_distinct() {
+        assert!(get_paddle_keys().contains(&DEFAULT_LEFT_KEY));
+        assert!(get_paddle_keys().contains(&DEFAULT_RIGHT_KEY));
+        assert_ne!(DEFAULT_LEFT_KEY, DEFAULT_RIGHT_KEY);
+    }
+}
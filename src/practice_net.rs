@@ -0,0 +1,426 @@
+// Practice net relay
+// A small MOPP server for a class on a LAN: the instructor hosts it, and every instance
+// that joins (Internet CW with this machine's address) hears the keying of all the others
+// on the same channel. Packets are forwarded unchanged, so any MOPP client can join, but
+// each station's packets leave from a port of their own: the relay port plus the station
+// number. Clients tell the stations apart by that port, so each student gets a pitch and
+// a decoder pane of their own. This suits a LAN; a NAT in between would drop them.
+//
+// Stations join with "hi" or "hi <channel>", or just start keying on channel 0, and are
+// forgotten after a quiet spell. Clients send the join again every few minutes.
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::mopp::{join_channel, MoppPacket};
+
+pub const DEFAULT_PORT: u16 = 7373;
+
+// Twice the clients' join interval
+const STATION_TIMEOUT: Duration = Duration::from_secs(600);
+
+const MAX_STATION_NUMBER: u8 = 255;
+
+const RETRY_MS: u64 = 2000;
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayStatus {
+    Off,
+    Running { port: u16, stations: usize },
+    Error(String),
+}
+
+impl RelayStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            RelayStatus::Off => "Off".to_string(),
+            RelayStatus::Running { port, stations } => format!("Hosting on port {} - {} station(s)", port, stations),
+            RelayStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, RelayStatus::Running { .. })
+    }
+}
+
+// A joined station, as listed in the UI
+#[derive(Debug, Clone)]
+pub struct RelayStation {
+    pub number: u8,
+    pub address: SocketAddr,
+    pub channel: u32,
+}
+
+struct Station {
+    number: u8,
+    channel: u32,
+    last_heard: Instant,
+    socket: UdpSocket,  // Sends this station's packets, from the relay port + number
+}
+
+struct Shared {
+    enabled: bool,
+    port: u16,
+    restart: bool,
+    status: RelayStatus,
+    stations: Vec<RelayStation>,
+    station_timeout: Duration,
+}
+
+#[derive(Clone)]
+pub struct NetRelay {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl NetRelay {
+    pub fn start(enabled: bool, port: u16) -> Self {
+        let relay = NetRelay {
+            shared: Arc::new(Mutex::new(Shared {
+                enabled,
+                port,
+                restart: false,
+                status: RelayStatus::Off,
+                stations: Vec::new(),
+                station_timeout: STATION_TIMEOUT,
+            })),
+        };
+
+        let runner = relay.clone();
+        thread::spawn(move || runner.run());
+        relay
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.shared.lock().unwrap().enabled
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.enabled != enabled {
+            shared.enabled = enabled;
+            shared.restart = true;
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.shared.lock().unwrap().port
+    }
+
+    pub fn set_port(&self, port: u16) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.port != port {
+            shared.port = port;
+            shared.restart = true;
+        }
+    }
+
+    pub fn status(&self) -> RelayStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    pub fn stations(&self) -> Vec<RelayStation> {
+        self.shared.lock().unwrap().stations.clone()
+    }
+
+    fn set_status(&self, status: RelayStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self) {
+        loop {
+            let (enabled, port) = {
+                let mut shared = self.shared.lock().unwrap();
+                shared.restart = false;
+                (shared.enabled, shared.port)
+            };
+
+            if enabled {
+                let bound = UdpSocket::bind(("0.0.0.0", port))
+                    .and_then(|socket| socket.set_read_timeout(Some(POLL)).map(|_| socket));
+                match bound {
+                    Ok(socket) => {
+                        println!("Practice net relay on port {}", port);
+                        self.set_status(RelayStatus::Running { port, stations: 0 });
+                        if let Err(e) = self.serve(&socket, port) {
+                            eprintln!("Practice net relay error: {}", e);
+                            self.set_status(RelayStatus::Error(e.to_string()));
+                        }
+                    }
+                    Err(e) => self.set_status(RelayStatus::Error(format!("port {}: {}", port, e))),
+                }
+                self.shared.lock().unwrap().stations.clear();
+            } else {
+                self.set_status(RelayStatus::Off);
+            }
+
+            // Retry after an error, or wait to be enabled
+            for _ in 0..RETRY_MS / 50 {
+                if self.shared.lock().unwrap().restart {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    // Relay until disabled or moved to another port, or on error
+    fn serve(&self, socket: &UdpSocket, port: u16) -> io::Result<()> {
+        let mut stations: HashMap<SocketAddr, Station> = HashMap::new();
+        let mut buffer = [0u8; 512];
+        let local = socket.local_addr()?.ip();
+
+        loop {
+            let (restart, timeout) = {
+                let shared = self.shared.lock().unwrap();
+                (shared.restart, shared.station_timeout)
+            };
+            if restart {
+                return Ok(());
+            }
+
+            let before = stations.len();
+            stations.retain(|_, station| station.last_heard.elapsed() < timeout);
+            let mut changed = stations.len() != before;
+
+            // The read timeout paces the loop
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                // Windows reports a station that went away on the next receive
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                                   | io::ErrorKind::ConnectionReset) => {
+                    if changed {
+                        self.publish(&stations, port);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let bytes = &buffer[..length];
+            let packet = match MoppPacket::decode(bytes) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            let joined = join_channel(&packet);
+            match stations.get_mut(&from) {
+                Some(station) => {
+                    station.last_heard = Instant::now();
+                    if let Some(channel) = joined {
+                        changed |= station.channel != channel;
+                        station.channel = channel;
+                    }
+                }
+                None => {
+                    let Some((number, station_socket)) = free_number(&stations, local, port) else { continue };
+                    let channel = joined.unwrap_or(0);
+                    println!("Station {} joined from {} (channel {})", number, from, channel);
+                    stations.insert(from, Station {
+                        number,
+                        channel,
+                        last_heard: Instant::now(),
+                        socket: station_socket,
+                    });
+                    changed = true;
+                }
+            }
+            if changed {
+                self.publish(&stations, port);
+            }
+
+            // Joins are not keying, everything else goes to the rest of the channel
+            if joined.is_none() {
+                let sender = &stations[&from];
+                for (address, station) in &stations {
+                    if *address != from && station.channel == sender.channel {
+                        if let Err(e) = sender.socket.send_to(bytes, address) {
+                            eprintln!("Practice net relay: {}: {}", address, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn publish(&self, stations: &HashMap<SocketAddr, Station>, port: u16) {
+        let mut list: Vec<RelayStation> = stations.iter()
+            .map(|(address, station)| RelayStation {
+                number: station.number,
+                address: *address,
+                channel: station.channel,
+            })
+            .collect();
+        list.sort_by_key(|station| station.number);
+
+        let mut shared = self.shared.lock().unwrap();
+        shared.status = RelayStatus::Running { port, stations: list.len() };
+        shared.stations = list;
+    }
+}
+
+// Lowest station number not in use whose port (relay port + number) is free, with the
+// socket bound to it
+fn free_number(stations: &HashMap<SocketAddr, Station>, local: IpAddr, port: u16) -> Option<(u8, UdpSocket)> {
+    (1..=MAX_STATION_NUMBER)
+        .filter(|number| stations.values().all(|station| station.number != *number))
+        .find_map(|number| {
+            let station_port = port.checked_add(number as u16)?;
+            UdpSocket::bind((local, station_port)).ok().map(|socket| (number, socket))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mopp::join_text;
+
+    // A relay serving on a free local port until dropped
+    struct TestRelay {
+        relay: NetRelay,
+        address: SocketAddr,
+    }
+
+    impl TestRelay {
+        fn start(station_timeout: Duration) -> TestRelay {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_read_timeout(Some(POLL)).unwrap();
+            let address = socket.local_addr().unwrap();
+            let relay = NetRelay {
+                shared: Arc::new(Mutex::new(Shared {
+                    enabled: true,
+                    port: address.port(),
+                    restart: false,
+                    status: RelayStatus::Off,
+                    stations: Vec::new(),
+                    station_timeout,
+                })),
+            };
+            let runner = relay.clone();
+            thread::spawn(move || runner.serve(&socket, address.port()));
+            TestRelay { relay, address }
+        }
+
+        // A station that has sent its join word
+        fn join(&self, channel: u32) -> UdpSocket {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+            self.send(&socket, &MoppPacket::from_text(1, 20, &join_text(channel)));
+            socket
+        }
+
+        fn send(&self, socket: &UdpSocket, packet: &MoppPacket) {
+            socket.send_to(&packet.encode(), self.address).unwrap();
+            // The station is listed once the relay has read the packet
+            let address = socket.local_addr().unwrap();
+            assert!(wait_for(|| self.relay.stations().iter().any(|station| station.address == address)));
+        }
+
+        // Station number of each joined station, by the station's own port
+        fn numbers(&self) -> Vec<(u16, u8)> {
+            self.relay.stations().iter().map(|station| (station.address.port(), station.number)).collect()
+        }
+    }
+
+    impl Drop for TestRelay {
+        fn drop(&mut self) {
+            self.relay.shared.lock().unwrap().restart = true;
+        }
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        condition()
+    }
+
+    // A keyed letter, sent as it is by the client
+    fn letter() -> MoppPacket {
+        let mut packet = MoppPacket::from_text(2, 20, "K");
+        packet.symbols.pop();
+        packet.symbols.push(crate::morse_decoder::Symbol::LetterSpace);
+        packet
+    }
+
+    fn receive(socket: &UdpSocket) -> Option<(Vec<u8>, SocketAddr)> {
+        let mut buffer = [0u8; 512];
+        socket.recv_from(&mut buffer).ok().map(|(length, from)| (buffer[..length].to_vec(), from))
+    }
+
+    #[test]
+    fn stations_get_the_lowest_free_number() {
+        let relay = TestRelay::start(STATION_TIMEOUT);
+        let a = relay.join(0);
+        let b = relay.join(0);
+        let c = relay.join(5);
+        let port = |socket: &UdpSocket| socket.local_addr().unwrap().port();
+        assert_eq!(relay.numbers(), [(port(&a), 1), (port(&b), 2), (port(&c), 3)]);
+        assert_eq!(relay.relay.stations()[2].channel, 5);
+        assert_eq!(relay.relay.status(), RelayStatus::Running { port: relay.address.port(), stations: 3 });
+
+        // Joining again only moves the station to another channel
+        relay.send(&b, &MoppPacket::from_text(1, 20, &join_text(5)));
+        assert!(wait_for(|| relay.relay.stations()[1].channel == 5));
+        assert_eq!(relay.numbers(), [(port(&a), 1), (port(&b), 2), (port(&c), 3)]);
+    }
+
+    #[test]
+    fn keying_goes_unchanged_to_the_rest_of_the_channel() {
+        let relay = TestRelay::start(STATION_TIMEOUT);
+        let a = relay.join(0);
+        let b = relay.join(0);
+        let c = relay.join(3);
+
+        let packet = letter();
+        a.send_to(&packet.encode(), relay.address).unwrap();
+
+        // Plain MOPP bytes, from the relay port + the sender's station number
+        let (bytes, from) = receive(&b).unwrap();
+        assert_eq!(bytes, packet.encode());
+        assert_eq!(from.port(), relay.address.port() + 1);
+
+        // Nothing back to the sender, nothing to other channels
+        assert!(receive(&a).is_none());
+        assert!(receive(&c).is_none());
+    }
+
+    #[test]
+    fn joins_are_not_forwarded() {
+        let relay = TestRelay::start(STATION_TIMEOUT);
+        let a = relay.join(0);
+        let _b = relay.join(0);
+        assert!(receive(&a).is_none());
+    }
+
+    #[test]
+    fn keying_without_a_join_is_channel_0() {
+        let relay = TestRelay::start(STATION_TIMEOUT);
+        let a = relay.join(0);
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        relay.send(&b, &letter());
+        assert_eq!(relay.relay.stations()[1].channel, 0);
+        assert_eq!(receive(&a).unwrap().0, letter().encode());
+    }
+
+    #[test]
+    fn quiet_stations_are_forgotten() {
+        let relay = TestRelay::start(Duration::from_millis(300));
+        let _a = relay.join(0);
+        let b = relay.join(0);
+        assert_eq!(relay.relay.stations().len(), 2);
+        assert!(wait_for(|| relay.relay.stations().is_empty()));
+        assert_eq!(relay.relay.status(), RelayStatus::Running { port: relay.address.port(), stations: 0 });
+
+        // Station 1 is free again, and its port with it
+        relay.send(&b, &letter());
+        assert_eq!(relay.numbers(), [(b.local_addr().unwrap().port(), 1)]);
+    }
+}
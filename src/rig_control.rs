@@ -0,0 +1,409 @@
+// Hamlib rigctld CW
+// Many rigs send CW text given over CAT. This client talks to rigctld over TCP: decoded
+// words and typed macros go to the rig with send_morse, and the keyer speed is kept in
+// step with the rig's KEYSPD level in both directions (the WPM slider sets the rig, the
+// rig's keyer knob moves the slider).
+//
+// For testing without a rig, run Hamlib's dummy rig: `rigctld -m 1`.
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_ADDRESS: &str = "localhost:4532";
+
+// How often the rig's speed is read back
+const SPEED_POLL_MS: u64 = 1000;
+
+// Retry interval after a failed connection
+const RETRY_MS: u64 = 2000;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RigStatus {
+    Off,
+    Connecting(String),
+    Connected(String),
+    Error(String),
+}
+
+impl RigStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            RigStatus::Off => "Off".to_string(),
+            RigStatus::Connecting(address) => format!("Connecting to {}...", address),
+            RigStatus::Connected(address) => format!("Connected: {}", address),
+            RigStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, RigStatus::Connected(_))
+    }
+}
+
+// Called from the rig thread when the speed was changed on the rig
+pub type SpeedHandler = Arc<dyn Fn(u32) + Send + Sync>;
+
+struct Shared {
+    enabled: bool,
+    address: String,
+    reconnect: bool,
+    status: RigStatus,
+    send_decoded: bool,
+    sync_speed: bool,
+    app_wpm: u32,
+    word: String,              // Decoded characters waiting for the end of the word
+    outgoing: VecDeque<String>,
+}
+
+#[derive(Clone)]
+pub struct RigControl {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl RigControl {
+    pub fn start(enabled: bool, address: String, wpm: u32, handler: SpeedHandler) -> Self {
+        let rig = RigControl {
+            shared: Arc::new(Mutex::new(Shared {
+                enabled,
+                address,
+                reconnect: false,
+                status: RigStatus::Off,
+                send_decoded: true,
+                sync_speed: true,
+                app_wpm: wpm,
+                word: String::new(),
+                outgoing: VecDeque::new(),
+            })),
+        };
+
+        let runner = rig.clone();
+        thread::spawn(move || runner.run(handler));
+        rig
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.shared.lock().unwrap().enabled
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.enabled != enabled {
+            shared.enabled = enabled;
+            shared.reconnect = true;
+        }
+    }
+
+    pub fn address(&self) -> String {
+        self.shared.lock().unwrap().address.clone()
+    }
+
+    pub fn set_address(&self, address: &str) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.address != address.trim() {
+            shared.address = address.trim().to_string();
+            shared.reconnect = true;
+        }
+    }
+
+    pub fn send_decoded(&self) -> bool {
+        self.shared.lock().unwrap().send_decoded
+    }
+
+    pub fn set_send_decoded(&self, send_decoded: bool) {
+        self.shared.lock().unwrap().send_decoded = send_decoded;
+    }
+
+    pub fn sync_speed(&self) -> bool {
+        self.shared.lock().unwrap().sync_speed
+    }
+
+    pub fn set_sync_speed(&self, sync_speed: bool) {
+        self.shared.lock().unwrap().sync_speed = sync_speed;
+    }
+
+    pub fn status(&self) -> RigStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    // WPM slider position; sent to the rig when it changes
+    pub fn set_speed(&self, wpm: u32) {
+        self.shared.lock().unwrap().app_wpm = wpm;
+    }
+
+    // Text for the rig to send (typed text, macros)
+    pub fn send_text(&self, text: &str) {
+        let text = rig_text(text);
+        let mut shared = self.shared.lock().unwrap();
+        if shared.status.is_connected() && !text.trim().is_empty() {
+            shared.outgoing.push_back(text);
+        }
+    }
+
+    // Decoder output; whole words are sent once the word space is seen
+    pub fn decoded(&self, text: &str) {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.send_decoded || !shared.status.is_connected() {
+            return;
+        }
+        for ch in text.chars() {
+            if ch == ' ' {
+                if !shared.word.is_empty() {
+                    let word = format!("{} ", std::mem::take(&mut shared.word));
+                    shared.outgoing.push_back(rig_text(&word));
+                }
+            } else {
+                shared.word.push(ch);
+            }
+        }
+    }
+
+    fn set_status(&self, status: RigStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self, handler: SpeedHandler) {
+        loop {
+            let (enabled, address) = {
+                let mut shared = self.shared.lock().unwrap();
+                shared.reconnect = false;
+                (shared.enabled, shared.address.clone())
+            };
+
+            if enabled {
+                self.set_status(RigStatus::Connecting(address.clone()));
+                match connect(&address) {
+                    Ok(connection) => {
+                        println!("rigctld connected: {}", address);
+                        self.set_status(RigStatus::Connected(address.clone()));
+                        if let Err(e) = self.session(connection, &handler) {
+                            eprintln!("rigctld error: {}", e);
+                            self.set_status(RigStatus::Error(e.to_string()));
+                        }
+                    }
+                    Err(e) => self.set_status(RigStatus::Error(format!("{}: {}", address, e))),
+                }
+                let mut shared = self.shared.lock().unwrap();
+                shared.outgoing.clear();
+                shared.word.clear();
+            } else {
+                self.set_status(RigStatus::Off);
+            }
+
+            // Retry after an error, or wait to be enabled
+            for _ in 0..RETRY_MS / 50 {
+                if self.shared.lock().unwrap().reconnect {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    // One connection: returns when disabled or moved to another address, or on error
+    fn session(&self, mut rig: Connection, handler: &SpeedHandler) -> io::Result<()> {
+        // The rig's speed wins when connecting, then whichever side changes
+        let mut synced_wpm = None;
+        let mut next_poll = Instant::now();
+
+        loop {
+            let (text, app_wpm, sync_speed, reconnect) = {
+                let mut shared = self.shared.lock().unwrap();
+                (shared.outgoing.pop_front(), shared.app_wpm, shared.sync_speed, shared.reconnect)
+            };
+            if reconnect {
+                return Ok(());
+            }
+
+            if let Some(text) = text {
+                // A refused text is reported, the connection stays up
+                if let Err(refused) = rig.command(&format!("\\send_morse {}", text))? {
+                    eprintln!("rigctld send_morse: {}", refused);
+                }
+            }
+
+            if sync_speed {
+                if Instant::now() >= next_poll {
+                    next_poll = Instant::now() + Duration::from_millis(SPEED_POLL_MS);
+                    let rig_wpm = rig.get_keyspd()?;
+                    if synced_wpm != Some(rig_wpm) {
+                        synced_wpm = Some(rig_wpm);
+                        if rig_wpm != app_wpm {
+                            // Taken as the slider position so it is not sent straight back
+                            self.shared.lock().unwrap().app_wpm = rig_wpm;
+                            handler(rig_wpm);
+                        }
+                        continue;
+                    }
+                }
+                if synced_wpm.is_some_and(|wpm| wpm != app_wpm) {
+                    rig.command(&format!("\\set_level KEYSPD {}", app_wpm))?.map_err(io::Error::other)?;
+                    synced_wpm = Some(app_wpm);
+                }
+            } else {
+                synced_wpm = None;
+            }
+
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+fn connect(address: &str) -> io::Result<Connection> {
+    let socket = address.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not found"))?;
+    let stream = TcpStream::connect_timeout(&socket, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(Connection { reader: BufReader::new(stream.try_clone()?), writer: stream })
+}
+
+impl Connection {
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "rigctld closed the connection"));
+        }
+        Ok(line.trim().to_string())
+    }
+
+    // Command answered with "RPRT <code>". A negative code is a Hamlib error and comes
+    // back as the inner error; anything else means the replies are out of step, so the
+    // connection has to be made again.
+    fn command(&mut self, command: &str) -> io::Result<Result<(), String>> {
+        writeln!(self.writer, "{}", command)?;
+        let reply = self.read_line()?;
+        match reply.strip_prefix("RPRT ").map(|code| code.trim().parse::<i32>()) {
+            Some(Ok(0)) => Ok(Ok(())),
+            Some(Ok(code)) if code < 0 => Ok(Err(format!("rigctld refused {:?}: error {}", command, code))),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply {:?}", reply))),
+        }
+    }
+
+    // Rig keyer speed in WPM
+    fn get_keyspd(&mut self) -> io::Result<u32> {
+        writeln!(self.writer, "\\get_level KEYSPD")?;
+        let reply = self.read_line()?;
+        match reply.parse::<f64>() {
+            Ok(wpm) => Ok(wpm.round().max(1.0) as u32),
+            Err(_) => Err(io::Error::other(format!("no KEYSPD level: {:?}", reply))),
+        }
+    }
+}
+
+// Text rigs can send: upper case, common prosigns as their ASCII stand-ins, other
+// <XX> prosigns as plain letters, nothing that would end the command line
+fn rig_text(text: &str) -> String {
+    text.to_uppercase()
+        .replace("<AR>", "+")
+        .replace("<BT>", "=")
+        .replace("<KN>", "(")
+        .chars()
+        .filter(|ch| !matches!(ch, '<' | '>' | '\n' | '\r'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn text_for_the_rig() {
+        assert_eq!(rig_text("cq de dl1abc <ar>"), "CQ DE DL1ABC +");
+        assert_eq!(rig_text("<BT> <kn> <sk>"), "= ( SK");
+        assert_eq!(rig_text("73\n"), "73");
+    }
+
+    // Poll until the condition holds, for up to five seconds (the speed is read once a second)
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        condition()
+    }
+
+    // Stand-in for rigctld: answers KEYSPD from `speed` and refuses texts containing "QRT"
+    struct DummyRig {
+        address: String,
+        speed: Arc<Mutex<u32>>,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DummyRig {
+        fn start(speed: u32) -> DummyRig {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let speed = Arc::new(Mutex::new(speed));
+            let sent = Arc::new(Mutex::new(Vec::new()));
+            let (rig_speed, rig_sent) = (Arc::clone(&speed), Arc::clone(&sent));
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                let mut writer = stream.try_clone().unwrap();
+                for line in BufReader::new(stream).lines() {
+                    let Ok(line) = line else { break };
+                    let reply = if line == "\\get_level KEYSPD" {
+                        rig_speed.lock().unwrap().to_string()
+                    } else if let Some(wpm) = line.strip_prefix("\\set_level KEYSPD ") {
+                        *rig_speed.lock().unwrap() = wpm.parse().unwrap();
+                        "RPRT 0".to_string()
+                    } else if let Some(text) = line.strip_prefix("\\send_morse ") {
+                        rig_sent.lock().unwrap().push(text.to_string());
+                        if text.contains("QRT") { "RPRT -11" } else { "RPRT 0" }.to_string()
+                    } else {
+                        "RPRT -4".to_string()
+                    };
+                    if writeln!(writer, "{}", reply).is_err() {
+                        break;
+                    }
+                }
+            });
+            DummyRig { address, speed, sent }
+        }
+    }
+
+    #[test]
+    fn session_with_dummy_rig() {
+        let dummy = DummyRig::start(25);
+        let slider = Arc::new(Mutex::new(20));
+        let handler_slider = Arc::clone(&slider);
+        let rig = RigControl::start(true, dummy.address.clone(), 20,
+                                    Arc::new(move |wpm| *handler_slider.lock().unwrap() = wpm));
+        assert!(wait_for(|| rig.status().is_connected()), "{:?}", rig.status());
+
+        // The rig's speed wins when connecting
+        assert!(wait_for(|| *slider.lock().unwrap() == 25));
+        assert_eq!(*dummy.speed.lock().unwrap(), 25);
+
+        // Slider to rig, then the rig's knob to the slider
+        rig.set_speed(30);
+        assert!(wait_for(|| *dummy.speed.lock().unwrap() == 30));
+        *dummy.speed.lock().unwrap() = 18;
+        assert!(wait_for(|| *slider.lock().unwrap() == 18));
+
+        // A refused text is skipped; the replies stay in step
+        rig.send_text("qrt <ar>");
+        rig.send_text("cq <kn>");
+        rig.decoded("TEST ");
+        let expected = ["QRT +", "CQ (", "TEST "];
+        assert!(wait_for(|| *dummy.sent.lock().unwrap() == expected), "{:?}", dummy.sent.lock().unwrap());
+        rig.set_speed(22);
+        assert!(wait_for(|| *dummy.speed.lock().unwrap() == 22));
+        assert!(rig.status().is_connected(), "{:?}", rig.status());
+        rig.set_enabled(false);
+    }
+}
@@ -0,0 +1,398 @@
+// Serial port paddle input
+// Many paddle interfaces hang off a USB-serial adapter: DTR and RTS are raised to power
+// the paddle contacts, which switch them through to DSR and CTS. A polling thread reads
+// those modem-status lines and drives the paddles like MIDI does.
+//
+// The line source is behind the ModemLines trait. Besides real ports there is a
+// pseudo-terminal stand-in (Unix) for testing without hardware: ptys have no modem
+// lines, so it reads the line changes as characters written to the pty instead.
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::paddle_input::PaddleSide;
+
+// Modem lines are polled this often
+const POLL_INTERVAL_MS: u64 = 1;
+
+// Port list refresh and retry interval after an error
+const RESCAN_INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LineState {
+    pub cts: bool,
+    pub dsr: bool,
+}
+
+// Source of modem-status line levels
+pub trait ModemLines: Send {
+    fn read_lines(&mut self) -> io::Result<LineState>;
+
+    // Shown in the status line
+    fn describe(&self) -> String;
+}
+
+// Real serial port
+struct SerialLines {
+    port: Box<dyn serialport::SerialPort>,
+    name: String,
+}
+
+impl SerialLines {
+    fn open(name: &str) -> serialport::Result<Self> {
+        let mut port = serialport::new(name, 9600)
+            .timeout(Duration::from_millis(10))
+            .open()?;
+        // The paddle contacts are powered from DTR and RTS
+        port.write_data_terminal_ready(true)?;
+        port.write_request_to_send(true)?;
+        Ok(SerialLines { port, name: name.to_string() })
+    }
+}
+
+impl ModemLines for SerialLines {
+    fn read_lines(&mut self) -> io::Result<LineState> {
+        Ok(LineState {
+            cts: self.port.read_clear_to_send()?,
+            dsr: self.port.read_data_set_ready()?,
+        })
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+}
+
+// Pseudo-terminal stand-in for testing: write "D"/"d" to the pty to raise/drop DSR
+// and "C"/"c" for CTS, e.g. `printf D > /dev/pts/5`
+#[cfg(unix)]
+pub struct PtyStandIn {
+    master: serialport::TTYPort,
+    slave: serialport::TTYPort,  // Kept open so the master stays readable
+    lines: LineState,
+}
+
+#[cfg(unix)]
+impl PtyStandIn {
+    pub fn open() -> serialport::Result<Self> {
+        use serialport::SerialPort;
+
+        let (mut master, slave) = serialport::TTYPort::pair()?;
+        master.set_timeout(Duration::from_millis(POLL_INTERVAL_MS))?;
+        println!("Serial stand-in: write D/d (DSR) and C/c (CTS) to {}",
+                 slave.name().unwrap_or_default());
+        Ok(PtyStandIn { master, slave, lines: LineState::default() })
+    }
+
+    pub fn slave_name(&self) -> String {
+        use serialport::SerialPort;
+        self.slave.name().unwrap_or_default()
+    }
+}
+
+#[cfg(unix)]
+impl ModemLines for PtyStandIn {
+    fn read_lines(&mut self) -> io::Result<LineState> {
+        use io::Read;
+        use serialport::SerialPort;
+
+        let mut buf = [0u8; 64];
+        while self.master.bytes_to_read()? > 0 {
+            let count = self.master.read(&mut buf)?;
+            for &byte in &buf[..count] {
+                match byte {
+                    b'C' => self.lines.cts = true,
+                    b'c' => self.lines.cts = false,
+                    b'D' => self.lines.dsr = true,
+                    b'd' => self.lines.dsr = false,
+                    _ => {}
+                }
+            }
+        }
+        Ok(self.lines)
+    }
+
+    fn describe(&self) -> String {
+        format!("stand-in {} (write D/d, C/c)", self.slave_name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerialChoice {
+    Off,
+    Port(String),
+    PseudoTerminal,  // Stand-in for testing (Unix only)
+}
+
+impl SerialChoice {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SerialChoice::Off => "Off",
+            SerialChoice::Port(name) => name,
+            SerialChoice::PseudoTerminal => "Pseudo-terminal (test)",
+        }
+    }
+
+    // Settings file form
+    pub fn to_setting(&self) -> String {
+        match self {
+            SerialChoice::Off => "off".to_string(),
+            SerialChoice::Port(name) => name.clone(),
+            SerialChoice::PseudoTerminal => "pty".to_string(),
+        }
+    }
+
+    pub fn from_setting(text: &str) -> SerialChoice {
+        match text {
+            "" | "off" => SerialChoice::Off,
+            "pty" => SerialChoice::PseudoTerminal,
+            name => SerialChoice::Port(name.to_string()),
+        }
+    }
+
+    fn open(&self) -> Option<io::Result<Box<dyn ModemLines>>> {
+        match self {
+            SerialChoice::Off => None,
+            SerialChoice::Port(name) => Some(SerialLines::open(name)
+                .map(|lines| Box::new(lines) as Box<dyn ModemLines>)
+                .map_err(io::Error::from)),
+            #[cfg(unix)]
+            SerialChoice::PseudoTerminal => Some(PtyStandIn::open()
+                .map(|lines| Box::new(lines) as Box<dyn ModemLines>)
+                .map_err(io::Error::from)),
+            #[cfg(not(unix))]
+            SerialChoice::PseudoTerminal => Some(Err(io::Error::new(
+                io::ErrorKind::Unsupported, "pseudo-terminals need Linux or macOS"))),
+        }
+    }
+}
+
+// Serial ports to offer in the picker; the stand-in is added on Unix
+pub fn get_serial_choices(ports: &[String]) -> Vec<SerialChoice> {
+    let mut choices = vec![SerialChoice::Off];
+    choices.extend(ports.iter().cloned().map(SerialChoice::Port));
+    if cfg!(unix) {
+        choices.push(SerialChoice::PseudoTerminal);
+    }
+    choices
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerialStatus {
+    Off,
+    Connected(String),
+    Error(String),
+}
+
+impl SerialStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            SerialStatus::Off => "Off".to_string(),
+            SerialStatus::Connected(port) => format!("Connected: {}", port),
+            SerialStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, SerialStatus::Connected(_))
+    }
+}
+
+// Called from the serial thread when a paddle changes
+pub type SerialHandler = Arc<dyn Fn(PaddleSide, bool) + Send + Sync>;
+
+struct Shared {
+    ports: Vec<String>,
+    choice: SerialChoice,
+    swap: bool,  // Default: DSR = LEFT, CTS = RIGHT
+    status: SerialStatus,
+    reopen: bool,
+}
+
+#[derive(Clone)]
+pub struct SerialInputManager {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl SerialInputManager {
+    pub fn start(choice: SerialChoice, swap: bool, handler: SerialHandler) -> Self {
+        let manager = SerialInputManager {
+            shared: Arc::new(Mutex::new(Shared {
+                ports: Vec::new(),
+                choice,
+                swap,
+                status: SerialStatus::Off,
+                reopen: false,
+            })),
+        };
+
+        let poller = manager.clone();
+        thread::spawn(move || poller.poll(handler));
+        manager
+    }
+
+    pub fn ports(&self) -> Vec<String> {
+        self.shared.lock().unwrap().ports.clone()
+    }
+
+    pub fn choice(&self) -> SerialChoice {
+        self.shared.lock().unwrap().choice.clone()
+    }
+
+    pub fn set_choice(&self, choice: SerialChoice) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.choice != choice {
+            shared.choice = choice;
+            shared.reopen = true;
+        }
+    }
+
+    pub fn swap(&self) -> bool {
+        self.shared.lock().unwrap().swap
+    }
+
+    pub fn set_swap(&self, swap: bool) {
+        self.shared.lock().unwrap().swap = swap;
+    }
+
+    pub fn status(&self) -> SerialStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    fn set_status(&self, status: SerialStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn poll(&self, handler: SerialHandler) {
+        let mut lines: Option<Box<dyn ModemLines>> = None;
+        let mut paddles = (false, false);
+        let mut next_scan = Instant::now();
+
+        loop {
+            if Instant::now() >= next_scan {
+                next_scan = Instant::now() + Duration::from_millis(RESCAN_INTERVAL_MS);
+                self.refresh_ports();
+
+                // (Re)open the chosen port; after an error this retries once per rescan
+                if lines.is_none() {
+                    let choice = self.choice();
+                    match choice.open() {
+                        None => self.set_status(SerialStatus::Off),
+                        Some(Ok(opened)) => {
+                            println!("Serial input connected: {}", opened.describe());
+                            self.set_status(SerialStatus::Connected(opened.describe()));
+                            lines = Some(opened);
+                        }
+                        Some(Err(e)) => {
+                            self.set_status(SerialStatus::Error(format!("{}: {}", choice.as_str(), e)));
+                        }
+                    }
+                }
+            }
+
+            if std::mem::take(&mut self.shared.lock().unwrap().reopen) {
+                lines = None;
+                next_scan = Instant::now();
+            }
+
+            let state = match lines.as_mut().map(|l| l.read_lines()) {
+                Some(Ok(state)) => state,
+                Some(Err(e)) => {
+                    eprintln!("Serial input error: {}", e);
+                    self.set_status(SerialStatus::Error(e.to_string()));
+                    lines = None;
+                    LineState::default()
+                }
+                None => LineState::default(),
+            };
+
+            let (left, right) = paddle_levels(state, self.swap());
+            if left != paddles.0 {
+                handler(PaddleSide::Left, left);
+            }
+            if right != paddles.1 {
+                handler(PaddleSide::Right, right);
+            }
+            paddles = (left, right);
+
+            let interval = if lines.is_some() { POLL_INTERVAL_MS } else { 50 };
+            thread::sleep(Duration::from_millis(interval));
+        }
+    }
+
+    fn refresh_ports(&self) {
+        let ports: Vec<String> = serialport::available_ports()
+            .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+            .unwrap_or_default();
+        self.shared.lock().unwrap().ports = ports;
+    }
+}
+
+// LEFT and RIGHT paddle levels: DSR is LEFT and CTS is RIGHT, unless swapped
+fn paddle_levels(lines: LineState, swap: bool) -> (bool, bool) {
+    if swap { (lines.cts, lines.dsr) } else { (lines.dsr, lines.cts) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paddle_input::{PaddleContacts, PaddleSource};
+
+    #[test]
+    fn lines_map_to_paddles() {
+        let dsr = LineState { cts: false, dsr: true };
+        let cts = LineState { cts: true, dsr: false };
+        assert_eq!(paddle_levels(dsr, false), (true, false));
+        assert_eq!(paddle_levels(cts, false), (false, true));
+        assert_eq!(paddle_levels(dsr, true), (false, true));
+        assert_eq!(paddle_levels(cts, true), (true, false));
+        assert_eq!(paddle_levels(LineState::default(), false), (false, false));
+    }
+
+    // Poll until the condition holds, for up to two seconds
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        condition()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty_stand_in_drives_paddles() {
+        use std::io::Write;
+
+        let contacts = Arc::new(Mutex::new(PaddleContacts::default()));
+        let handler_contacts = Arc::clone(&contacts);
+        let manager = SerialInputManager::start(SerialChoice::PseudoTerminal, false,
+            Arc::new(move |side, pressed| {
+                handler_contacts.lock().unwrap().set(PaddleSource::Serial, side, pressed);
+            }));
+        assert!(wait_for(|| manager.status().is_connected()), "{:?}", manager.status());
+
+        // "stand-in /dev/pts/N (write D/d, C/c)"
+        let SerialStatus::Connected(described) = manager.status() else { unreachable!() };
+        let path = described.split_whitespace().nth(1).unwrap().to_string();
+        let mut pty = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let is_down = |side| contacts.lock().unwrap().is_down(side);
+
+        pty.write_all(b"D").unwrap();
+        assert!(wait_for(|| is_down(PaddleSide::Left)));
+        assert!(!is_down(PaddleSide::Right));
+
+        pty.write_all(b"dC").unwrap();
+        assert!(wait_for(|| !is_down(PaddleSide::Left) && is_down(PaddleSide::Right)));
+
+        // Swapped wiring: CTS, still raised, is now LEFT
+        manager.set_swap(true);
+        assert!(wait_for(|| is_down(PaddleSide::Left) && !is_down(PaddleSide::Right)));
+
+        pty.write_all(b"c").unwrap();
+        assert!(wait_for(|| !is_down(PaddleSide::Left) && !is_down(PaddleSide::Right)));
+    }
+}
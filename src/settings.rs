@@ -0,0 +1,93 @@
+// Persistent settings - a small "key = value" text file in the user's config directory
+// (%APPDATA%\paddle_decoder\settings.txt on Windows, ~/.config/paddle_decoder/settings.txt
+// elsewhere). Only choices that depend on the user's hardware are stored here.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+const SETTINGS_DIR: &str = "paddle_decoder";
+const SETTINGS_FILE: &str = "settings.txt";
+
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    values: BTreeMap<String, String>,
+}
+
+impl Settings {
+    // Missing or unreadable file gives empty settings
+    pub fn load() -> Self {
+        let mut settings = Settings::default();
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return settings,
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return settings,
+        };
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    settings.values.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                None => eprintln!("Ignoring settings line: {}", line),
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return,
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                eprintln!("Failed to create {}: {}", dir.display(), e);
+                return;
+            }
+        }
+
+        let mut text = String::from("# Paddle Decoder settings\n");
+        for (key, value) in &self.values {
+            text.push_str(&format!("{} = {}\n", key, value));
+        }
+        if let Err(e) = fs::write(&path, text) {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| value.as_str())
+    }
+
+    // Set a value (or remove it with None) and write the file
+    pub fn set(&mut self, key: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self.values.insert(key.to_string(), value.trim().to_string()),
+            None => self.values.remove(key),
+        };
+        self.save();
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(SETTINGS_FILE))
+}
+
+// The app's directory under the user's config directory
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|dir| dir.join(SETTINGS_DIR))
+}
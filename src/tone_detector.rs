@@ -0,0 +1,523 @@
+// CW tone detection
+// Turns audio into key-down / key-up events: a Goertzel filter measures the level at the
+// signal's pitch every 5 ms, and an adaptive threshold halfway between the tracked signal
+// and noise levels decides whether the key is down. The events go through MorseDecoder's
+// hand-keyed path, so recordings are decoded with the same speed tracking and word
+// spacing as a straight key.
+//
+// The pitch is found automatically (the strongest tone between 300 and 1500 Hz), or set
+// from outside, e.g. by clicking on a spectrum.
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::morse_decoder::MorseDecoder;
+use crate::morse_table::Alphabet;
+
+pub const MIN_PITCH_HZ: f32 = 300.0;
+pub const MAX_PITCH_HZ: f32 = 1500.0;
+
+// Goertzel block: 20 ms, about 50 Hz wide, moved on in 5 ms steps
+const BLOCK_MS: f64 = 20.0;
+const HOP_MS: f64 = 5.0;
+
+// Pitch search: 20 Hz wide bins 10 Hz apart
+const SEARCH_BLOCK_MS: f64 = 50.0;
+const SEARCH_STEP_HZ: f32 = 10.0;
+// How much audio the pitch search looks at
+const SEARCH_MS: f64 = 20_000.0;
+const STREAM_SEARCH_MS: f64 = 1500.0;
+// Audio a live stream keeps, and how much of it the spectrum shows
+const RECENT_MS: f64 = 2000.0;
+const SPECTRUM_MS: f64 = 500.0;
+// A tone this much stronger than the median bin counts as a signal (noise alone stays
+// within a few percent once averaged)
+const MIN_PEAK_RATIO: f32 = 2.0;
+
+// Threshold between noise (0) and signal (1), with hysteresis
+const KEY_DOWN_LEVEL: f32 = 0.6;
+const KEY_UP_LEVEL: f32 = 0.4;
+// Signal must be this far above the average noise before anything is keyed, so a pitch
+// with no signal on it stays quiet
+const MIN_SNR: f32 = 3.0;
+// How fast the tracked signal and noise levels follow the audio, per 5 ms step
+const LEVEL_DECAY: f32 = 0.003;
+const FLOOR_RATE: f32 = 0.01;
+// A change must last this many steps, so clicks and dropouts are ignored
+const DEBOUNCE_HOPS: u32 = 3;
+
+// Key-up gaps from this many dits on are letter or word gaps
+const LETTER_GAP_DITS: f64 = 2.0;
+// Letter gaps are most of the gaps; from this length on they are stretched (Farnsworth)
+const STRETCHED_GAP_DITS: f64 = 4.0;
+// A gap this much longer than the one below starts the word gaps
+const GAP_GROUP_RATIO: f64 = 1.6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub key_down: bool,
+    pub at_ms: f64,  // From the first sample
+}
+
+// Level of the given frequency in the block, 0.0 - 1.0 for a full-scale sine
+pub fn goertzel(samples: &[f32], sample_rate: u32, frequency: f32) -> f32 {
+    let omega = 2.0 * std::f32::consts::PI * frequency / sample_rate as f32;
+    let coefficient = 2.0 * omega.cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    for &sample in samples {
+        let s0 = sample + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let power = (s1 * s1 + s2 * s2 - coefficient * s1 * s2).max(0.0);
+    2.0 * power.sqrt() / samples.len().max(1) as f32
+}
+
+// Average level per frequency between MIN_PITCH_HZ and MAX_PITCH_HZ
+pub fn spectrum(samples: &[f32], sample_rate: u32) -> Vec<(f32, f32)> {
+    let block = (sample_rate as f64 * SEARCH_BLOCK_MS / 1000.0) as usize;
+    let blocks: Vec<&[f32]> = samples.chunks_exact(block.max(1)).collect();
+    let steps = ((MAX_PITCH_HZ - MIN_PITCH_HZ) / SEARCH_STEP_HZ) as usize;
+
+    (0..=steps)
+        .map(|step| {
+            let frequency = MIN_PITCH_HZ + step as f32 * SEARCH_STEP_HZ;
+            let total: f32 = blocks.iter().map(|b| goertzel(b, sample_rate, frequency)).sum();
+            (frequency, total / blocks.len().max(1) as f32)
+        })
+        .collect()
+}
+
+// Strongest tone in the audio, if one stands out from the noise
+pub fn find_pitch(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let spectrum = spectrum(samples, sample_rate);
+    let (peak, &(frequency, level)) = spectrum.iter().enumerate()
+        .max_by(|a, b| a.1.1.total_cmp(&b.1.1))?;
+
+    let mut levels: Vec<f32> = spectrum.iter().map(|&(_, level)| level).collect();
+    levels.sort_by(f32::total_cmp);
+    let median = levels[levels.len() / 2];
+    if level <= 0.0 || level < median * MIN_PEAK_RATIO {
+        return None;
+    }
+
+    // Between the bins: the top of a parabola through the peak and its neighbours
+    if peak == 0 || peak + 1 == spectrum.len() {
+        return Some(frequency);
+    }
+    let (left, right) = (spectrum[peak - 1].1, spectrum[peak + 1].1);
+    let curve = left - 2.0 * level + right;
+    let shift = if curve < 0.0 { 0.5 * (left - right) / curve } else { 0.0 };
+    Some(frequency + shift.clamp(-0.5, 0.5) * SEARCH_STEP_HZ)
+}
+
+// Streaming detector: feed audio in any chunk size, get the key changes back
+pub struct ToneDetector {
+    sample_rate: u32,
+    block: usize,
+    hop: usize,
+    pitch: Option<f32>,
+    buffer: Vec<f32>,   // Audio not analysed yet, from `start` on
+    start: usize,
+    time_ms: f64,       // Time of buffer[start]
+    signal: f32,
+    noise: f32,
+    floor: f32,         // Average level while the key is up
+    key_down: bool,
+    pending: u32,       // Steps the opposite state has lasted
+    pending_since: f64,
+}
+
+impl ToneDetector {
+    // Without a pitch, the first 1.5 s of audio are searched for one
+    pub fn new(sample_rate: u32, pitch: Option<f32>) -> Self {
+        ToneDetector {
+            sample_rate,
+            block: (sample_rate as f64 * BLOCK_MS / 1000.0) as usize,
+            hop: (sample_rate as f64 * HOP_MS / 1000.0) as usize,
+            pitch,
+            buffer: Vec::new(),
+            start: 0,
+            time_ms: 0.0,
+            signal: 0.0,
+            noise: 0.0,
+            floor: 0.0,
+            key_down: false,
+            pending: 0,
+            pending_since: 0.0,
+        }
+    }
+
+    // Start the signal and noise levels from a stretch of audio at the current pitch, so
+    // the first elements are not lost while the levels settle
+    pub fn prime(&mut self, samples: &[f32]) {
+        let Some(pitch) = self.pitch else { return };
+        let block = self.block.max(1);
+        let mut levels: Vec<f32> = samples.windows(block)
+            .step_by(self.hop.max(1))
+            .map(|window| goertzel(window, self.sample_rate, pitch))
+            .collect();
+        if levels.is_empty() {
+            return;
+        }
+        levels.sort_by(f32::total_cmp);
+        self.noise = levels[levels.len() / 10];
+        self.floor = levels[levels.len() / 4];
+        self.signal = levels[levels.len() - 1 - levels.len() / 20];
+    }
+
+    pub fn pitch(&self) -> Option<f32> {
+        self.pitch
+    }
+
+    // Listen on another pitch, or search again with None. The levels start over, so the
+    // detector wants priming again; a key that was down comes up.
+    pub fn set_pitch(&mut self, pitch: Option<f32>) -> Option<KeyEvent> {
+        let event = self.finish();
+        self.pitch = pitch;
+        self.signal = 0.0;
+        self.noise = 0.0;
+        self.floor = 0.0;
+        self.pending = 0;
+        event
+    }
+
+    // Time of the audio analysed so far, from the first sample
+    pub fn time_ms(&self) -> f64 {
+        self.time_ms
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<KeyEvent> {
+        // Analysed audio is dropped here rather than after every step
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        self.buffer.extend_from_slice(samples);
+        let mut events = Vec::new();
+
+        let pitch = match self.pitch {
+            Some(pitch) => pitch,
+            None => {
+                let search = (self.sample_rate as f64 * STREAM_SEARCH_MS / 1000.0) as usize;
+                if self.buffer.len() < search {
+                    return events;
+                }
+                match find_pitch(&self.buffer, self.sample_rate) {
+                    Some(pitch) => {
+                        self.pitch = Some(pitch);
+                        let search_audio = std::mem::take(&mut self.buffer);
+                        self.prime(&search_audio);
+                        self.buffer = search_audio;
+                        pitch
+                    }
+                    None => {
+                        // Nothing yet, keep the latest half for the next try
+                        self.skip(self.buffer.len() - search / 2);
+                        return events;
+                    }
+                }
+            }
+        };
+
+        let block = self.block.max(1);
+        while self.buffer.len() - self.start >= block {
+            let level = goertzel(&self.buffer[self.start..self.start + block], self.sample_rate, pitch);
+            if let Some(event) = self.step(level) {
+                events.push(event);
+            }
+            self.skip(self.hop.max(1));
+        }
+        events
+    }
+
+    // Key up at the end of the audio
+    pub fn finish(&mut self) -> Option<KeyEvent> {
+        self.key_down.then(|| {
+            self.key_down = false;
+            KeyEvent { key_down: false, at_ms: self.time_ms }
+        })
+    }
+
+    fn skip(&mut self, samples: usize) {
+        self.start += samples;
+        self.time_ms += samples as f64 * 1000.0 / self.sample_rate as f64;
+    }
+
+    fn step(&mut self, level: f32) -> Option<KeyEvent> {
+        // Both levels jump towards a new extreme and drift back slowly
+        if level > self.signal {
+            self.signal = level;
+        } else {
+            self.signal += (level - self.signal) * LEVEL_DECAY;
+        }
+        if level < self.noise {
+            self.noise = level;
+        } else {
+            self.noise += (level - self.noise) * LEVEL_DECAY;
+        }
+
+        if !self.key_down && self.pending == 0 {
+            self.floor += (level - self.floor) * FLOOR_RATE;
+        }
+
+        let range = self.signal - self.noise;
+        let tone = self.signal > self.floor * MIN_SNR && range > 0.0;
+        let raw_down = tone && if self.key_down {
+            level > self.noise + range * KEY_UP_LEVEL
+        } else {
+            level > self.noise + range * KEY_DOWN_LEVEL
+        };
+
+        if raw_down == self.key_down {
+            self.pending = 0;
+            return None;
+        }
+        if self.pending == 0 {
+            self.pending_since = self.time_ms;
+        }
+        self.pending += 1;
+        if self.pending < DEBOUNCE_HOPS {
+            return None;
+        }
+        self.pending = 0;
+        self.key_down = raw_down;
+        Some(KeyEvent { key_down: raw_down, at_ms: self.pending_since })
+    }
+}
+
+// Feeds key events to a decoder as hand-keyed elements, timed from `start`. The decoder
+// times the letter and word gaps too, on the clock of the events rather than the wall
+// clock, so a recording decodes the same however fast it is processed.
+pub struct EventDecoder {
+    start: Instant,
+    down_at: Option<f64>,
+}
+
+impl EventDecoder {
+    pub fn new(start: Instant) -> Self {
+        EventDecoder { start, down_at: None }
+    }
+
+    fn at(&self, ms: f64) -> Instant {
+        self.start + Duration::from_secs_f64(ms.max(0.0) / 1000.0)
+    }
+
+    pub fn event(&mut self, decoder: &mut MorseDecoder, event: KeyEvent, text: &mut String) {
+        if event.key_down {
+            // The gap before this element may have ended a letter or a word
+            self.flush(decoder, event.at_ms, text);
+            self.down_at = Some(event.at_ms);
+        } else if let Some(down_at) = self.down_at.take() {
+            let duration_ms = (event.at_ms - down_at).round() as u32;
+            decoder.add_timed_element_at(duration_ms, self.at(event.at_ms));
+        }
+    }
+
+    // Letter and word ended by the given time, if the key is up
+    pub fn flush(&mut self, decoder: &mut MorseDecoder, ms: f64, text: &mut String) {
+        if self.down_at.is_some() {
+            return;
+        }
+        while let Some(decoded) = decoder.check_timeout_at(self.at(ms)) {
+            text.push_str(&decoded);
+        }
+    }
+}
+
+// Live decoding of an audio stream, e.g. a receiver on the sound card. Audio comes in any
+// chunk size and the text comes back as soon as it is decoded. The last two seconds are
+// kept for the spectrum and to start the levels after a change of pitch.
+pub struct StreamDecoder {
+    sample_rate: u32,
+    detector: ToneDetector,
+    events: EventDecoder,
+    recent: Vec<f32>,
+}
+
+impl StreamDecoder {
+    // Without a pitch, the strongest tone is searched for
+    pub fn new(sample_rate: u32, pitch: Option<f32>) -> Self {
+        StreamDecoder {
+            sample_rate,
+            detector: ToneDetector::new(sample_rate, pitch),
+            events: EventDecoder::new(Instant::now()),
+            recent: Vec::new(),
+        }
+    }
+
+    pub fn pitch(&self) -> Option<f32> {
+        self.detector.pitch()
+    }
+
+    pub fn set_pitch(&mut self, decoder: &mut MorseDecoder, pitch: Option<f32>) -> String {
+        let mut text = String::new();
+        if let Some(event) = self.detector.set_pitch(pitch) {
+            self.events.event(decoder, event, &mut text);
+        }
+        self.detector.prime(&self.recent);
+        text
+    }
+
+    pub fn feed(&mut self, decoder: &mut MorseDecoder, samples: &[f32]) -> String {
+        let keep = (self.sample_rate as f64 * RECENT_MS / 1000.0) as usize;
+        self.recent.extend_from_slice(samples);
+        if self.recent.len() > keep * 2 {
+            self.recent.drain(..self.recent.len() - keep);
+        }
+
+        let mut text = String::new();
+        for event in self.detector.process(samples) {
+            self.events.event(decoder, event, &mut text);
+        }
+        self.events.flush(decoder, self.detector.time_ms(), &mut text);
+        text
+    }
+
+    // Average level per frequency over the last half second (see spectrum())
+    pub fn spectrum(&self) -> Vec<(f32, f32)> {
+        let length = (self.sample_rate as f64 * SPECTRUM_MS / 1000.0) as usize;
+        let recent = &self.recent[self.recent.len().saturating_sub(length)..];
+        spectrum(recent, self.sample_rate)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioDecode {
+    pub text: String,
+    pub pitch: f32,
+    pub wpm: Option<u32>,
+}
+
+// Decode a whole recording, starting out at `wpm`. The first pass learns the sender's
+// speed, the second decodes with it and any Farnsworth spacing from the start. Each pass
+// has a decoder of its own, so nothing is left behind in the caller's.
+pub fn decode_samples(samples: &[f32], sample_rate: u32, wpm: u32, alphabet: Alphabet,
+                      prefer_prosigns: bool) -> Option<AudioDecode> {
+    let search = samples.len().min((sample_rate as f64 * SEARCH_MS / 1000.0) as usize);
+    let pitch = find_pitch(&samples[..search], sample_rate)?;
+
+    let mut detector = ToneDetector::new(sample_rate, Some(pitch));
+    detector.prime(&samples[..search]);
+    let mut events = detector.process(samples);
+    events.extend(detector.finish());
+    let end_ms = samples.len() as f64 * 1000.0 / sample_rate as f64;
+
+    let new_decoder = |wpm| {
+        let mut decoder = MorseDecoder::new(wpm);
+        decoder.set_alphabet(alphabet);
+        decoder.set_prefer_prosigns(prefer_prosigns);
+        decoder
+    };
+    let mut decoder = new_decoder(wpm);
+    let mut text = String::new();
+    for pass in 0..2 {
+        if pass == 1 {
+            match decoder.estimated_wpm() {
+                Some(wpm) => {
+                    decoder = new_decoder(wpm);
+                    decoder.update_wpm(wpm);
+                    let dit_ms = decoder.dit_length_ms() as f64;
+                    decoder.update_farnsworth(farnsworth_wpm(&events, dit_ms).unwrap_or(wpm));
+                }
+                None => break,
+            }
+        }
+        text.clear();
+        let mut events_decoder = EventDecoder::new(Instant::now());
+        for &event in &events {
+            events_decoder.event(&mut decoder, event, &mut text);
+        }
+        // Long enough after the end for the last word to finish
+        events_decoder.flush(&mut decoder, end_ms + 60_000.0, &mut text);
+    }
+
+    Some(AudioDecode { text: text.trim().to_string(), pitch, wpm: decoder.estimated_wpm() })
+}
+
+// Effective (Farnsworth) speed of a recording, from its key-up gaps. Letter and word gaps
+// form two groups 3:7 apart, and Farnsworth spacing stretches both: the spacing unit is
+// then a third of the typical letter gap. With one group only (e.g. single letters), or
+// gaps that are not stretched, there is nothing to go by, as noise blurs the groups.
+fn farnsworth_wpm(events: &[KeyEvent], dit_ms: f64) -> Option<u32> {
+    let mut gaps: Vec<f64> = events.windows(2)
+        .filter(|pair| !pair[0].key_down && pair[1].key_down)
+        .map(|pair| (pair[1].at_ms - pair[0].at_ms) / dit_ms.max(1.0))
+        .filter(|&gap| gap > LETTER_GAP_DITS)
+        .collect();
+    gaps.sort_by(f64::total_cmp);
+    if gaps.get(gaps.len() / 2).is_none_or(|&median| median < STRETCHED_GAP_DITS) {
+        return None;
+    }
+    let word_gaps = gaps.windows(2).position(|pair| pair[1] > pair[0] * GAP_GROUP_RATIO)? + 1;
+    let letter_gap = gaps[word_gaps / 2];
+    let unit_ms = (letter_gap / 3.0).max(1.0) * dit_ms;
+    // PARIS is 31 dits of elements and 19 spacing units (see farnsworth_extra_per_unit_ms())
+    Some((60_000.0 / (31.0 * dit_ms + 19.0 * unit_ms)).round() as u32)
+}
+
+// Mono samples of a WAV file (channels mixed) and its sample rate
+pub fn read_wav(path: &Path) -> Result<(Vec<f32>, u32), hound::Error> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = samples.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+// Decode a WAV file; Ok(None) when no CW tone was found in it
+pub fn decode_wav(path: &Path, wpm: u32, alphabet: Alphabet, prefer_prosigns: bool)
+                  -> Result<Option<AudioDecode>, hound::Error> {
+    let (samples, sample_rate) = read_wav(path)?;
+    Ok(decode_samples(&samples, sample_rate, wpm, alphabet, prefer_prosigns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::morse_player::MorsePlayer;
+
+    const TEXT: &str = "CQ CQ DE DL1ABC K THE QUICK BROWN FOX 73";
+
+    // Render the text with the player, write it out and decode the file again
+    fn round_trip(char_wpm: u32, effective_wpm: u32) -> AudioDecode {
+        let player = MorsePlayer::new_with_alphabet(600.0, char_wpm, effective_wpm, Alphabet::Latin);
+        let path = std::env::temp_dir()
+            .join(format!("tone_detector_{}_{}_{}.wav", std::process::id(), char_wpm, effective_wpm));
+        player.export_wav(&path, TEXT).unwrap();
+        let decoded = decode_wav(&path, 20, Alphabet::Latin, false);
+        std::fs::remove_file(&path).ok();
+        decoded.unwrap().expect("no tone found")
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        for wpm in [12, 15, 20, 25, 30] {
+            let decoded = round_trip(wpm, wpm);
+            assert_eq!(decoded.text, TEXT, "at {} WPM", wpm);
+            assert!((decoded.pitch - 600.0).abs() < 10.0, "pitch {} at {} WPM", decoded.pitch, wpm);
+        }
+    }
+
+    #[test]
+    fn farnsworth_round_trip() {
+        for (char_wpm, effective_wpm) in [(18, 10), (20, 12), (20, 15), (25, 18), (30, 20)] {
+            let decoded = round_trip(char_wpm, effective_wpm);
+            assert_eq!(decoded.text, TEXT, "at {}/{} WPM", char_wpm, effective_wpm);
+        }
+    }
+
+    #[test]
+    fn silence_has_no_tone() {
+        let silence = vec![0.0; 48_000];
+        assert!(decode_samples(&silence, 48_000, 20, Alphabet::Latin, false).is_none());
+    }
+}
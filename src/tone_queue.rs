@@ -0,0 +1,272 @@
+// Sample-accurate tone scheduling
+// A ToneQueue holds key-down / key-up segments measured in samples and one audio source
+// renders them back to back, so element and gap lengths are exact no matter how late the
+// thread that queued them wakes up. With nothing queued the source follows a live key
+// instead (straight key contact), and plays silence while the key is up.
+//
+// Everything queued or keyed can be mirrored to a transmitter keying output.
+use rodio::Source;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::envelope::KeyingEnvelope;
+use crate::keying_output::KeyingOutput;
+
+pub const SAMPLE_RATE: u32 = 48000;
+pub const AMPLITUDE: f32 = 0.3;
+
+#[derive(Default)]
+struct Schedule {
+    segments: VecDeque<(bool, u64)>,  // Key down, length in samples
+    carry: f64,  // Fraction of a sample left over from the previous segment
+}
+
+struct Shared {
+    schedule: Mutex<Schedule>,
+    pending_samples: AtomicU64,  // Queued samples not rendered yet
+    closed: AtomicBool,          // Source ends once the queue has played out
+    live_key: AtomicBool,
+    frequency: AtomicU32,        // f32 bits
+    edge_ms: AtomicU32,
+    mirror: Mutex<Option<KeyingOutput>>,
+}
+
+#[derive(Clone)]
+pub struct ToneQueue {
+    shared: Arc<Shared>,
+}
+
+impl ToneQueue {
+    pub fn new(frequency: f32, edge_ms: u32) -> Self {
+        ToneQueue {
+            shared: Arc::new(Shared {
+                schedule: Mutex::new(Schedule::default()),
+                pending_samples: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+                live_key: AtomicBool::new(false),
+                frequency: AtomicU32::new(frequency.to_bits()),
+                edge_ms: AtomicU32::new(edge_ms),
+                mirror: Mutex::new(None),
+            }),
+        }
+    }
+
+    // Audio source playing this queue; create one per sink
+    pub fn source(&self) -> ToneQueueSource {
+        let envelope = KeyingEnvelope::new(self.shared.edge_ms.load(Ordering::Relaxed), SAMPLE_RATE);
+        ToneQueueSource {
+            queue: self.clone(),
+            phase: 0.0,
+            envelope,
+            segment_key_down: false,
+            segment_left: 0,
+        }
+    }
+
+    // Queue the key down or up for the given time. Fractions of a sample are carried
+    // into the next segment so long sequences do not drift.
+    pub fn push(&self, key_down: bool, duration_ms: f64) {
+        if let Some(output) = self.shared.mirror.lock().unwrap().as_ref() {
+            output.push(key_down, duration_ms);
+        }
+
+        let mut schedule = self.shared.schedule.lock().unwrap();
+        let exact = duration_ms.max(0.0) * SAMPLE_RATE as f64 / 1000.0 + schedule.carry;
+        let samples = exact.floor();
+        schedule.carry = exact - samples;
+
+        let samples = samples as u64;
+        if samples > 0 {
+            schedule.segments.push_back((key_down, samples));
+            self.shared.pending_samples.fetch_add(samples, Ordering::Relaxed);
+        }
+    }
+
+    // Time left before everything queued so far has been rendered
+    pub fn queued_ms(&self) -> f64 {
+        self.shared.pending_samples.load(Ordering::Relaxed) as f64 * 1000.0 / SAMPLE_RATE as f64
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.shared.pending_samples.load(Ordering::Relaxed) == 0
+    }
+
+    // No more segments will be queued; the source ends after the last one
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+    }
+
+    // Live key, used while nothing is queued
+    pub fn key(&self, down: bool) {
+        if let Some(output) = self.shared.mirror.lock().unwrap().as_ref() {
+            output.key(down);
+        }
+        self.shared.live_key.store(down, Ordering::Relaxed);
+    }
+
+    // Send everything queued or keyed from now on to the keying output too
+    pub fn mirror_to(&self, output: KeyingOutput) {
+        *self.shared.mirror.lock().unwrap() = Some(output);
+    }
+
+    pub fn set_frequency(&self, frequency: f32) {
+        self.shared.frequency.store(frequency.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_edge_ms(&self, edge_ms: u32) {
+        self.shared.edge_ms.store(edge_ms, Ordering::Relaxed);
+    }
+}
+
+pub struct ToneQueueSource {
+    queue: ToneQueue,
+    phase: f32,
+    envelope: KeyingEnvelope,
+    segment_key_down: bool,
+    segment_left: u64,
+}
+
+impl ToneQueueSource {
+    fn next_segment(&mut self) {
+        let mut schedule = self.queue.shared.schedule.lock().unwrap();
+        if let Some((key_down, samples)) = schedule.segments.pop_front() {
+            self.segment_key_down = key_down;
+            self.segment_left = samples;
+        }
+    }
+}
+
+impl Iterator for ToneQueueSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.segment_left == 0 && !self.queue.is_idle() {
+            self.next_segment();
+        }
+        let shared = &self.queue.shared;
+
+        let key_down = if self.segment_left > 0 {
+            self.segment_left -= 1;
+            shared.pending_samples.fetch_sub(1, Ordering::Relaxed);
+            self.segment_key_down
+        } else {
+            if shared.closed.load(Ordering::Relaxed) && self.envelope.is_silent() {
+                return None;
+            }
+            shared.live_key.load(Ordering::Relaxed)
+        };
+
+        if !key_down && self.envelope.is_silent() {
+            // Edge time only changes between tones, each tone starts at a zero crossing
+            self.envelope.set_edge_ms(shared.edge_ms.load(Ordering::Relaxed), SAMPLE_RATE);
+            self.phase = 0.0;
+            return Some(0.0);
+        }
+
+        let gain = self.envelope.next_gain(key_down);
+        let frequency = f32::from_bits(shared.frequency.load(Ordering::Relaxed));
+        let sample = (self.phase * 2.0 * std::f32::consts::PI).sin() * AMPLITUDE * gain;
+        self.phase += frequency / SAMPLE_RATE as f32;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        Some(sample)
+    }
+}
+
+impl Source for ToneQueueSource {
+    fn current_frame_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { 1 }
+    fn sample_rate(&self) -> u32 { SAMPLE_RATE }
+    fn total_duration(&self) -> Option<Duration> { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES_PER_MS: usize = SAMPLE_RATE as usize / 1000;
+
+    #[test]
+    fn segments_render_their_exact_length() {
+        let queue = ToneQueue::new(600.0, 1);
+        queue.push(true, 10.0);
+        queue.push(false, 5.0);
+        queue.push(true, 2.5);
+        queue.push(false, 7.0);
+        queue.close();
+        assert_eq!(queue.source().count(), (10 + 5 + 7) * SAMPLES_PER_MS + SAMPLES_PER_MS * 5 / 2);
+    }
+
+    #[test]
+    fn odd_lengths_do_not_drift() {
+        let queue = ToneQueue::new(600.0, 1);
+        // 1000 dits and gaps at 27 WPM, 2133.33 samples each
+        let dit_ms = 1200.0 / 27.0;
+        for _ in 0..1000 {
+            queue.push(true, dit_ms);
+            queue.push(false, dit_ms);
+        }
+        queue.close();
+        let expected = 2000.0 * dit_ms * SAMPLE_RATE as f64 / 1000.0;
+        assert!((queue.queued_ms() * SAMPLES_PER_MS as f64 - expected).abs() <= 1.0);
+        let rendered = queue.source().count() as f64;
+        assert!((rendered - expected).abs() <= 1.0, "{rendered} samples, expected {expected}");
+    }
+
+    #[test]
+    fn queued_time_counts_down() {
+        let queue = ToneQueue::new(600.0, 5);
+        assert!(queue.is_idle());
+        queue.push(true, 100.0);
+        assert!(!queue.is_idle());
+        assert_eq!(queue.queued_ms(), 100.0);
+
+        let mut source = queue.source();
+        source.by_ref().take(50 * SAMPLES_PER_MS).for_each(drop);
+        assert_eq!(queue.queued_ms(), 50.0);
+        assert!(!queue.is_idle());
+        source.by_ref().take(50 * SAMPLES_PER_MS).for_each(drop);
+        assert_eq!(queue.queued_ms(), 0.0);
+        assert!(queue.is_idle());
+    }
+
+    #[test]
+    fn close_lets_the_last_tone_fade_out() {
+        let queue = ToneQueue::new(600.0, 5);
+        queue.push(true, 10.0);
+        queue.close();
+        let samples: Vec<f32> = queue.source().collect();
+        // The 5 ms release after the key-down segment still plays
+        assert_eq!(samples.len(), 15 * SAMPLES_PER_MS);
+        let release = &samples[10 * SAMPLES_PER_MS..];
+        assert!(release[..release.len() / 2].iter().any(|s| s.abs() > 0.1 * AMPLITUDE));
+    }
+
+    #[test]
+    fn closed_empty_queue_ends_at_once() {
+        let queue = ToneQueue::new(600.0, 5);
+        queue.close();
+        assert_eq!(queue.source().next(), None);
+    }
+
+    #[test]
+    fn live_key_plays_while_nothing_is_queued() {
+        let queue = ToneQueue::new(600.0, 5);
+        let mut source = queue.source();
+        assert!(source.by_ref().take(10 * SAMPLES_PER_MS).all(|s| s == 0.0));
+
+        queue.key(true);
+        let tone: Vec<f32> = source.by_ref().take(20 * SAMPLES_PER_MS).collect();
+        let peak = tone[10 * SAMPLES_PER_MS..].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - AMPLITUDE).abs() < 0.01 * AMPLITUDE, "peak {peak}");
+
+        queue.key(false);
+        let after: Vec<f32> = source.by_ref().take(20 * SAMPLES_PER_MS).collect();
+        assert!(after[10 * SAMPLES_PER_MS..].iter().all(|&s| s == 0.0));
+        // The queue was never used, so nothing counts as pending
+        assert!(queue.is_idle());
+    }
+}
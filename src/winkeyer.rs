@@ -0,0 +1,731 @@
+// K1EL WinKeyer support
+// The WinKeyer binary protocol over a serial port (1200 baud, 8N2): the host opens the
+// keyer, sets its speed and sends text to be keyed; the keyer sends back status bytes,
+// speed pot bytes and the characters decoded from its paddle (paddle echo).
+//
+// The protocol helpers are shared with the emulation in winkeyer_emulator.rs. A fake
+// WinKeyer on a pseudo-terminal (Unix) stands in for the real device when testing.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const BAUD_RATE: u32 = 1200;
+
+// Commands
+pub const CMD_ADMIN: u8 = 0x00;
+pub const CMD_SPEED: u8 = 0x02;
+pub const CMD_CLEAR_BUFFER: u8 = 0x0A;
+pub const CMD_MODE: u8 = 0x0E;
+pub const CMD_REQUEST_STATUS: u8 = 0x15;
+pub const CMD_MERGE: u8 = 0x1B;
+
+// Admin subcommands
+pub const ADMIN_HOST_OPEN: u8 = 0x02;
+pub const ADMIN_HOST_CLOSE: u8 = 0x03;
+pub const ADMIN_ECHO_TEST: u8 = 0x04;
+
+// Mode register bits
+pub const MODE_PADDLE_ECHO: u8 = 0x40;
+pub const MODE_SERIAL_ECHO: u8 = 0x04;
+
+// Status byte (0b110x_xxxx) bits
+pub const STATUS_XOFF: u8 = 0x01;    // Buffer more than 2/3 full
+pub const STATUS_BREAKIN: u8 = 0x02; // Paddles in use
+pub const STATUS_BUSY: u8 = 0x04;    // Keying
+
+// Version reported by the fake and the emulator (WinKeyer 2.3)
+pub const EMULATED_VERSION: u8 = 23;
+
+// What a byte from the WinKeyer means
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WinkeyerByte {
+    Status(u8),    // Status flags
+    SpeedPot(u8),  // Speed pot position, 0-63 above the pot minimum
+    Echo(char),    // Character echoed from the paddle (or from the host text)
+}
+
+pub fn classify(byte: u8) -> WinkeyerByte {
+    match byte & 0xC0 {
+        0xC0 => WinkeyerByte::Status(byte & 0x1F),
+        0x80 => WinkeyerByte::SpeedPot(byte & 0x3F),
+        _ => WinkeyerByte::Echo(byte as char),
+    }
+}
+
+pub fn status_byte(flags: u8) -> u8 {
+    0xC0 | (flags & 0x1F)
+}
+
+// Number of bytes that follow a command byte, given the bytes after it seen so far.
+// None means the length depends on a byte not received yet.
+pub fn command_args(command: &[u8]) -> Option<usize> {
+    let args = match command[0] {
+        CMD_ADMIN => match command.get(1)? {
+            // Calibrate, echo test, standalone message, X1MODE, antenna switch,
+            // X2MODE, sidetone volume
+            0x00 | 0x04 | 0x0E | 0x0F | 0x13 | 0x16 | 0x19 => 2,
+            _ => 1,
+        },
+        0x04 | CMD_MERGE => 2,   // PTT lead/tail, merge letters
+        0x05 => 3,               // Speed pot setup
+        0x0F => 15,              // Load defaults
+        // Pointer command: "add nulls" has a count
+        0x16 => match command.get(1)? {
+            0x03 => 2,
+            _ => 1,
+        },
+        0x07 | 0x08 | CMD_CLEAR_BUFFER | 0x13 | CMD_REQUEST_STATUS | 0x1E | 0x1F => 0,
+        0x01..=0x1F => 1,
+        _ => 0,  // Text
+    };
+    Some(args)
+}
+
+// Text as WinKeyer buffer bytes: upper case, <XX> prosigns as merged letters,
+// characters the WinKeyer cannot send are dropped
+pub fn encode_text(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut rest = text;
+
+    while let Some(ch) = rest.chars().next() {
+        // Two-letter prosigns are sent with the merge command
+        if ch == '<' {
+            if let Some(end) = rest.find('>') {
+                let letters: Vec<u8> = rest[1..end].bytes().map(|b| b.to_ascii_uppercase()).collect();
+                if letters.len() == 2 && letters.iter().all(|b| b.is_ascii_alphanumeric()) {
+                    bytes.extend([CMD_MERGE, letters[0], letters[1]]);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        rest = &rest[ch.len_utf8()..];
+        let ch = ch.to_ascii_uppercase();
+        if ch.is_ascii() && (' '..='Z').contains(&ch) && !"#%*;".contains(ch) {
+            bytes.push(ch as u8);
+        }
+    }
+    bytes
+}
+
+// Byte stream to and from a WinKeyer
+pub trait WinkeyerLink: Read + Write + Send {}
+impl<T: Read + Write + Send> WinkeyerLink for T {}
+
+fn open_port(path: &str) -> io::Result<Box<dyn WinkeyerLink>> {
+    let port = serialport::new(path, BAUD_RATE)
+        .stop_bits(serialport::StopBits::Two)
+        .timeout(Duration::from_millis(10))
+        .open()?;
+    Ok(Box::new(port))
+}
+
+// Wait for one byte; None on timeout
+fn read_byte(link: &mut dyn WinkeyerLink, timeout: Duration) -> io::Result<Option<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1];
+    while Instant::now() < deadline {
+        match link.read(&mut buf) {
+            Ok(1) => return Ok(Some(buf[0])),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WinkeyerChoice {
+    Off,
+    Port(String),
+    StandIn,  // Fake WinKeyer on a pseudo-terminal (Unix only)
+}
+
+impl WinkeyerChoice {
+    pub fn as_str(&self) -> &str {
+        match self {
+            WinkeyerChoice::Off => "Off",
+            WinkeyerChoice::Port(name) => name,
+            WinkeyerChoice::StandIn => "Fake WinKeyer (test)",
+        }
+    }
+
+    pub fn to_setting(&self) -> String {
+        match self {
+            WinkeyerChoice::Off => "off".to_string(),
+            WinkeyerChoice::Port(name) => name.clone(),
+            WinkeyerChoice::StandIn => "fake".to_string(),
+        }
+    }
+
+    pub fn from_setting(text: &str) -> WinkeyerChoice {
+        match text {
+            "" | "off" => WinkeyerChoice::Off,
+            "fake" => WinkeyerChoice::StandIn,
+            name => WinkeyerChoice::Port(name.to_string()),
+        }
+    }
+}
+
+pub fn get_winkeyer_choices(ports: &[String]) -> Vec<WinkeyerChoice> {
+    let mut choices = vec![WinkeyerChoice::Off];
+    choices.extend(ports.iter().cloned().map(WinkeyerChoice::Port));
+    if cfg!(unix) {
+        choices.push(WinkeyerChoice::StandIn);
+    }
+    choices
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WinkeyerStatus {
+    Off,
+    Connected { port: String, version: u8 },
+    Error(String),
+}
+
+impl WinkeyerStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            WinkeyerStatus::Off => "Off".to_string(),
+            WinkeyerStatus::Connected { port, version } => format!("WinKeyer v{} on {}", version, port),
+            WinkeyerStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self, WinkeyerStatus::Connected { .. })
+    }
+}
+
+// Called from the WinKeyer thread with each character decoded from its paddle
+pub type EchoHandler = Arc<dyn Fn(char) + Send + Sync>;
+
+struct Shared {
+    choice: WinkeyerChoice,
+    reopen: bool,
+    status: WinkeyerStatus,
+    busy: bool,            // Keying text or paddle
+    speed_wpm: u32,
+    outgoing: VecDeque<u8>,  // Text bytes waiting for room in the keyer's buffer
+    clear: bool,           // Drop anything not keyed yet
+}
+
+#[derive(Clone)]
+pub struct WinkeyerHost {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl WinkeyerHost {
+    pub fn start(choice: WinkeyerChoice, speed_wpm: u32, handler: EchoHandler) -> Self {
+        let host = WinkeyerHost {
+            shared: Arc::new(Mutex::new(Shared {
+                choice,
+                reopen: false,
+                status: WinkeyerStatus::Off,
+                busy: false,
+                speed_wpm,
+                outgoing: VecDeque::new(),
+                clear: false,
+            })),
+        };
+
+        let runner = host.clone();
+        thread::spawn(move || runner.run(handler));
+        host
+    }
+
+    pub fn choice(&self) -> WinkeyerChoice {
+        self.shared.lock().unwrap().choice.clone()
+    }
+
+    pub fn set_choice(&self, choice: WinkeyerChoice) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.choice != choice {
+            shared.choice = choice;
+            shared.reopen = true;
+        }
+    }
+
+    pub fn status(&self) -> WinkeyerStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.status().is_connected()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared.busy || !shared.outgoing.is_empty()
+    }
+
+    // Speed in WPM (5-99); sent when it changes
+    pub fn set_speed(&self, wpm: u32) {
+        self.shared.lock().unwrap().speed_wpm = wpm.clamp(5, 99);
+    }
+
+    // Queue text to be keyed by the WinKeyer
+    pub fn send_text(&self, text: &str) {
+        self.shared.lock().unwrap().outgoing.extend(encode_text(text));
+    }
+
+    // Stop keying and drop anything still queued
+    pub fn clear(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.outgoing.clear();
+        shared.clear = true;
+    }
+
+    fn set_status(&self, status: WinkeyerStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self, handler: EchoHandler) {
+        // The fake WinKeyer lives as long as its connection
+        #[cfg(unix)]
+        let mut _fake: Option<FakeWinkeyer> = None;
+
+        loop {
+            let choice = {
+                let mut shared = self.shared.lock().unwrap();
+                shared.reopen = false;
+                shared.choice.clone()
+            };
+
+            let opened = match &choice {
+                WinkeyerChoice::Off => {
+                    self.set_status(WinkeyerStatus::Off);
+                    None
+                }
+                WinkeyerChoice::Port(path) => Some(open_port(path).map(|link| (link, path.clone()))),
+                #[cfg(unix)]
+                WinkeyerChoice::StandIn => Some(FakeWinkeyer::start().and_then(|fake| {
+                    let path = fake.host_path.clone();
+                    let link = open_port(&path)?;
+                    let description = format!("fake, paddle input {}", fake.paddle_path);
+                    _fake = Some(fake);
+                    Ok((link, description))
+                })),
+                #[cfg(not(unix))]
+                WinkeyerChoice::StandIn => Some(Err(io::Error::new(
+                    io::ErrorKind::Unsupported, "the fake WinKeyer needs Linux or macOS"))),
+            };
+
+            match opened {
+                Some(Ok((link, port))) => {
+                    if let Err(e) = self.session(link, &port, &handler) {
+                        eprintln!("WinKeyer error: {}", e);
+                        self.set_status(WinkeyerStatus::Error(e.to_string()));
+                    }
+                }
+                Some(Err(e)) => self.set_status(WinkeyerStatus::Error(format!("{}: {}", choice.as_str(), e))),
+                None => {}
+            }
+
+            #[cfg(unix)]
+            {
+                _fake = None;
+            }
+            self.shared.lock().unwrap().busy = false;
+
+            // Retry after an error (unplugged device) or wait for another choice
+            for _ in 0..20 {
+                if self.shared.lock().unwrap().reopen {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    // One open connection: returns when another port is chosen or on error
+    fn session(&self, mut link: Box<dyn WinkeyerLink>, port: &str, handler: &EchoHandler) -> io::Result<()> {
+        // Host open answers with the firmware version
+        link.write_all(&[CMD_ADMIN, ADMIN_HOST_OPEN])?;
+        let version = loop {
+            match read_byte(link.as_mut(), Duration::from_secs(2))? {
+                // Status or pot bytes may arrive first
+                Some(byte) if byte & 0x80 != 0 => continue,
+                Some(version) => break version,
+                None => return Err(io::Error::new(io::ErrorKind::TimedOut, "no answer to host open")),
+            }
+        };
+        println!("WinKeyer v{} opened on {}", version, port);
+
+        // Paddle echo only - serial echo would mix the host text into the decoded text
+        link.write_all(&[CMD_MODE, MODE_PADDLE_ECHO])?;
+        self.set_status(WinkeyerStatus::Connected { port: port.to_string(), version });
+
+        let mut sent_speed = 0;
+        let mut xoff = false;
+        let mut buf = [0u8; 64];
+
+        let result = loop {
+            let (speed, clear, reopen) = {
+                let mut shared = self.shared.lock().unwrap();
+                (shared.speed_wpm, std::mem::take(&mut shared.clear), shared.reopen)
+            };
+            if reopen {
+                break Ok(());
+            }
+            if speed != sent_speed {
+                link.write_all(&[CMD_SPEED, speed as u8])?;
+                sent_speed = speed;
+            }
+            if clear {
+                link.write_all(&[CMD_CLEAR_BUFFER])?;
+            }
+
+            // Feed text while the keyer's buffer has room
+            if !xoff {
+                let chunk: Vec<u8> = {
+                    let mut shared = self.shared.lock().unwrap();
+                    let count = shared.outgoing.len().min(8);
+                    shared.outgoing.drain(..count).collect()
+                };
+                if !chunk.is_empty() {
+                    link.write_all(&chunk)?;
+                    self.shared.lock().unwrap().busy = true;
+                }
+            }
+
+            let count = match link.read(&mut buf) {
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+                Err(e) => break Err(e),
+            };
+            for &byte in &buf[..count] {
+                match classify(byte) {
+                    WinkeyerByte::Status(flags) => {
+                        xoff = flags & STATUS_XOFF != 0;
+                        self.shared.lock().unwrap().busy = flags & (STATUS_BUSY | STATUS_BREAKIN) != 0;
+                    }
+                    WinkeyerByte::SpeedPot(_) => {}
+                    WinkeyerByte::Echo(ch) => handler(ch),
+                }
+            }
+        };
+
+        // Give the keyer back to its own paddles
+        let _ = link.write_all(&[CMD_ADMIN, ADMIN_HOST_CLOSE]);
+        result
+    }
+}
+
+// Fake WinKeyer for testing without the device. The host talks to it through one
+// pseudo-terminal; characters typed into a second one are treated as sent on its
+// paddle and reported with paddle echo.
+#[cfg(unix)]
+pub struct FakeWinkeyer {
+    pub host_path: String,
+    pub paddle_path: String,
+    stop: Arc<std::sync::atomic::AtomicBool>,
+    #[cfg_attr(not(test), allow(dead_code))]  // Inspected by the tests
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[cfg(unix)]
+impl FakeWinkeyer {
+    pub fn start() -> io::Result<FakeWinkeyer> {
+        use serialport::SerialPort;
+
+        let (mut host_master, host_slave) = serialport::TTYPort::pair()?;
+        let (mut paddle_master, paddle_slave) = serialport::TTYPort::pair()?;
+        host_master.set_timeout(Duration::from_millis(5))?;
+        paddle_master.set_timeout(Duration::from_millis(5))?;
+
+        let host_path = host_slave.name().unwrap_or_default();
+        let paddle_path = paddle_slave.name().unwrap_or_default();
+        println!("Fake WinKeyer on {}, type paddle text into {}", host_path, paddle_path);
+
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let state = Arc::new(Mutex::new(FakeState::default()));
+        let fake_stop = Arc::clone(&stop);
+        let fake_state = Arc::clone(&state);
+        thread::spawn(move || {
+            // Dropping the slaves would close the terminals for the other side
+            let _slaves = (host_slave, paddle_slave);
+            while !fake_stop.load(std::sync::atomic::Ordering::Relaxed) {
+                if fake_state.lock().unwrap().step(&mut host_master, &mut paddle_master).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(FakeWinkeyer { host_path, paddle_path, stop, state })
+    }
+
+    // Speed last set by the host
+    #[cfg(test)]
+    fn speed_wpm(&self) -> u32 {
+        self.state.lock().unwrap().speed_wpm
+    }
+
+    // Text in the buffer, not keyed yet
+    #[cfg(test)]
+    fn buffered(&self) -> Vec<u8> {
+        self.state.lock().unwrap().buffer.iter().copied().collect()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for FakeWinkeyer {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(unix)]
+#[derive(Default)]
+struct FakeState {
+    pending: Vec<u8>,        // Command bytes not complete yet
+    open: bool,
+    mode: u8,
+    speed_wpm: u32,
+    buffer: VecDeque<u8>,    // Text waiting to be "keyed"
+    keying_until: Option<Instant>,
+    last_status: Option<u8>,
+}
+
+#[cfg(unix)]
+impl FakeState {
+    fn step(&mut self, host: &mut serialport::TTYPort, paddle: &mut serialport::TTYPort) -> io::Result<()> {
+        let mut buf = [0u8; 64];
+        let count = match host.read(&mut buf) {
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e),
+        };
+        for &byte in &buf[..count] {
+            self.pending.push(byte);
+            if let Some(args) = command_args(&self.pending) {
+                if self.pending.len() > args {
+                    let command = std::mem::take(&mut self.pending);
+                    self.command(&command, host)?;
+                }
+            }
+        }
+
+        // Pretend to key the buffered text at the set speed
+        let now = Instant::now();
+        if !matches!(self.keying_until, Some(until) if now < until) {
+            self.keying_until = self.buffer.pop_front().map(|ch| {
+                // Average character: about 10 dits including the letter space
+                let dit_ms = 1200 / self.speed_wpm.max(5) as u64;
+                let units = if ch == b' ' { 4 } else { 10 };
+                now + Duration::from_millis(dit_ms * units)
+            });
+        }
+
+        // Characters typed into the paddle terminal count as sent on the paddle
+        let count = match paddle.read(&mut buf) {
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) => return Err(e),
+        };
+        if self.open && self.mode & MODE_PADDLE_ECHO != 0 {
+            let echo: Vec<u8> = buf[..count].iter()
+                .map(|b| if *b == b'\n' { b' ' } else { b.to_ascii_uppercase() })
+                .filter(|b| (b' '..=b'Z').contains(b))
+                .collect();
+            host.write_all(&echo)?;
+        }
+
+        if self.open {
+            let mut flags = 0;
+            if self.keying_until.is_some() {
+                flags |= STATUS_BUSY;
+            }
+            if self.buffer.len() > 85 {
+                flags |= STATUS_XOFF;
+            }
+            if self.last_status != Some(flags) {
+                host.write_all(&[status_byte(flags)])?;
+                self.last_status = Some(flags);
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: &[u8], host: &mut serialport::TTYPort) -> io::Result<()> {
+        match command {
+            [CMD_ADMIN, ADMIN_HOST_OPEN] => {
+                self.open = true;
+                self.last_status = None;
+                host.write_all(&[EMULATED_VERSION])?;
+            }
+            [CMD_ADMIN, ADMIN_HOST_CLOSE] => self.open = false,
+            [CMD_ADMIN, ADMIN_ECHO_TEST, byte] => host.write_all(&[*byte])?,
+            [CMD_SPEED, wpm] => self.speed_wpm = *wpm as u32,
+            [CMD_MODE, mode] => self.mode = *mode,
+            [CMD_CLEAR_BUFFER] => {
+                self.buffer.clear();
+                self.keying_until = None;
+            }
+            [CMD_MERGE, a, b] => self.buffer.extend([*a, *b]),
+            [text] if *text >= 0x20 => self.buffer.push_back(*text),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_lengths() {
+        assert_eq!(command_args(&[CMD_ADMIN]), None);
+        assert_eq!(command_args(&[CMD_ADMIN, ADMIN_HOST_OPEN]), Some(1));
+        assert_eq!(command_args(&[CMD_ADMIN, ADMIN_ECHO_TEST]), Some(2));
+        assert_eq!(command_args(&[CMD_SPEED]), Some(1));
+        assert_eq!(command_args(&[CMD_MODE]), Some(1));
+        assert_eq!(command_args(&[CMD_MERGE]), Some(2));
+        assert_eq!(command_args(&[CMD_CLEAR_BUFFER]), Some(0));
+        assert_eq!(command_args(&[CMD_REQUEST_STATUS]), Some(0));
+        assert_eq!(command_args(&[0x0F]), Some(15));
+        assert_eq!(command_args(&[0x16]), None);
+        assert_eq!(command_args(&[0x16, 0x03]), Some(2));
+        assert_eq!(command_args(b"A"), Some(0));
+    }
+
+    #[test]
+    fn text_encoding() {
+        assert_eq!(encode_text("cq de dl1abc"), b"CQ DE DL1ABC");
+        // Two-letter prosigns are merged, anything else in brackets is sent as it is
+        assert_eq!(encode_text("73 <sk>"), [b'7', b'3', b' ', CMD_MERGE, b'S', b'K']);
+        assert_eq!(encode_text("<AR><bt>"), [CMD_MERGE, b'A', b'R', CMD_MERGE, b'B', b'T']);
+        assert_eq!(encode_text("<ABC>"), b"<ABC>");
+        assert_eq!(encode_text("a#b%c*d;e\u{e4}"), b"ABCDE");
+    }
+
+    // Answers host open with a version byte and records everything the host writes
+    struct RecordingLink {
+        version: Option<u8>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for RecordingLink {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.version.take() {
+                Some(version) => {
+                    buf[0] = version;
+                    Ok(1)
+                }
+                None => Err(io::Error::new(io::ErrorKind::TimedOut, "nothing to read")),
+            }
+        }
+    }
+
+    impl Write for RecordingLink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn session_turns_on_paddle_echo_only() {
+        let host = WinkeyerHost {
+            shared: Arc::new(Mutex::new(Shared {
+                choice: WinkeyerChoice::Off,
+                reopen: true,  // Return right after the setup
+                status: WinkeyerStatus::Off,
+                busy: false,
+                speed_wpm: 0,
+                outgoing: VecDeque::new(),
+                clear: false,
+            })),
+        };
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let link = RecordingLink { version: Some(EMULATED_VERSION), written: Arc::clone(&written) };
+        let handler: EchoHandler = Arc::new(|_| {});
+        host.session(Box::new(link), "test", &handler).unwrap();
+
+        // Host open, mode register with only bit 6 (paddle echo) set, host close
+        assert_eq!(*written.lock().unwrap(), [0x00, 0x02, 0x0E, 0x40, 0x00, 0x03]);
+    }
+
+    // Poll until the condition holds, for up to three seconds
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        condition()
+    }
+
+    // A host connected to a fake WinKeyer, with the characters echoed from its paddle
+    fn connect(speed_wpm: u32) -> (FakeWinkeyer, WinkeyerHost, Arc<Mutex<String>>) {
+        let fake = FakeWinkeyer::start().unwrap();
+        let echoed = Arc::new(Mutex::new(String::new()));
+        let handler_echoed = Arc::clone(&echoed);
+        let host = WinkeyerHost::start(WinkeyerChoice::Port(fake.host_path.clone()), speed_wpm,
+                                       Arc::new(move |ch| handler_echoed.lock().unwrap().push(ch)));
+        assert!(wait_for(|| host.is_connected()), "{:?}", host.status());
+        match host.status() {
+            WinkeyerStatus::Connected { version, .. } => assert_eq!(version, EMULATED_VERSION),
+            status => panic!("{:?}", status),
+        }
+        (fake, host, echoed)
+    }
+
+    #[test]
+    fn host_sets_speed() {
+        let (fake, host, _) = connect(25);
+        assert!(wait_for(|| fake.speed_wpm() == 25));
+        host.set_speed(32);
+        assert!(wait_for(|| fake.speed_wpm() == 32));
+        host.set_choice(WinkeyerChoice::Off);
+    }
+
+    #[test]
+    fn host_sends_merged_prosigns() {
+        let (fake, host, _) = connect(5);
+        host.send_text("K <ar>");
+        // The first character is taken from the buffer to be keyed right away
+        assert!(wait_for(|| fake.buffered() == b" AR"));
+        assert!(host.is_busy());
+        host.set_choice(WinkeyerChoice::Off);
+    }
+
+    #[test]
+    fn paddle_echo_becomes_text() {
+        let (fake, host, echoed) = connect(20);
+        let mut paddle = std::fs::OpenOptions::new().write(true).open(&fake.paddle_path).unwrap();
+        paddle.write_all(b"cq test\n").unwrap();
+        assert!(wait_for(|| echoed.lock().unwrap().as_str() == "CQ TEST "));
+        host.set_choice(WinkeyerChoice::Off);
+    }
+
+    #[test]
+    fn xoff_holds_the_send_queue() {
+        let (fake, host, _) = connect(5);
+        let text = "PARIS ".repeat(30);
+        host.send_text(&text);
+
+        // The fake raises XOFF past 85 buffered bytes, and the host stops feeding it
+        assert!(wait_for(|| fake.buffered().len() > 85));
+        thread::sleep(Duration::from_millis(300));
+        let buffered = fake.buffered().len();
+        assert!(buffered < 100, "{} bytes buffered", buffered);
+        assert!(!host.shared.lock().unwrap().outgoing.is_empty());
+
+        host.clear();
+        assert!(wait_for(|| fake.buffered().is_empty()));
+        assert!(host.shared.lock().unwrap().outgoing.is_empty());
+        host.set_choice(WinkeyerChoice::Off);
+    }
+}
@@ -0,0 +1,576 @@
+// WinKeyer emulation
+// Contest loggers (N1MM, fldigi, ...) that can drive a WinKeyer can key through the app:
+// the emulator answers the WinKeyer command set on a virtual serial device and keys the
+// text on the sidetone, with the same element timing as the training playback. The
+// main window WPM slider plays the part of the speed pot.
+//
+// On Linux and macOS the device is a pseudo-terminal, also linked as
+// ~/.config/paddle_decoder/winkeyer. On Windows choose one end of a virtual null-modem
+// pair (e.g. com0com) and point the logger at the other end.
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::morse_player::MorsePlayer;
+use crate::morse_table::Alphabet;
+use crate::tone_queue::ToneQueue;
+use crate::winkeyer::{
+    command_args, status_byte, WinkeyerLink, ADMIN_ECHO_TEST,
+    ADMIN_HOST_CLOSE, ADMIN_HOST_OPEN, BAUD_RATE, CMD_ADMIN, CMD_CLEAR_BUFFER, CMD_MERGE,
+    CMD_MODE, CMD_REQUEST_STATUS, CMD_SPEED, EMULATED_VERSION, MODE_SERIAL_ECHO,
+    STATUS_BREAKIN, STATUS_BUSY, STATUS_XOFF,
+};
+
+// The next character is queued this close to the end of the current one, like the keyer
+const LOOKAHEAD_MS: f64 = 3.0;
+
+// WinKeyer input buffer; XOFF is reported above 2/3 full
+const BUFFER_SIZE: usize = 128;
+const XOFF_LEVEL: usize = 85;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BufferItem {
+    Text(u8),
+    Merge(u8, u8),     // Two letters sent as one character (prosign)
+    Speed(u32),        // Buffered speed change
+    CancelSpeed,
+    KeyDown(u8),       // Key down for n seconds
+    Wait(u8),          // Pause for n seconds
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmulatorChoice {
+    Off,
+    Port(String),
+    PseudoTerminal,  // Linux and macOS
+}
+
+impl EmulatorChoice {
+    pub fn as_str(&self) -> &str {
+        match self {
+            EmulatorChoice::Off => "Off",
+            EmulatorChoice::Port(name) => name,
+            EmulatorChoice::PseudoTerminal => "Virtual port (pty)",
+        }
+    }
+
+    pub fn to_setting(&self) -> String {
+        match self {
+            EmulatorChoice::Off => "off".to_string(),
+            EmulatorChoice::Port(name) => name.clone(),
+            EmulatorChoice::PseudoTerminal => "pty".to_string(),
+        }
+    }
+
+    pub fn from_setting(text: &str) -> EmulatorChoice {
+        match text {
+            "" | "off" => EmulatorChoice::Off,
+            "pty" => EmulatorChoice::PseudoTerminal,
+            name => EmulatorChoice::Port(name.to_string()),
+        }
+    }
+}
+
+pub fn get_emulator_choices(ports: &[String]) -> Vec<EmulatorChoice> {
+    let mut choices = vec![EmulatorChoice::Off];
+    if cfg!(unix) {
+        choices.push(EmulatorChoice::PseudoTerminal);
+    }
+    choices.extend(ports.iter().cloned().map(EmulatorChoice::Port));
+    choices
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmulatorStatus {
+    Off,
+    Waiting(String),    // Device open, no host open command yet
+    HostOpen(String),
+    Error(String),
+}
+
+impl EmulatorStatus {
+    pub fn describe(&self) -> String {
+        match self {
+            EmulatorStatus::Off => "Off".to_string(),
+            EmulatorStatus::Waiting(device) => format!("Waiting for logger on {}", device),
+            EmulatorStatus::HostOpen(device) => format!("Logger connected on {}", device),
+            EmulatorStatus::Error(e) => format!("Error: {}", e),
+        }
+    }
+
+    pub fn is_host_open(&self) -> bool {
+        matches!(self, EmulatorStatus::HostOpen(_))
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, EmulatorStatus::Error(_))
+    }
+}
+
+// Whether a paddle is held down - paddle input breaks in on the logger's text
+pub type PaddleCheck = Arc<dyn Fn() -> bool + Send + Sync>;
+
+struct Shared {
+    choice: EmulatorChoice,
+    reopen: bool,
+    status: EmulatorStatus,
+    pot_wpm: u32,
+}
+
+#[derive(Clone)]
+pub struct WinkeyerEmulator {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl WinkeyerEmulator {
+    pub fn start(choice: EmulatorChoice, pot_wpm: u32, sidetone: ToneQueue, paddles: PaddleCheck) -> Self {
+        let emulator = WinkeyerEmulator {
+            shared: Arc::new(Mutex::new(Shared {
+                choice,
+                reopen: false,
+                status: EmulatorStatus::Off,
+                pot_wpm,
+            })),
+        };
+
+        let runner = emulator.clone();
+        thread::spawn(move || runner.run(sidetone, paddles));
+        emulator
+    }
+
+    pub fn choice(&self) -> EmulatorChoice {
+        self.shared.lock().unwrap().choice.clone()
+    }
+
+    pub fn set_choice(&self, choice: EmulatorChoice) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.choice != choice {
+            shared.choice = choice;
+            shared.reopen = true;
+        }
+    }
+
+    pub fn status(&self) -> EmulatorStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    // Speed pot position in WPM; used while the logger leaves the speed to the pot
+    pub fn set_speed_pot(&self, wpm: u32) {
+        self.shared.lock().unwrap().pot_wpm = wpm;
+    }
+
+    fn set_status(&self, status: EmulatorStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn run(&self, sidetone: ToneQueue, paddles: PaddleCheck) {
+        loop {
+            let choice = {
+                let mut shared = self.shared.lock().unwrap();
+                shared.reopen = false;
+                shared.choice.clone()
+            };
+
+            match open_device(&choice) {
+                None => self.set_status(EmulatorStatus::Off),
+                Some(Ok(device)) => {
+                    self.set_status(EmulatorStatus::Waiting(device.name.clone()));
+                    if let Err(e) = self.serve(device, &sidetone, &paddles) {
+                        eprintln!("WinKeyer emulator error: {}", e);
+                        self.set_status(EmulatorStatus::Error(e.to_string()));
+                    }
+                }
+                Some(Err(e)) => self.set_status(EmulatorStatus::Error(format!("{}: {}", choice.as_str(), e))),
+            }
+
+            // Retry after an error or wait for another choice
+            for _ in 0..20 {
+                if self.shared.lock().unwrap().reopen {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    // Answer the logger until another device is chosen or the device fails
+    fn serve(&self, mut device: Device, sidetone: &ToneQueue, paddles: &PaddleCheck) -> io::Result<()> {
+        println!("WinKeyer emulator listening on {}", device.name);
+        let mut keyer = Emulation::default();
+        let mut buf = [0u8; 64];
+
+        loop {
+            let (pot_wpm, reopen) = {
+                let shared = self.shared.lock().unwrap();
+                (shared.pot_wpm, shared.reopen)
+            };
+            if reopen {
+                keyer.clear(sidetone);
+                return Ok(());
+            }
+
+            let count = match device.link.read(&mut buf) {
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+                Err(e) => return Err(e),
+            };
+
+            let was_open = keyer.open;
+            for &byte in &buf[..count] {
+                keyer.receive(byte, device.link.as_mut(), sidetone, pot_wpm)?;
+            }
+            if keyer.open != was_open {
+                self.set_status(if keyer.open {
+                    println!("WinKeyer emulator: logger connected");
+                    EmulatorStatus::HostOpen(device.name.clone())
+                } else {
+                    EmulatorStatus::Waiting(device.name.clone())
+                });
+            }
+
+            keyer.update(device.link.as_mut(), sidetone, pot_wpm, paddles())?;
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+struct Device {
+    link: Box<dyn WinkeyerLink>,
+    name: String,
+    #[cfg(unix)]
+    _slave: Option<serialport::TTYPort>,  // Keeps the pty alive between logger sessions
+}
+
+fn open_device(choice: &EmulatorChoice) -> Option<io::Result<Device>> {
+    match choice {
+        EmulatorChoice::Off => None,
+        EmulatorChoice::Port(name) => Some(serialport::new(name, BAUD_RATE)
+            .stop_bits(serialport::StopBits::Two)
+            .timeout(Duration::from_millis(1))
+            .open()
+            .map(|port| Device {
+                link: Box::new(port),
+                name: name.clone(),
+                #[cfg(unix)]
+                _slave: None,
+            })
+            .map_err(io::Error::from)),
+        #[cfg(unix)]
+        EmulatorChoice::PseudoTerminal => Some(open_pty()),
+        #[cfg(not(unix))]
+        EmulatorChoice::PseudoTerminal => Some(Err(io::Error::new(
+            io::ErrorKind::Unsupported, "pseudo-terminals need Linux or macOS"))),
+    }
+}
+
+// Pseudo-terminal for the logger, with a link at a path that stays the same
+#[cfg(unix)]
+fn open_pty() -> io::Result<Device> {
+    use serialport::SerialPort;
+
+    let (mut master, slave) = serialport::TTYPort::pair()?;
+    master.set_timeout(Duration::from_millis(1))?;
+    let mut name = slave.name().unwrap_or_default();
+
+    if let Some(dir) = crate::settings::config_dir() {
+        let link = dir.join("winkeyer");
+        let _ = std::fs::create_dir_all(&dir);
+        let _ = std::fs::remove_file(&link);
+        match std::os::unix::fs::symlink(&name, &link) {
+            Ok(()) => name = format!("{} ({})", link.display(), name),
+            Err(e) => eprintln!("Failed to link {}: {}", link.display(), e),
+        }
+    }
+    Ok(Device { link: Box::new(master), name, _slave: Some(slave) })
+}
+
+// WinKeyer state as seen by the logger
+#[derive(Default)]
+struct Emulation {
+    pending: Vec<u8>,           // Command bytes not complete yet
+    open: bool,
+    mode: u8,
+    speed_wpm: u32,             // 0 = use the speed pot
+    buffered_wpm: Option<u32>,  // Speed change from the buffer
+    farnsworth_wpm: u32,        // 0 = off
+    pot_min_wpm: u32,
+    buffer: VecDeque<BufferItem>,
+    after_letter: bool,         // The sidetone queue ends in a letter space
+    sending: bool,              // Our text is on the sidetone
+    tuning: bool,               // Key down from the key immediate command
+    last_status: Option<u8>,
+    last_pot: Option<u8>,
+}
+
+impl Emulation {
+    fn receive(&mut self, byte: u8, link: &mut dyn WinkeyerLink, sidetone: &ToneQueue, pot_wpm: u32) -> io::Result<()> {
+        self.pending.push(byte);
+        match command_args(&self.pending) {
+            Some(args) if self.pending.len() > args => {
+                let command = std::mem::take(&mut self.pending);
+                self.command(&command, link, sidetone, pot_wpm)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn command(&mut self, command: &[u8], link: &mut dyn WinkeyerLink, sidetone: &ToneQueue, pot_wpm: u32) -> io::Result<()> {
+        match *command {
+            [CMD_ADMIN, 0x01] => {
+                self.clear(sidetone);
+                *self = Emulation { open: self.open, ..Emulation::default() };
+            }
+            [CMD_ADMIN, ADMIN_HOST_OPEN] => {
+                self.open = true;
+                self.last_status = None;
+                self.last_pot = None;
+                link.write_all(&[EMULATED_VERSION])?;
+            }
+            [CMD_ADMIN, ADMIN_HOST_CLOSE] => {
+                self.clear(sidetone);
+                self.open = false;
+            }
+            [CMD_ADMIN, ADMIN_ECHO_TEST, byte] => link.write_all(&[byte])?,
+            [CMD_SPEED, wpm] => self.speed_wpm = wpm as u32,
+            [0x05, min, ..] => self.pot_min_wpm = min as u32,
+            [0x07] => link.write_all(&[self.pot_byte(pot_wpm)])?,
+            [0x08] => {
+                self.buffer.pop_back();
+            }
+            [CMD_CLEAR_BUFFER] => self.clear(sidetone),
+            // Key immediate: tune
+            [0x0B, down] => {
+                self.tuning = down != 0;
+                sidetone.key(self.tuning);
+            }
+            [0x0D, wpm] => self.farnsworth_wpm = wpm as u32,
+            [CMD_MODE, mode] => self.mode = mode,
+            // Load defaults: mode register and speed come first
+            [0x0F, mode, wpm, ..] => {
+                self.mode = mode;
+                self.speed_wpm = wpm as u32;
+            }
+            [CMD_REQUEST_STATUS] => link.write_all(&[status_byte(self.flags(false))])?,
+            [0x19, seconds] => self.push(BufferItem::KeyDown(seconds)),
+            [0x1A, seconds] => self.push(BufferItem::Wait(seconds)),
+            [CMD_MERGE, a, b] => self.push(BufferItem::Merge(a, b)),
+            [0x1C, wpm] => self.push(BufferItem::Speed(wpm as u32)),
+            [0x1E] => self.push(BufferItem::CancelSpeed),
+            [text] if text >= 0x20 => self.push(BufferItem::Text(text.to_ascii_uppercase())),
+            // Sidetone, weight, PTT and the other settings do not change what is heard
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, item: BufferItem) {
+        if self.buffer.len() < BUFFER_SIZE {
+            self.buffer.push_back(item);
+        }
+    }
+
+    fn clear(&mut self, sidetone: &ToneQueue) {
+        self.buffer.clear();
+        self.buffered_wpm = None;
+        if self.tuning {
+            sidetone.key(false);
+            self.tuning = false;
+        }
+    }
+
+    fn flags(&self, paddles_down: bool) -> u8 {
+        let mut flags = 0;
+        if self.sending || self.tuning || !self.buffer.is_empty() {
+            flags |= STATUS_BUSY;
+        }
+        if paddles_down {
+            flags |= STATUS_BREAKIN;
+        }
+        if self.buffer.len() > XOFF_LEVEL {
+            flags |= STATUS_XOFF;
+        }
+        flags
+    }
+
+    fn pot_byte(&self, pot_wpm: u32) -> u8 {
+        0x80 | pot_wpm.saturating_sub(self.pot_min_wpm).min(63) as u8
+    }
+
+    // Key the buffer on the sidetone and report status and speed pot changes
+    fn update(&mut self, link: &mut dyn WinkeyerLink, sidetone: &ToneQueue, pot_wpm: u32, paddles_down: bool) -> io::Result<()> {
+        // Touching the paddle aborts the logger's text, as on a real WinKeyer
+        if paddles_down && !self.buffer.is_empty() {
+            self.clear(sidetone);
+        }
+
+        if sidetone.is_idle() {
+            self.sending = false;
+            self.after_letter = false;
+        }
+        while !self.tuning && sidetone.queued_ms() <= LOOKAHEAD_MS {
+            let item = match self.buffer.pop_front() {
+                Some(item) => item,
+                None => break,
+            };
+            self.key(item, link, sidetone, pot_wpm)?;
+        }
+
+        if self.open {
+            let flags = self.flags(paddles_down);
+            if self.last_status != Some(flags) {
+                link.write_all(&[status_byte(flags)])?;
+                self.last_status = Some(flags);
+            }
+            let pot = self.pot_byte(pot_wpm);
+            if self.last_pot != Some(pot) {
+                link.write_all(&[pot])?;
+                self.last_pot = Some(pot);
+            }
+        }
+        Ok(())
+    }
+
+    fn key(&mut self, item: BufferItem, link: &mut dyn WinkeyerLink, sidetone: &ToneQueue, pot_wpm: u32) -> io::Result<()> {
+        let text = match item {
+            BufferItem::Text(ch) => {
+                if self.mode & MODE_SERIAL_ECHO != 0 {
+                    link.write_all(&[ch])?;
+                }
+                (ch as char).to_string()
+            }
+            BufferItem::Merge(a, b) => format!("<{}{}>", a as char, b as char),
+            BufferItem::Speed(wpm) => {
+                self.buffered_wpm = Some(wpm);
+                return Ok(());
+            }
+            BufferItem::CancelSpeed => {
+                self.buffered_wpm = None;
+                return Ok(());
+            }
+            BufferItem::KeyDown(seconds) | BufferItem::Wait(seconds) => {
+                sidetone.push(matches!(item, BufferItem::KeyDown(_)), seconds as f64 * 1000.0);
+                self.sending = true;
+                self.after_letter = false;
+                return Ok(());
+            }
+        };
+
+        // Farnsworth sends the characters faster than the set speed
+        let wpm = match self.buffered_wpm.unwrap_or(self.speed_wpm) {
+            0 => pot_wpm,
+            wpm => wpm,
+        }.clamp(5, 99);
+        let char_wpm = self.farnsworth_wpm.max(wpm);
+        let player = MorsePlayer::new_with_alphabet(0.0, char_wpm, wpm, Alphabet::Latin);
+        self.after_letter = player.queue_morse_after(sidetone, &text, self.after_letter);
+        self.sending = true;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Feed command bytes from the logger; returns what the emulator wrote back
+    fn feed(keyer: &mut Emulation, bytes: &[u8], sidetone: &ToneQueue, pot_wpm: u32) -> Vec<u8> {
+        let mut link = Cursor::new(Vec::new());
+        for &byte in bytes {
+            keyer.receive(byte, &mut link, sidetone, pot_wpm).unwrap();
+        }
+        link.into_inner()
+    }
+
+    fn update(keyer: &mut Emulation, sidetone: &ToneQueue, pot_wpm: u32, paddles_down: bool) -> Vec<u8> {
+        let mut link = Cursor::new(Vec::new());
+        keyer.update(&mut link, sidetone, pot_wpm, paddles_down).unwrap();
+        link.into_inner()
+    }
+
+    #[test]
+    fn host_open_and_echo_test() {
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        assert_eq!(feed(&mut keyer, &[0x00, 0x02], &sidetone, 20), [EMULATED_VERSION]);
+        assert!(keyer.open);
+        assert_eq!(feed(&mut keyer, &[0x00, 0x04, 0x55], &sidetone, 20), [0x55]);
+        assert!(feed(&mut keyer, &[0x00, 0x03], &sidetone, 20).is_empty());
+        assert!(!keyer.open);
+    }
+
+    #[test]
+    fn speed_pot_reply() {
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        // Pot setup: minimum 10 WPM, range 25, unused byte
+        feed(&mut keyer, &[0x05, 10, 25, 0], &sidetone, 20);
+        assert_eq!(feed(&mut keyer, &[0x07], &sidetone, 22), [0x80 | 12]);
+        // Below the minimum reads as 0, the pot byte holds 6 bits
+        assert_eq!(feed(&mut keyer, &[0x07], &sidetone, 5), [0x80]);
+        assert_eq!(feed(&mut keyer, &[0x07], &sidetone, 99), [0x80 | 63]);
+    }
+
+    #[test]
+    fn status_bytes() {
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        assert_eq!(feed(&mut keyer, &[0x15], &sidetone, 20), [0xC0]);
+
+        // Text in the buffer is busy, more than 85 bytes raise XOFF too
+        feed(&mut keyer, b"PARIS", &sidetone, 20);
+        assert_eq!(feed(&mut keyer, &[0x15], &sidetone, 20), [0xC4]);
+        feed(&mut keyer, &[b'E'; 90], &sidetone, 20);
+        assert_eq!(feed(&mut keyer, &[0x15], &sidetone, 20), [0xC5]);
+
+        feed(&mut keyer, &[0x0A], &sidetone, 20);
+        assert_eq!(feed(&mut keyer, &[0x15], &sidetone, 20), [0xC0]);
+    }
+
+    #[test]
+    fn unsolicited_status_and_pot_after_host_open() {
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        assert!(update(&mut keyer, &sidetone, 20, false).is_empty());
+
+        feed(&mut keyer, &[0x00, 0x02], &sidetone, 20);
+        assert_eq!(update(&mut keyer, &sidetone, 20, false), [0xC0, 0x80 | 20]);
+        // Only changes are reported
+        assert!(update(&mut keyer, &sidetone, 20, false).is_empty());
+        assert_eq!(update(&mut keyer, &sidetone, 21, false), [0x80 | 21]);
+    }
+
+    #[test]
+    fn serial_echo_follows_mode_bit_2() {
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        feed(&mut keyer, &[0x0E, 0x04, b'k'], &sidetone, 20);
+        assert_eq!(update(&mut keyer, &sidetone, 20, false), b"K");
+        assert!(!sidetone.is_idle());
+
+        // Paddle echo (bit 6) alone does not echo the logger's text
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        feed(&mut keyer, &[0x0E, 0x40, b'K'], &sidetone, 20);
+        assert!(update(&mut keyer, &sidetone, 20, false).is_empty());
+        assert!(!sidetone.is_idle());
+    }
+
+    #[test]
+    fn paddle_breaks_in_and_clears_the_buffer() {
+        let sidetone = ToneQueue::new(600.0, 5);
+        let mut keyer = Emulation::default();
+        feed(&mut keyer, &[0x00, 0x02], &sidetone, 20);
+        feed(&mut keyer, b"CQ CQ", &sidetone, 20);
+        update(&mut keyer, &sidetone, 20, false);
+        assert!(!keyer.buffer.is_empty());
+
+        let reply = update(&mut keyer, &sidetone, 20, true);
+        assert!(keyer.buffer.is_empty());
+        // Still busy with the character already on the sidetone, and in break-in
+        assert_eq!(reply, [status_byte(STATUS_BUSY | STATUS_BREAKIN)]);
+    }
+}